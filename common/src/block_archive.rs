@@ -0,0 +1,70 @@
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use solana_transaction_status::EncodedConfirmedBlock;
+
+pub const ARCHIVE_EXTENSION: &str = "json.zst";
+// Blocks without a block time can't be dated; they are rare enough to share a folder.
+const UNDATED: &str = "undated";
+const COMPRESSION_LEVEL: i32 = 3;
+
+/// Keeps raw `getBlock` responses as zstd-compressed JSON under
+/// `<dir>/<YYYY-MM-DD>/<slot>.json.zst`, so blocks can be decoded again without RPC.
+#[derive(Clone, Debug)]
+pub struct BlockArchive {
+    dir: PathBuf,
+}
+
+impl BlockArchive {
+    pub fn new(dir: &str) -> Self {
+        BlockArchive {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    pub fn path(&self, slot: u64, block: &EncodedConfirmedBlock) -> PathBuf {
+        let date = block
+            .block_time
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|datetime| datetime.format("%Y-%m-%d").to_string())
+            .unwrap_or_else(|| UNDATED.to_string());
+        self.dir
+            .join(date)
+            .join(format!("{}.{}", slot, ARCHIVE_EXTENSION))
+    }
+
+    /// Writes through a temporary file, so an interrupted run never leaves a truncated
+    /// block behind.
+    pub fn write(&self, slot: u64, block: &EncodedConfirmedBlock) -> Result<()> {
+        let path = self.path(slot, block);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp_path = path.with_extension("zst.tmp");
+
+        let file = File::create(&tmp_path)?;
+        let mut encoder = zstd::Encoder::new(BufWriter::new(file), COMPRESSION_LEVEL)?;
+        serde_json::to_writer(&mut encoder, block)?;
+        encoder.finish()?.flush()?;
+        fs::rename(&tmp_path, &path)
+            .with_context(|| format!("Failed to move block into {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Reads a block saved either as plain `getBlock` JSON or by `BlockArchive`.
+pub fn read_block(path: &Path) -> Result<EncodedConfirmedBlock> {
+    let file =
+        File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let block = if path.to_string_lossy().ends_with(".zst") {
+        serde_json::from_reader(BufReader::new(zstd::Decoder::new(file)?))
+    } else {
+        serde_json::from_reader(BufReader::new(file))
+    };
+    block.with_context(|| format!("Failed to parse {}", path.display()))
+}
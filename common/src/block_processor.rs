@@ -1,76 +1,321 @@
 use anyhow::Result;
 use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::{self, OpenOptions},
+    io::Write,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    thread::current,
-    time::{SystemTime, UNIX_EPOCH},
 };
 
 use crate::{
-    global::OUTPUT_PATH,
-    models::{TokenBalance, TradeData, UiTokenAmount, ZmqData},
-    tx_processor::process_tx,
-    utils::{
-        convert_to_date, get_amt, get_mint, get_signer_balance_change, save_trades_to_avro,
-        save_trades_to_csv,
+    decode_error::{count_failure, DecodeError},
+    metrics::{record_liquidity, record_pools, record_trades, TRANSACTIONS_DECODED, WRITE_LATENCY},
+    models::{
+        DeadLetter, DecodedEvents, LiquidityEvent, OutputConfig, OutputFormat, PoolCreated,
+        TradeData, ZmqData, LIQUIDITY_STREAM, POOLS_STREAM, TRADES_TOPIC,
     },
+    tx_processor::{process_tx, transaction_signature},
+    utils::{save_liquidity_events_to_avro, save_pools_to_avro, save_to_csv, save_trades_to_avro},
 };
 use chrono::{DateTime, Utc};
-use solana_transaction_status::{EncodedConfirmedBlock, UiInnerInstructions};
-use std::time::Duration;
+use serde::Serialize;
+use solana_transaction_status::{EncodedConfirmedBlock, EncodedTransactionWithStatusMeta};
+use tracing::{debug, instrument, warn};
 
+#[instrument(skip_all, fields(slot))]
 pub async fn process_block(
     slot: u64, // node returns wrong slot
     block: EncodedConfirmedBlock,
+    output: &OutputConfig,
     publisher_clone: Option<Arc<Mutex<zmq::Socket>>>,
-) -> Result<()> {
-    let timestamp = block.block_time.expect("Block time not found");
-    let mut data: Vec<TradeData> = vec![];
+) -> Result<usize> {
+    let timestamp = block.block_time.ok_or(DecodeError::MissingBlockTime)?;
 
     // convert timestamp to human readable timestamp
-    let d = UNIX_EPOCH + Duration::from_secs(timestamp.try_into().unwrap());
-    // Create DateTime from SystemTime
-    let datetime = DateTime::<Utc>::from(d);
+    let datetime = DateTime::<Utc>::from_timestamp(timestamp, 0)
+        .ok_or(DecodeError::InvalidBlockTime(timestamp))?;
     // Formats the combined date and time with the specified format string.
     let timestamp_str = datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string();
 
     let date_str = datetime.format("%Y-%m-%d").to_string();
 
-    for trx in block.transactions {
-        match process_tx(trx, slot, timestamp).await {
-            Some(trades) => {
-                data.extend(trades);
-            }
-            None => {}
+    let (events, dead_letters) = decode_block(slot, timestamp, block.transactions).await;
+    let data: Vec<TradeData> = events
+        .trades
+        .into_iter()
+        .filter(|trade| output.filter.matches(trade))
+        .collect();
+    let liquidity: Vec<LiquidityEvent> = events
+        .liquidity
+        .into_iter()
+        .filter(|event| output.filter.matches_liquidity(event))
+        .collect();
+    let pools: Vec<PoolCreated> = events
+        .pools
+        .into_iter()
+        .filter(|pool| output.filter.matches_pool(pool))
+        .collect();
+    if !dead_letters.is_empty() {
+        record_dead_letters(&dead_letters, output)?;
+    }
+
+    debug!(
+        block_time = %timestamp_str,
+        trades = data.len(),
+        liquidity_events = liquidity.len(),
+        pools = pools.len(),
+        "Decoded block"
+    );
+
+    // Nothing to write; the caller records the slot as empty.
+    let written = data.len() + liquidity.len() + pools.len();
+    if written == 0 {
+        return Ok(0);
+    }
+
+    // New pools go out first, listings are the most time-sensitive stream.
+    if !pools.is_empty() {
+        let file_path = output.slot_file(Some(POOLS_STREAM), &date_str, slot);
+        let file_path = file_path.to_string_lossy();
+        let write_timer = WRITE_LATENCY
+            .with_label_values(&[output.format.extension()])
+            .start_timer();
+        match output.format {
+            OutputFormat::Avro => save_pools_to_avro(&pools, &file_path).await?,
+            OutputFormat::Csv => save_to_csv(&pools, &file_path).await?,
+        }
+        write_timer.observe_duration();
+
+        if let Some(publisher) = &publisher_clone {
+            publish(publisher, POOLS_STREAM, slot, &date_str, pools)?;
         }
     }
 
-    let current_time = SystemTime::now();
-    let current_datetime = DateTime::<Utc>::from(current_time);
-    let current_timestamp_str = current_datetime.format("%Y-%m-%d %H:%M:%S.%f").to_string();
+    if !data.is_empty() {
+        let file_path = output.slot_file(None, &date_str, slot);
+        let file_path = file_path.to_string_lossy();
+        let write_timer = WRITE_LATENCY
+            .with_label_values(&[output.format.extension()])
+            .start_timer();
+        match output.format {
+            OutputFormat::Avro => save_trades_to_avro(&data, &file_path).await?,
+            OutputFormat::Csv => save_to_csv(&data, &file_path).await?,
+        }
+        write_timer.observe_duration();
 
-    println!(
-        "Block time: {:?}, processed at: {:?}",
-        timestamp_str, current_timestamp_str
-    );
+        if let Some(publisher) = &publisher_clone {
+            publish(publisher, TRADES_TOPIC, slot, &date_str, data)?;
+        }
+    }
 
-    let file_path = format!("{}{}/{}.avro", OUTPUT_PATH.as_str(), date_str, slot);
-    // TODO: fix paths, incosistent across modules
+    if !liquidity.is_empty() {
+        let file_path = output.slot_file(Some(LIQUIDITY_STREAM), &date_str, slot);
+        let file_path = file_path.to_string_lossy();
+        let write_timer = WRITE_LATENCY
+            .with_label_values(&[output.format.extension()])
+            .start_timer();
+        match output.format {
+            OutputFormat::Avro => save_liquidity_events_to_avro(&liquidity, &file_path).await?,
+            OutputFormat::Csv => save_to_csv(&liquidity, &file_path).await?,
+        }
+        write_timer.observe_duration();
+
+        if let Some(publisher) = &publisher_clone {
+            publish(publisher, LIQUIDITY_STREAM, slot, &date_str, liquidity)?;
+        }
+    }
 
-    // save_trades_to_csv(&data, file_path.as_str()).await.expect("Failed to save trades to csv");
-    save_trades_to_avro(&data, file_path.as_str())
-        .await?;
+    Ok(written)
+}
 
-    // TODO: ZMQ
-    // let zmq_data: ZmqData = ZmqData {
-    //     slot: slot,
-    //     date: date_str,
-    //     data: data,
-    // };
+// Sends a slot's records of one stream as a two-part message: topic, then JSON.
+fn publish<T: Serialize>(
+    publisher: &Mutex<zmq::Socket>,
+    topic: &str,
+    slot: u64,
+    date: &str,
+    data: Vec<T>,
+) -> Result<()> {
+    let zmq_data = ZmqData {
+        slot,
+        date: date.to_string(),
+        data,
+    };
+    let json_str = serde_json::to_string(&zmq_data)?;
+    let sock = publisher.lock().unwrap();
+    sock.send(topic, zmq::SNDMORE)?;
+    sock.send(&json_str, 0)?;
+    Ok(())
+}
 
-    // let json_str = serde_json::to_string(&zmq_data).unwrap();
-    // let sock = publisher_clone.lock().unwrap();
-    // sock.send("", zmq::SNDMORE).unwrap(); // optional topic
-    // sock.send(&json_str, 0).unwrap();
+// Skipped slots have no block time, hence no date folder, so they are listed in one
+// file at the root of the output directory, one slot per line.
+const SKIPPED_SLOTS_FILE: &str = "skipped_slots";
 
+lazy_static::lazy_static! {
+    // What each output directory's skipped slot and dead letter files already hold,
+    // loaded on first use, so rerunning a range doesn't append the same lines again.
+    static ref RECORDED_SKIPPED: Mutex<HashMap<PathBuf, HashSet<u64>>> = Mutex::new(HashMap::new());
+    static ref RECORDED_DEAD_LETTERS: Mutex<HashMap<PathBuf, HashSet<(u64, String)>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Records that `slot` was skipped by its leader, so later runs don't treat it as a gap.
+/// Slots already listed are not written again.
+pub fn record_skipped_slot(slot: u64, output: &OutputConfig) -> Result<()> {
+    let dir = Path::new(&output.dir);
+    let mut recorded = RECORDED_SKIPPED.lock().unwrap();
+    let slots = match recorded.entry(dir.to_path_buf()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(load_skipped_slots(dir)?),
+    };
+    if slots.contains(&slot) {
+        return Ok(());
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(SKIPPED_SLOTS_FILE))?;
+    writeln!(file, "{}", slot)?;
+    slots.insert(slot);
     Ok(())
 }
+
+pub fn load_skipped_slots(dir: &Path) -> Result<HashSet<u64>> {
+    let path = dir.join(SKIPPED_SLOTS_FILE);
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| line.trim().parse().ok())
+        .collect())
+}
+
+// Transactions that failed to decode, one JSON `DeadLetter` per line.
+const DEAD_LETTERS_FILE: &str = "dead_letters";
+
+/// Appends the dead letters whose slot and signature are not in the file yet.
+pub fn record_dead_letters(dead_letters: &[DeadLetter], output: &OutputConfig) -> Result<()> {
+    let dir = Path::new(&output.dir);
+    let mut recorded = RECORDED_DEAD_LETTERS.lock().unwrap();
+    let known = match recorded.entry(dir.to_path_buf()) {
+        Entry::Occupied(entry) => entry.into_mut(),
+        Entry::Vacant(entry) => entry.insert(load_dead_letter_keys(dir)?),
+    };
+
+    let mut lines = String::new();
+    let mut added = vec![];
+    for dead_letter in dead_letters {
+        let key = (dead_letter.slot, dead_letter.signature.clone());
+        if known.contains(&key) || added.contains(&key) {
+            continue;
+        }
+        lines.push_str(&serde_json::to_string(dead_letter)?);
+        lines.push('\n');
+        added.push(key);
+    }
+    if lines.is_empty() {
+        return Ok(());
+    }
+
+    fs::create_dir_all(dir)?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(DEAD_LETTERS_FILE))?;
+    file.write_all(lines.as_bytes())?;
+    known.extend(added);
+    Ok(())
+}
+
+// Slot and signature of every dead letter already written to `dir`.
+fn load_dead_letter_keys(dir: &Path) -> Result<HashSet<(u64, String)>> {
+    let path = dir.join(DEAD_LETTERS_FILE);
+    if !path.exists() {
+        return Ok(HashSet::new());
+    }
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| serde_json::from_str::<DeadLetter>(line).ok())
+        .map(|dead_letter| (dead_letter.slot, dead_letter.signature))
+        .collect())
+}
+
+/// Decodes every transaction of a block on its own: a transaction that fails is counted
+/// under its `DecodeError` reason and returned as a dead letter instead of its events.
+pub async fn decode_block(
+    slot: u64,
+    timestamp: i64,
+    transactions: Vec<EncodedTransactionWithStatusMeta>,
+) -> (DecodedEvents, Vec<DeadLetter>) {
+    let mut data = DecodedEvents::default();
+    let mut dead_letters = vec![];
+    for trx in transactions {
+        let signature = transaction_signature(&trx.transaction);
+        match process_tx(trx, slot, timestamp).await {
+            Ok(events) => {
+                TRANSACTIONS_DECODED.inc();
+                record_trades(&events.trades);
+                record_liquidity(&events.liquidity);
+                record_pools(&events.pools);
+                data.extend(events);
+            }
+            Err(e) => {
+                count_failure(&e);
+                let signature = signature.unwrap_or_default();
+                warn!(slot, %signature, reason = e.reason(), "Failed to decode transaction: {}", e);
+                dead_letters.push(DeadLetter {
+                    slot,
+                    signature,
+                    reason: e.reason().to_string(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+    (data, dead_letters)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(name: &str) -> OutputConfig {
+        let dir = std::env::temp_dir().join(format!("raydium-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        OutputConfig {
+            dir: dir.to_string_lossy().into_owned(),
+            format: OutputFormat::Csv,
+            filter: Default::default(),
+        }
+    }
+
+    #[test]
+    fn records_each_skipped_slot_and_dead_letter_once() {
+        let output = output("records-once");
+        fs::create_dir_all(&output.dir).unwrap();
+        // A line left by an earlier run is not written again either.
+        fs::write(Path::new(&output.dir).join(SKIPPED_SLOTS_FILE), "7\n").unwrap();
+        for slot in [7, 8, 8] {
+            record_skipped_slot(slot, &output).unwrap();
+        }
+        let skipped = fs::read_to_string(Path::new(&output.dir).join(SKIPPED_SLOTS_FILE)).unwrap();
+        assert_eq!(skipped, "7\n8\n");
+
+        let dead_letter = DeadLetter {
+            slot: 8,
+            signature: "sig".to_string(),
+            reason: "missing_meta".to_string(),
+            error: "Transaction has no meta".to_string(),
+        };
+        record_dead_letters(&[dead_letter.clone(), dead_letter.clone()], &output).unwrap();
+        record_dead_letters(&[dead_letter], &output).unwrap();
+        let dead_letters = fs::read_to_string(Path::new(&output.dir).join(DEAD_LETTERS_FILE)).unwrap();
+        assert_eq!(dead_letters.lines().count(), 1);
+
+        fs::remove_dir_all(&output.dir).unwrap();
+    }
+}
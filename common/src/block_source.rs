@@ -0,0 +1,699 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    ops::Range,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Context, Error, Result};
+use futures::{stream, StreamExt};
+use solana_client::{
+    nonblocking::pubsub_client::PubsubClient,
+    rpc_config::{RpcBlockSubscribeConfig, RpcBlockSubscribeFilter},
+};
+use solana_sdk::{clock::DEFAULT_SLOTS_PER_EPOCH, commitment_config::CommitmentConfig};
+use solana_transaction_status::{EncodedConfirmedBlock, TransactionDetails, UiTransactionEncoding};
+use tokio::time::sleep;
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use tracing::{debug, warn};
+
+use crate::{
+    block_archive::{read_block, ARCHIVE_EXTENSION},
+    config::config,
+    geyser::GeyserClient,
+    metrics::endpoint_label,
+    old_faithful::CarReader,
+    rate_control::RateController,
+    rpc_client::{get_latest_slot, FetchError},
+};
+
+/// Context attached to errors from sources that know which slot failed, so consumers
+/// can `downcast_ref::<FailedSlot>()` to find it.
+#[derive(Debug)]
+pub struct FailedSlot(pub u64);
+
+impl fmt::Display for FailedSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Failed to fetch slot {}", self.0)
+    }
+}
+
+/// Error yielded when a following source falls too far behind the tip and jumps ahead.
+/// The slots in the range were never fetched and should be recorded for a backfill.
+#[derive(Debug)]
+pub struct LeftBehind(pub Range<u64>);
+
+impl fmt::Display for LeftBehind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Slots {}..{} were left behind", self.0.start, self.0.end)
+    }
+}
+
+pub type BlockStream = Pin<Box<dyn Stream<Item = Result<(u64, EncodedConfirmedBlock)>> + Send>>;
+
+/// Anything that can hand `(slot, block)` pairs to `process_block`.
+pub trait BlockSource: Send + Sync {
+    /// Streams the blocks for `slots`, in the order given.
+    fn fetch(&self, slots: Vec<u64>) -> BlockStream;
+
+    /// Streams blocks as the chain advances, starting at `from` where the source
+    /// can replay and at the current tip otherwise.
+    fn follow(&self, from: Option<u64>) -> BlockStream;
+}
+
+/// Command line selection of a block source that can serve slot ranges, shared by the
+/// indexer and the preprocessor. Blocks come from RPC unless a flag names files.
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct SourceArgs {
+    /// Read recorded blocks from this directory instead of RPC: getBlock responses
+    /// (<slot>.json) or a block archive written with --archive-dir
+    #[arg(long, conflicts_with = "car")]
+    pub replay_dir: Option<String>,
+
+    /// Read blocks from Old Faithful epoch archives: a .car file or a directory of them
+    #[arg(long)]
+    pub car: Option<String>,
+}
+
+impl SourceArgs {
+    /// Whether blocks are read from disk, i.e. the run needs no RPC access.
+    pub fn is_offline(&self) -> bool {
+        self.replay_dir.is_some() || self.car.is_some()
+    }
+
+    /// The selected source; `concurrency` and `max_lag` only apply to RPC.
+    pub fn block_source(&self, concurrency: usize, max_lag: u64) -> Box<dyn BlockSource> {
+        if let Some(dir) = &self.replay_dir {
+            Box::new(FileBlockSource::new(dir))
+        } else if let Some(path) = &self.car {
+            Box::new(CarBlockSource::new(path))
+        } else {
+            Box::new(RpcBlockSource::new(concurrency, max_lag))
+        }
+    }
+}
+
+// Yields a single error, for sources that cannot serve a request at all.
+fn unsupported(message: &'static str) -> BlockStream {
+    Box::pin(stream::once(async move { Err(anyhow!(message)) }))
+}
+
+// How many times a range fetch goes back over the slots that failed, and how long it
+// waits before the first of those passes (growing linearly after that).
+const REQUEUE_ROUNDS: usize = 2;
+const REQUEUE_DELAY: Duration = Duration::from_secs(5);
+
+/// Polls `getBlock` over JSON-RPC.
+pub struct RpcBlockSource {
+    concurrency: usize,
+    max_lag: u64,
+    rate: Arc<RateController>,
+}
+
+impl RpcBlockSource {
+    /// Blocks are fetched up to `concurrency` at a time but always yielded in slot
+    /// order. The actual number in flight backs off when the RPC throttles and
+    /// recovers as requests succeed again. When following the tip and falling more
+    /// than `max_lag` slots behind (e.g. after a long RPC stall), the source jumps
+    /// forward and yields a `LeftBehind` error for the range it did not fetch.
+    pub fn new(concurrency: usize, max_lag: u64) -> Self {
+        RpcBlockSource {
+            concurrency,
+            max_lag,
+            rate: RateController::new(1, concurrency),
+        }
+    }
+}
+
+async fn fetch_slot(rate: Arc<RateController>, slot: u64) -> Result<(u64, EncodedConfirmedBlock)> {
+    let block = rate
+        .fetch_block(slot)
+        .await
+        .map_err(Error::new)
+        .context(FailedSlot(slot))?;
+    Ok((slot, block))
+}
+
+async fn latest_slot_or_wait() -> u64 {
+    loop {
+        match get_latest_slot().await {
+            Ok(slot) => return slot,
+            Err(e) => {
+                warn!("Failed to get latest slot: {:#}", e);
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+impl BlockSource for RpcBlockSource {
+    /// Slots that still fail once their retries are used up are put back at the end
+    /// of the queue, up to `REQUEUE_ROUNDS` times, before they are reported.
+    fn fetch(&self, slots: Vec<u64>) -> BlockStream {
+        let concurrency = self.concurrency;
+        let rate = self.rate.clone();
+
+        Box::pin(async_stream::stream! {
+            let mut queue = slots;
+            for round in 0..=REQUEUE_ROUNDS {
+                let mut requeued = vec![];
+                let mut blocks = stream::iter(queue)
+                    .map(|slot| {
+                        let rate = rate.clone();
+                        async move { (slot, rate.fetch_block(slot).await) }
+                    })
+                    .buffered(concurrency);
+                while let Some((slot, block)) = blocks.next().await {
+                    match block {
+                        Ok(block) => yield Ok((slot, block)),
+                        Err(e)
+                            if round < REQUEUE_ROUNDS
+                                && matches!(e, FetchError::Transport(_) | FetchError::NotAvailable(_)) =>
+                        {
+                            warn!(slot, round, "Requeueing slot: {:#}", e);
+                            requeued.push(slot);
+                        }
+                        Err(e) => yield Err(Error::new(e).context(FailedSlot(slot))),
+                    }
+                }
+                if requeued.is_empty() {
+                    break;
+                }
+                sleep(REQUEUE_DELAY * (round as u32 + 1)).await;
+                queue = requeued;
+            }
+        })
+    }
+
+    fn follow(&self, from: Option<u64>) -> BlockStream {
+        let concurrency = self.concurrency;
+        let max_lag = self.max_lag;
+        let rate = self.rate.clone();
+
+        Box::pin(async_stream::stream! {
+            let mut next_slot = match from {
+                Some(slot) => slot,
+                None => latest_slot_or_wait().await,
+            };
+
+            loop {
+                let tip = latest_slot_or_wait().await;
+                if tip < next_slot {
+                    sleep(Duration::from_millis(400)).await;
+                    continue;
+                }
+
+                let lag = tip - next_slot;
+                debug!(tip, slot = next_slot, lag, "Tail");
+                if lag > max_lag {
+                    let left_behind = next_slot..tip - max_lag;
+                    warn!(
+                        tip,
+                        lag,
+                        "Tail: lag exceeds {}, skipping slots {}..{}",
+                        max_lag,
+                        left_behind.start,
+                        left_behind.end
+                    );
+                    next_slot = left_behind.end;
+                    yield Err(anyhow!("Tail fell {} slots behind the tip", lag).context(LeftBehind(left_behind)));
+                }
+
+                let mut blocks = stream::iter(next_slot..=tip)
+                    .map(|slot| {
+                        let rate = rate.clone();
+                        async move { (slot, fetch_slot(rate, slot).await) }
+                    })
+                    .buffered(concurrency);
+                while let Some((slot, block)) = blocks.next().await {
+                    next_slot = slot + 1;
+                    yield block;
+                }
+            }
+        })
+    }
+}
+
+/// Receives blocks over the websocket `blockSubscribe` API. Only live blocks are
+/// available, and the RPC node must run with `--rpc-pubsub-enable-block-subscription`.
+pub struct WsBlockSource {
+    url: String,
+    commitment: CommitmentConfig,
+}
+
+impl WsBlockSource {
+    pub fn new(url: &str) -> Self {
+        WsBlockSource {
+            url: url.to_string(),
+            commitment: CommitmentConfig::confirmed(),
+        }
+    }
+}
+
+impl BlockSource for WsBlockSource {
+    fn fetch(&self, _slots: Vec<u64>) -> BlockStream {
+        unsupported("blockSubscribe cannot fetch historical slots")
+    }
+
+    fn follow(&self, _from: Option<u64>) -> BlockStream {
+        let url = self.url.clone();
+        let config = RpcBlockSubscribeConfig {
+            commitment: Some(self.commitment),
+            encoding: Some(UiTransactionEncoding::Base64),
+            transaction_details: Some(TransactionDetails::Full),
+            show_rewards: Some(false),
+            max_supported_transaction_version: Some(0),
+        };
+
+        Box::pin(async_stream::stream! {
+            loop {
+                let client = match PubsubClient::new(&url).await {
+                    Ok(client) => client,
+                    Err(e) => {
+                        warn!(endpoint = %endpoint_label(&url), "Failed to connect: {:#}", e);
+                        sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                // blockSubscribe filters on a single program only, and more than one
+                // Raydium program is decoded, so whole blocks are requested.
+                let filter = RpcBlockSubscribeFilter::All;
+                let (mut updates, _unsubscribe) =
+                    match client.block_subscribe(filter, Some(config.clone())).await {
+                        Ok(subscription) => subscription,
+                        Err(e) => {
+                            warn!(endpoint = %endpoint_label(&url), "Failed to subscribe to blocks: {:#}", e);
+                            sleep(Duration::from_secs(1)).await;
+                            continue;
+                        }
+                    };
+
+                while let Some(update) = updates.next().await {
+                    let update = update.value;
+                    match (update.block, update.err) {
+                        (Some(block), _) => yield Ok((update.slot, EncodedConfirmedBlock::from(block))),
+                        (None, Some(err)) => yield Err(anyhow!("Block update error for slot {}: {:?}", update.slot, err)),
+                        (None, None) => {}
+                    }
+                }
+                warn!(endpoint = %endpoint_label(&url), "Block subscription ended, reconnecting");
+                sleep(Duration::from_secs(1)).await;
+            }
+        })
+    }
+}
+
+/// Streams confirmed blocks containing Raydium transactions from a Geyser gRPC
+/// endpoint, reconnecting whenever the subscription drops.
+pub struct GeyserBlockSource {
+    client: GeyserClient,
+}
+
+impl GeyserBlockSource {
+    pub fn new(client: GeyserClient) -> Self {
+        GeyserBlockSource { client }
+    }
+}
+
+impl BlockSource for GeyserBlockSource {
+    fn fetch(&self, _slots: Vec<u64>) -> BlockStream {
+        unsupported("Geyser subscriptions cannot fetch historical slots")
+    }
+
+    fn follow(&self, from: Option<u64>) -> BlockStream {
+        let client = self.client.clone();
+
+        Box::pin(async_stream::stream! {
+            let mut from = from;
+            loop {
+                let blocks = match client
+                    .subscribe_blocks(config().programs.raydium(), from)
+                    .await
+                {
+                    Ok(blocks) => blocks,
+                    Err(e) => {
+                        warn!("Failed to subscribe to geyser: {:#}", e);
+                        sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let mut blocks = Box::pin(blocks);
+
+                while let Some(block) = blocks.next().await {
+                    if let Ok((slot, _)) = &block {
+                        from = Some(slot + 1);
+                    }
+                    yield block;
+                }
+                warn!("Geyser subscription ended, reconnecting");
+                sleep(Duration::from_secs(1)).await;
+            }
+        })
+    }
+}
+
+/// Reads recorded blocks: `<dir>/<slot>.json` files holding a `getBlock` response, or a
+/// `BlockArchive` (`<dir>/<date>/<slot>.json.zst`). Blocks are decoded in parallel on
+/// blocking threads and yielded in the requested order.
+pub struct FileBlockSource {
+    dir: PathBuf,
+}
+
+impl FileBlockSource {
+    pub fn new(dir: &str) -> Self {
+        FileBlockSource {
+            dir: PathBuf::from(dir),
+        }
+    }
+
+    fn slot_of(path: &Path) -> Option<u64> {
+        let name = path.file_name()?.to_str()?;
+        let stem = name
+            .strip_suffix(".json")
+            .or_else(|| name.strip_suffix(&format!(".{}", ARCHIVE_EXTENSION)))?;
+        stem.parse().ok()
+    }
+
+    // Every recorded slot and its file, looking one level into date folders.
+    fn index(&self) -> Result<BTreeMap<u64, PathBuf>> {
+        let mut index = BTreeMap::new();
+        for entry in fs::read_dir(&self.dir)
+            .with_context(|| format!("Failed to read {}", self.dir.display()))?
+        {
+            let path = entry?.path();
+            if path.is_dir() {
+                for entry in fs::read_dir(&path)? {
+                    let path = entry?.path();
+                    if let Some(slot) = Self::slot_of(&path) {
+                        index.insert(slot, path);
+                    }
+                }
+            } else if let Some(slot) = Self::slot_of(&path) {
+                index.insert(slot, path);
+            }
+        }
+        Ok(index)
+    }
+
+    fn read_slots(index: BTreeMap<u64, PathBuf>, slots: Vec<u64>) -> BlockStream {
+        let parallelism = std::thread::available_parallelism().map_or(4, |n| n.get());
+        let index = Arc::new(index);
+        Box::pin(
+            stream::iter(slots)
+                .map(move |slot| {
+                    let index = index.clone();
+                    async move {
+                        let path = index
+                            .get(&slot)
+                            .cloned()
+                            .ok_or_else(|| anyhow!("Slot {} is not recorded", slot))
+                            .context(FailedSlot(slot))?;
+                        let block = tokio::task::spawn_blocking(move || read_block(&path))
+                            .await?
+                            .context(FailedSlot(slot))?;
+                        Ok((slot, block))
+                    }
+                })
+                .buffered(parallelism),
+        )
+    }
+}
+
+impl BlockSource for FileBlockSource {
+    fn fetch(&self, slots: Vec<u64>) -> BlockStream {
+        match self.index() {
+            Ok(index) => Self::read_slots(index, slots),
+            Err(e) => Box::pin(stream::once(async move { Err::<_, Error>(e) })),
+        }
+    }
+
+    /// Replays every recorded slot at or after `from`, oldest first.
+    fn follow(&self, from: Option<u64>) -> BlockStream {
+        let index = match self.index() {
+            Ok(index) => index,
+            Err(e) => return Box::pin(stream::once(async move { Err::<_, Error>(e) })),
+        };
+        let slots = index.range(from.unwrap_or(0)..).map(|(slot, _)| *slot).collect();
+        Self::read_slots(index, slots)
+    }
+}
+
+/// Reads Old Faithful epoch archives (`.car` files) from local disk: either one file or
+/// every `.car` file in a directory, taken in epoch order (`epoch-<n>.car`).
+pub struct CarBlockSource {
+    path: PathBuf,
+}
+
+impl CarBlockSource {
+    pub fn new(path: &str) -> Self {
+        CarBlockSource {
+            path: PathBuf::from(path),
+        }
+    }
+
+    fn files(&self) -> Result<Vec<PathBuf>> {
+        if !self.path.is_dir() {
+            return Ok(vec![self.path.clone()]);
+        }
+        let mut files = vec![];
+        for entry in fs::read_dir(&self.path)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "car") {
+                files.push(path);
+            }
+        }
+        // By number, so epoch-99.car comes before epoch-100.car.
+        files.sort_by_cached_key(|path| (Self::epoch_of(path), path.clone()));
+        Ok(files)
+    }
+
+    // The last run of digits in the file name, e.g. 700 for `epoch-700.car`.
+    fn epoch_of(path: &Path) -> Option<u64> {
+        let stem = path.file_stem()?.to_str()?;
+        stem.split(|c: char| !c.is_ascii_digit())
+            .rfind(|part| !part.is_empty())?
+            .parse()
+            .ok()
+    }
+
+    // Walks the archives on a blocking thread and yields the `wanted` blocks, or every
+    // block at or after `from` when there is no list. An epoch archive holds every block
+    // of its epoch, so slots of `wanted` passed over within the epoch being read are
+    // reported as skipped. Slots outside every epoch read (a missing file, a file cut
+    // short) are reported as not in the archive, so they stay failed and are fetched
+    // again later.
+    fn scan(&self, wanted: Option<BTreeSet<u64>>, from: u64) -> BlockStream {
+        let files = match self.files() {
+            Ok(files) => files,
+            Err(e) => return Box::pin(stream::once(async move { Err::<_, Error>(e) })),
+        };
+        let (sender, receiver) = tokio::sync::mpsc::channel(64);
+
+        tokio::task::spawn_blocking(move || {
+            let mut wanted = wanted;
+            // Reports the wanted slots in `range` as skipped; false once the receiver
+            // is gone.
+            let report_skipped = |wanted: &mut Option<BTreeSet<u64>>, range: Range<u64>| {
+                let Some(wanted) = wanted.as_mut() else {
+                    return true;
+                };
+                let skipped: Vec<u64> = wanted.range(range).copied().collect();
+                for skipped_slot in skipped {
+                    wanted.remove(&skipped_slot);
+                    let error = Error::new(FetchError::Skipped(skipped_slot))
+                        .context(FailedSlot(skipped_slot));
+                    if sender.blocking_send(Err(error)).is_err() {
+                        return false;
+                    }
+                }
+                true
+            };
+
+            for file in files {
+                let mut reader = match CarReader::open(&file) {
+                    Ok(reader) => reader,
+                    Err(e) => {
+                        let _ = sender.blocking_send(Err(e));
+                        continue;
+                    }
+                };
+                // Start of the epoch being read and the slot after the last block seen.
+                let mut epoch: Option<(u64, u64)> = None;
+                loop {
+                    let (slot, block) = match reader.next_block() {
+                        Ok(Some((slot, block))) => (slot, block.context(FailedSlot(slot))),
+                        Ok(None) => {
+                            // The whole epoch was read; nothing after its last block exists.
+                            if let Some((start, next)) = epoch {
+                                let end = start + DEFAULT_SLOTS_PER_EPOCH;
+                                if !report_skipped(&mut wanted, next..end) {
+                                    return;
+                                }
+                            }
+                            break;
+                        }
+                        Err(e) => {
+                            // Sections can't be found again after a bad one; skip the file.
+                            let error = e.context(format!("In {}", file.display()));
+                            let _ = sender.blocking_send(Err(error));
+                            break;
+                        }
+                    };
+                    let start = slot - slot % DEFAULT_SLOTS_PER_EPOCH;
+                    let next = match epoch {
+                        Some((epoch_start, next)) if epoch_start == start => next,
+                        _ => start,
+                    };
+                    epoch = Some((start, slot + 1));
+                    if slot < from {
+                        continue;
+                    }
+                    if !report_skipped(&mut wanted, next..slot) {
+                        return;
+                    }
+                    if let Some(wanted) = wanted.as_mut() {
+                        if !wanted.remove(&slot) {
+                            continue;
+                        }
+                    }
+                    if sender.blocking_send(block.map(|block| (slot, block))).is_err() {
+                        return;
+                    }
+                    if wanted.as_ref().is_some_and(|wanted| wanted.is_empty()) {
+                        return;
+                    }
+                }
+            }
+            for slot in wanted.into_iter().flatten() {
+                let error = Error::new(FetchError::NotAvailable(slot))
+                    .context(format!("Slot {} is not in the archive", slot))
+                    .context(FailedSlot(slot));
+                if sender.blocking_send(Err(error)).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Box::pin(ReceiverStream::new(receiver))
+    }
+}
+
+impl BlockSource for CarBlockSource {
+    /// Streams the requested slots in archive (ascending) order, whatever order they
+    /// were given in.
+    fn fetch(&self, slots: Vec<u64>) -> BlockStream {
+        let from = slots.iter().min().copied().unwrap_or(0);
+        self.scan(Some(slots.into_iter().collect()), from)
+    }
+
+    /// Replays every archived block at or after `from`.
+    fn follow(&self, from: Option<u64>) -> BlockStream {
+        self.scan(None, from.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ciborium::Value;
+
+    use super::*;
+
+    // A CAR file of empty blocks at `slots`, each under a made-up CIDv0.
+    fn write_car(path: &Path, slots: &[u64]) {
+        fn section(out: &mut Vec<u8>, bytes: &[u8]) {
+            let mut len = bytes.len();
+            loop {
+                let byte = (len & 0x7f) as u8;
+                len >>= 7;
+                if len == 0 {
+                    out.push(byte);
+                    break;
+                }
+                out.push(byte | 0x80);
+            }
+            out.extend_from_slice(bytes);
+        }
+
+        let mut out = vec![];
+        let header = Value::Map(vec![
+            (Value::Text("version".into()), Value::Integer(1.into())),
+            (Value::Text("roots".into()), Value::Array(vec![])),
+        ]);
+        let mut bytes = vec![];
+        ciborium::into_writer(&header, &mut bytes).unwrap();
+        section(&mut out, &bytes);
+        for &slot in slots {
+            let block = Value::Array(vec![
+                Value::Integer(2.into()),
+                Value::Integer(slot.into()),
+                Value::Array(vec![]),
+                Value::Array(vec![]),
+                Value::Array(vec![
+                    Value::Integer((slot - 1).into()),
+                    Value::Integer(1_700_000_000.into()),
+                ]),
+            ]);
+            let mut bytes = vec![0x12, 0x20];
+            bytes.extend_from_slice(&slot.to_le_bytes().repeat(4));
+            ciborium::into_writer(&block, &mut bytes).unwrap();
+            section(&mut out, &bytes);
+        }
+        fs::write(path, out).unwrap();
+    }
+
+    #[tokio::test]
+    async fn car_source_only_reports_slots_of_read_epochs_as_skipped() {
+        let dir = std::env::temp_dir().join(format!("raydium-car-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let epoch = |n: u64| n * DEFAULT_SLOTS_PER_EPOCH;
+        // Named so that file name order would put epoch 10 first; epoch 3 is missing.
+        write_car(&dir.join("epoch-2.car"), &[epoch(2) + 1, epoch(2) + 3]);
+        write_car(&dir.join("epoch-10.car"), &[epoch(10)]);
+
+        let wanted = vec![
+            epoch(1) - 1,
+            epoch(2),
+            epoch(2) + 1,
+            epoch(2) + 2,
+            epoch(2) + 3,
+            epoch(3) - 1,
+            epoch(3) + 5,
+            epoch(10),
+        ];
+        let source = CarBlockSource::new(dir.to_str().unwrap());
+        let outcomes: Vec<(u64, &str)> = source
+            .fetch(wanted)
+            .map(|block| match block {
+                Ok((slot, _)) => (slot, "block"),
+                Err(e) => {
+                    let FailedSlot(slot) = e.downcast_ref::<FailedSlot>().unwrap();
+                    match e.downcast_ref::<FetchError>() {
+                        Some(FetchError::Skipped(_)) => (*slot, "skipped"),
+                        Some(FetchError::NotAvailable(_)) => (*slot, "missing"),
+                        _ => (*slot, "error"),
+                    }
+                }
+            })
+            .collect()
+            .await;
+
+        assert_eq!(
+            outcomes,
+            vec![
+                (epoch(2), "skipped"),
+                (epoch(2) + 1, "block"),
+                (epoch(2) + 2, "skipped"),
+                (epoch(2) + 3, "block"),
+                (epoch(3) - 1, "skipped"),
+                (epoch(10), "block"),
+                (epoch(1) - 1, "missing"),
+                (epoch(3) + 5, "missing"),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::Write,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+};
+
+use anyhow::{anyhow, Error, Result};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlotStatus {
+    /// Block processed and its trades written.
+    Done,
+    /// Block processed but it held no Raydium trades.
+    Empty,
+    /// The leader skipped the slot, there is no block to fetch.
+    Skipped,
+    /// Fetching or processing failed; the slot is retried on the next run.
+    Failed,
+}
+
+impl SlotStatus {
+    pub fn is_finished(self) -> bool {
+        self != SlotStatus::Failed
+    }
+}
+
+impl fmt::Display for SlotStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self {
+            SlotStatus::Done => "done",
+            SlotStatus::Empty => "empty",
+            SlotStatus::Skipped => "skipped",
+            SlotStatus::Failed => "failed",
+        };
+        write!(f, "{}", status)
+    }
+}
+
+impl FromStr for SlotStatus {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "done" => Ok(SlotStatus::Done),
+            "empty" => Ok(SlotStatus::Empty),
+            "skipped" => Ok(SlotStatus::Skipped),
+            "failed" => Ok(SlotStatus::Failed),
+            _ => Err(anyhow!("Unknown slot status: {}", s)),
+        }
+    }
+}
+
+/// Per-slot progress of indexer runs, kept as an append-only log of `<slot> <status>`
+/// lines. The latest line for a slot wins, and a line cut short by a crash is ignored.
+pub struct Checkpoint {
+    file: Mutex<File>,
+    statuses: Mutex<BTreeMap<u64, SlotStatus>>,
+}
+
+impl Checkpoint {
+    /// Opens the log at `path`, creating it if needed, and loads the recorded statuses.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut statuses = BTreeMap::new();
+        if path.exists() {
+            for line in fs::read_to_string(path)?.lines() {
+                let mut parts = line.split_whitespace();
+                let slot = parts.next().and_then(|slot| slot.parse::<u64>().ok());
+                let status = parts.next().and_then(|status| status.parse::<SlotStatus>().ok());
+                if let (Some(slot), Some(status)) = (slot, status) {
+                    statuses.insert(slot, status);
+                }
+            }
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        // Start on a fresh line if the previous run died mid-write.
+        if fs::read(path)?.last().is_some_and(|byte| *byte != b'\n') {
+            file.write_all(b"\n")?;
+        }
+
+        Ok(Checkpoint {
+            file: Mutex::new(file),
+            statuses: Mutex::new(statuses),
+        })
+    }
+
+    pub fn record(&self, slot: u64, status: SlotStatus) -> Result<()> {
+        self.file
+            .lock()
+            .unwrap()
+            .write_all(format!("{} {}\n", slot, status).as_bytes())?;
+        self.statuses.lock().unwrap().insert(slot, status);
+        Ok(())
+    }
+
+    /// Forces recorded statuses to disk, e.g. before exiting.
+    pub fn sync(&self) -> Result<()> {
+        self.file.lock().unwrap().sync_all()?;
+        Ok(())
+    }
+
+    pub fn status(&self, slot: u64) -> Option<SlotStatus> {
+        self.statuses.lock().unwrap().get(&slot).copied()
+    }
+
+    /// `slots` without the ones a previous run already finished, order preserved.
+    pub fn pending(&self, slots: Vec<u64>) -> Vec<u64> {
+        let statuses = self.statuses.lock().unwrap();
+        slots
+            .into_iter()
+            .filter(|slot| !statuses.get(slot).is_some_and(|status| status.is_finished()))
+            .collect()
+    }
+
+    /// Highest slot with a recorded status, where a tailing run picks up again.
+    pub fn last_slot(&self) -> Option<u64> {
+        self.statuses.lock().unwrap().keys().next_back().copied()
+    }
+
+    pub fn finished_slots(&self) -> Vec<u64> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, status)| status.is_finished())
+            .map(|(slot, _)| *slot)
+            .collect()
+    }
+
+    pub fn failed(&self) -> Vec<u64> {
+        self.statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, status)| **status == SlotStatus::Failed)
+            .map(|(slot, _)| *slot)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A checkpoint path of its own per test, starting out with `contents`.
+    fn log(name: &str, contents: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("raydium-checkpoint-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_latest_status_wins() {
+        let path = log("latest", "10 failed\n11 done\n10 done\n11 failed\n");
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.status(10), Some(SlotStatus::Done));
+        assert_eq!(checkpoint.status(11), Some(SlotStatus::Failed));
+        assert_eq!(checkpoint.status(12), None);
+
+        checkpoint.record(11, SlotStatus::Skipped).unwrap();
+        assert_eq!(checkpoint.status(11), Some(SlotStatus::Skipped));
+        assert_eq!(checkpoint.failed(), Vec::<u64>::new());
+    }
+
+    #[test]
+    fn test_truncated_last_line() {
+        let path = log("truncated", "10 done\n11 emp");
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.status(10), Some(SlotStatus::Done));
+        assert_eq!(checkpoint.status(11), None);
+
+        // The next record starts on its own line rather than extending the cut one.
+        checkpoint.record(12, SlotStatus::Empty).unwrap();
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "10 done\n11 emp\n12 empty\n"
+        );
+        drop(checkpoint);
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.status(11), None);
+        assert_eq!(checkpoint.status(12), Some(SlotStatus::Empty));
+    }
+
+    #[test]
+    fn test_pending() {
+        let path = log("pending", "");
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        checkpoint.record(1, SlotStatus::Done).unwrap();
+        checkpoint.record(2, SlotStatus::Empty).unwrap();
+        checkpoint.record(3, SlotStatus::Skipped).unwrap();
+        checkpoint.record(4, SlotStatus::Failed).unwrap();
+
+        assert_eq!(checkpoint.pending(vec![6, 4, 3, 5, 2, 1]), [6, 4, 5]);
+        assert_eq!(checkpoint.finished_slots(), [1, 2, 3]);
+        assert_eq!(checkpoint.failed(), [4]);
+    }
+
+    #[test]
+    fn test_last_slot_after_restart() {
+        let path = log("restart", "");
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.last_slot(), None);
+        checkpoint.record(7, SlotStatus::Done).unwrap();
+        checkpoint.record(9, SlotStatus::Failed).unwrap();
+        checkpoint.record(8, SlotStatus::Empty).unwrap();
+        checkpoint.sync().unwrap();
+        drop(checkpoint);
+
+        let checkpoint = Checkpoint::open(&path).unwrap();
+        assert_eq!(checkpoint.last_slot(), Some(9));
+        assert_eq!(checkpoint.pending(vec![7, 8, 9, 10]), [9, 10]);
+    }
+}
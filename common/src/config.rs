@@ -0,0 +1,326 @@
+// Settings shared by every binary, read once at startup. Values are layered, each one
+// overriding the previous:
+//
+//   1. built-in defaults
+//   2. the TOML file (RAYDIUM_CONFIG, or ./config.toml when it exists)
+//   3. the selected `[profile.<name>]` table of that file (RAYDIUM_PROFILE)
+//   4. the older SOLANA_RPC_URLS / SOLANA_RPC_URL, OUTPUT_PATH and METRICS_ADDR variables
+//   5. RAYDIUM__<SECTION>__<KEY> variables, e.g. RAYDIUM__CONCURRENCY__INDEXER=50
+//
+// Command line flags of the binaries take precedence over all of them.
+
+use std::{
+    env, fs,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use toml::{Table, Value};
+
+use crate::{listener::TradeFilter, models::OutputFormat, rpc_pool::RpcEndpoint};
+
+pub const RAYDIUM_AMM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
+pub const RAYDIUM_CLMM_PROGRAM_ID: &str = "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK";
+pub const RAYDIUM_CPMM_PROGRAM_ID: &str = "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C";
+pub const JUPITER_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
+pub const SERUM_PROGRAM_ID: &str = "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX";
+
+const DEFAULT_PATH: &str = "config.toml";
+const ENV_PREFIX: &str = "RAYDIUM__";
+
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub rpc: RpcConfig,
+    pub paths: PathsConfig,
+    pub programs: ProgramsConfig,
+    pub concurrency: ConcurrencyConfig,
+    pub sinks: SinksConfig,
+    /// Trades outside the filter are dropped before they are written or published.
+    pub filters: TradeFilter,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcConfig {
+    pub endpoints: Vec<RpcEndpoint>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PathsConfig {
+    /// Root of the per-slot trade files, also read by the preprocessor and planner.
+    pub output_dir: Option<String>,
+    /// Defaults to `<output_dir>/checkpoint`.
+    pub checkpoint: Option<String>,
+    pub archive_dir: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProgramsConfig {
+    pub raydium_amm: String,
+    pub raydium_clmm: String,
+    pub raydium_cpmm: String,
+    pub jupiter: String,
+    pub serum: String,
+}
+
+impl ProgramsConfig {
+    /// The Raydium programs whose transactions are decoded.
+    pub fn raydium(&self) -> Vec<String> {
+        vec![
+            self.raydium_amm.clone(),
+            self.raydium_clmm.clone(),
+            self.raydium_cpmm.clone(),
+        ]
+    }
+}
+
+impl Default for ProgramsConfig {
+    fn default() -> Self {
+        ProgramsConfig {
+            raydium_amm: RAYDIUM_AMM_PROGRAM_ID.to_string(),
+            raydium_clmm: RAYDIUM_CLMM_PROGRAM_ID.to_string(),
+            raydium_cpmm: RAYDIUM_CPMM_PROGRAM_ID.to_string(),
+            jupiter: JUPITER_PROGRAM_ID.to_string(),
+            serum: SERUM_PROGRAM_ID.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConcurrencyConfig {
+    /// Upper bound on blocks the indexer fetches at once.
+    pub indexer: usize,
+    /// Slots the preprocessor works on at once.
+    pub preprocessor: usize,
+    /// In tail mode, how far behind the tip the indexer may fall before skipping ahead.
+    pub max_lag: u64,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        ConcurrencyConfig {
+            indexer: 25,
+            preprocessor: 30,
+            max_lag: 1_000,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    pub format: OutputFormat,
+    /// ZMQ endpoint to publish processed slots on, e.g. tcp://*:5555
+    pub publisher: Option<String>,
+    pub metrics_addr: Option<SocketAddr>,
+}
+
+/// Loads the configuration for the rest of the process. `path` and `profile` replace
+/// RAYDIUM_CONFIG and RAYDIUM_PROFILE; must run before anything reads `config()`.
+pub fn init(path: Option<&Path>, profile: Option<&str>) -> Result<&'static Config> {
+    let loaded = Config::load(path, profile)?;
+    CONFIG
+        .set(loaded)
+        .map_err(|_| anyhow!("Configuration was already loaded"))?;
+    Ok(config())
+}
+
+/// The process-wide configuration, loaded from the environment on first use when
+/// `init` was not called.
+pub fn config() -> &'static Config {
+    CONFIG.get_or_init(|| Config::load(None, None).expect("Invalid configuration"))
+}
+
+impl Config {
+    pub fn load(path: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let path = match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => match env::var("RAYDIUM_CONFIG") {
+                Ok(path) => Some(PathBuf::from(path)),
+                Err(_) => Some(PathBuf::from(DEFAULT_PATH)).filter(|path| path.exists()),
+            },
+        };
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| env::var("RAYDIUM_PROFILE").ok());
+
+        let mut table = match &path {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config {}", path.display()))?;
+                text.parse::<Table>()
+                    .with_context(|| format!("Failed to parse config {}", path.display()))?
+            }
+            None => Table::new(),
+        };
+        let profiles = table.remove("profile");
+        if let Some(name) = &profile {
+            let overlay = profiles
+                .as_ref()
+                .and_then(|profiles| profiles.get(name))
+                .and_then(Value::as_table)
+                .ok_or_else(|| anyhow!("Config profile {} is not defined", name))?;
+            merge(&mut table, overlay.clone());
+        }
+        apply_legacy_env(&mut table)?;
+        apply_env_overrides(&mut table, env::vars());
+
+        let config: Config = Value::Table(table)
+            .try_into()
+            .context("Invalid configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks everything that deserializing can't, and reports all problems at once.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = vec![];
+
+        for endpoint in &self.rpc.endpoints {
+            match reqwest::Url::parse(&endpoint.url) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => problems.push(format!("rpc.endpoints: {} is not an http(s) url", endpoint.url)),
+            }
+            if endpoint.weight == 0 {
+                problems.push(format!("rpc.endpoints: weight of {} must be positive", endpoint.url));
+            }
+            if endpoint.rate_limit == Some(0) {
+                problems.push(format!("rpc.endpoints: rate_limit of {} must be positive", endpoint.url));
+            }
+        }
+
+        for (key, value) in [
+            ("output_dir", &self.paths.output_dir),
+            ("checkpoint", &self.paths.checkpoint),
+            ("archive_dir", &self.paths.archive_dir),
+        ] {
+            if value.as_deref().is_some_and(|value| value.trim().is_empty()) {
+                problems.push(format!("paths.{} is empty", key));
+            }
+        }
+
+        for (key, program) in [
+            ("raydium_amm", &self.programs.raydium_amm),
+            ("raydium_clmm", &self.programs.raydium_clmm),
+            ("raydium_cpmm", &self.programs.raydium_cpmm),
+            ("jupiter", &self.programs.jupiter),
+            ("serum", &self.programs.serum),
+        ] {
+            if Pubkey::from_str(program).is_err() {
+                problems.push(format!("programs.{}: {} is not a public key", key, program));
+            }
+        }
+
+        if self.concurrency.indexer == 0 {
+            problems.push("concurrency.indexer must be positive".to_string());
+        }
+        if self.concurrency.preprocessor == 0 {
+            problems.push("concurrency.preprocessor must be positive".to_string());
+        }
+
+        if self.sinks.publisher.as_deref().is_some_and(|publisher| publisher.trim().is_empty()) {
+            problems.push("sinks.publisher is empty".to_string());
+        }
+
+        for (key, addresses) in [("pools", &self.filters.pools), ("mints", &self.filters.mints)] {
+            for address in addresses {
+                if Pubkey::from_str(address).is_err() {
+                    problems.push(format!("filters.{}: {} is not a public key", key, address));
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Invalid configuration:\n  {}", problems.join("\n  ")))
+        }
+    }
+}
+
+// Tables are merged key by key; any other value in `overlay` replaces the one in `base`.
+fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+fn set(table: &mut Table, keys: &[String], value: Value) {
+    let Some((last, parents)) = keys.split_last() else {
+        return;
+    };
+    let mut table = table;
+    for key in parents {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        if !entry.is_table() {
+            *entry = Value::Table(Table::new());
+        }
+        table = entry.as_table_mut().unwrap();
+    }
+    table.insert(last.clone(), value);
+}
+
+// The variables used before the config file existed keep working.
+fn apply_legacy_env(table: &mut Table) -> Result<()> {
+    let rpc_urls = env::var("SOLANA_RPC_URLS").or_else(|_| env::var("SOLANA_RPC_URL"));
+    if let Ok(specs) = rpc_urls {
+        let endpoints = specs
+            .split(',')
+            .filter(|spec| !spec.trim().is_empty())
+            .map(|spec| {
+                let endpoint = RpcEndpoint::parse(spec)?;
+                let mut entry = Table::new();
+                entry.insert("url".to_string(), Value::String(endpoint.url));
+                entry.insert("weight".to_string(), Value::Integer(endpoint.weight.into()));
+                if let Some(rate_limit) = endpoint.rate_limit {
+                    entry.insert("rate_limit".to_string(), Value::Integer(rate_limit.into()));
+                }
+                Ok(Value::Table(entry))
+            })
+            .collect::<Result<Vec<_>>>()
+            .context("Invalid SOLANA_RPC_URLS")?;
+        set(table, &["rpc".into(), "endpoints".into()], Value::Array(endpoints));
+    }
+    if let Ok(dir) = env::var("OUTPUT_PATH") {
+        set(table, &["paths".into(), "output_dir".into()], Value::String(dir));
+    }
+    if let Ok(addr) = env::var("METRICS_ADDR") {
+        set(table, &["sinks".into(), "metrics_addr".into()], Value::String(addr));
+    }
+    Ok(())
+}
+
+// RAYDIUM__SECTION__KEY=value. Values are read as TOML (numbers, booleans, arrays) and
+// fall back to a plain string, so RAYDIUM__PATHS__OUTPUT_DIR=/data needs no quotes.
+fn apply_env_overrides(table: &mut Table, vars: impl Iterator<Item = (String, String)>) {
+    for (name, raw) in vars {
+        let Some(path) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let keys: Vec<String> = path.split("__").map(str::to_lowercase).collect();
+        let value = format!("value = {}", raw)
+            .parse::<Table>()
+            .ok()
+            .and_then(|mut parsed| parsed.remove("value"))
+            .unwrap_or(Value::String(raw));
+        set(table, &keys, value);
+    }
+}
@@ -0,0 +1,78 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use crate::metrics::DECODE_ERRORS;
+
+/// Why a transaction (or, for the block time, a whole block) could not be decoded.
+/// Each variant maps to a stable `reason` used for failure counts and dead letters.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum DecodeError {
+    #[error("block has no block time")]
+    MissingBlockTime,
+    #[error("block time {0} is out of range")]
+    InvalidBlockTime(i64),
+    #[error("transaction has no status meta")]
+    MissingMeta,
+    #[error("transaction has no signature")]
+    MissingSignature,
+    #[error("unsupported transaction encoding")]
+    UnsupportedEncoding,
+    #[error("{0} token balances missing from meta")]
+    MissingTokenBalances(&'static str),
+    #[error("no token balance for {0}")]
+    MissingVaultBalance(String),
+    #[error("lamport balances missing for account {0}")]
+    MissingLamportBalance(usize),
+    #[error("no inner instructions for instruction {0}")]
+    MissingInnerInstructions(usize),
+    #[error("account index {0} out of range")]
+    AccountIndex(usize),
+    #[error("instruction has no account at position {0}")]
+    MissingAccount(usize),
+    #[error("invalid instruction data: {0}")]
+    InstructionData(String),
+}
+
+impl DecodeError {
+    pub fn reason(&self) -> &'static str {
+        match self {
+            DecodeError::MissingBlockTime => "missing_block_time",
+            DecodeError::InvalidBlockTime(_) => "invalid_block_time",
+            DecodeError::MissingMeta => "missing_meta",
+            DecodeError::MissingSignature => "missing_signature",
+            DecodeError::UnsupportedEncoding => "unsupported_encoding",
+            DecodeError::MissingTokenBalances(_) => "missing_token_balances",
+            DecodeError::MissingVaultBalance(_) => "missing_vault_balance",
+            DecodeError::MissingLamportBalance(_) => "missing_lamport_balance",
+            DecodeError::MissingInnerInstructions(_) => "missing_inner_instructions",
+            DecodeError::AccountIndex(_) => "account_index",
+            DecodeError::MissingAccount(_) => "missing_account",
+            DecodeError::InstructionData(_) => "instruction_data",
+        }
+    }
+}
+
+impl From<solana_sdk::bs58::decode::Error> for DecodeError {
+    fn from(e: solana_sdk::bs58::decode::Error) -> Self {
+        DecodeError::InstructionData(e.to_string())
+    }
+}
+
+impl From<std::io::Error> for DecodeError {
+    fn from(e: std::io::Error) -> Self {
+        DecodeError::InstructionData(e.to_string())
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref FAILURE_COUNTS: Mutex<BTreeMap<&'static str, u64>> = Mutex::new(BTreeMap::new());
+}
+
+pub fn count_failure(error: &DecodeError) {
+    *FAILURE_COUNTS.lock().unwrap().entry(error.reason()).or_default() += 1;
+    DECODE_ERRORS.with_label_values(&[error.reason()]).inc();
+}
+
+/// Decode failures since startup, by reason.
+pub fn failure_counts() -> BTreeMap<&'static str, u64> {
+    FAILURE_COUNTS.lock().unwrap().clone()
+}
@@ -0,0 +1,329 @@
+pub mod proto;
+#[cfg(test)]
+mod tests;
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Result};
+use solana_account_decoder::parse_token::UiTokenAmount;
+use solana_sdk::{bs58, message::MessageHeader, transaction::TransactionVersion};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedBlock, EncodedTransaction,
+    EncodedTransactionWithStatusMeta, UiAddressTableLookup, UiCompiledInstruction,
+    UiInnerInstructions, UiInstruction, UiLoadedAddresses, UiMessage, UiRawMessage,
+    UiTransaction, UiTransactionStatusMeta, UiTransactionTokenBalance,
+};
+use tokio::sync::mpsc;
+use tokio_stream::{wrappers::ReceiverStream, Stream, StreamExt};
+use tonic::{
+    codec::ProstCodec,
+    codegen::http::uri::PathAndQuery,
+    metadata::AsciiMetadataValue,
+    transport::{ClientTlsConfig, Endpoint},
+};
+
+use self::proto::{
+    subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+    SubscribeRequestFilterBlocks, SubscribeRequestPing, SubscribeUpdate,
+};
+
+const SUBSCRIBE_PATH: &str = "/geyser.Geyser/Subscribe";
+
+/// Client for a Yellowstone (Geyser gRPC) endpoint.
+#[derive(Clone, Debug)]
+pub struct GeyserClient {
+    endpoint: String,
+    x_token: Option<String>,
+    commitment: CommitmentLevel,
+}
+
+impl GeyserClient {
+    pub fn new(endpoint: &str) -> Self {
+        GeyserClient {
+            endpoint: endpoint.to_string(),
+            x_token: None,
+            commitment: CommitmentLevel::Confirmed,
+        }
+    }
+
+    pub fn x_token(mut self, x_token: Option<String>) -> Self {
+        self.x_token = x_token;
+        self
+    }
+
+    pub fn commitment(mut self, commitment: CommitmentLevel) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Subscribes to full blocks, keeping only transactions that touch one of
+    /// `account_include`. Each update is converted into the same
+    /// `EncodedConfirmedBlock` shape `getBlock` returns, so it can go straight to
+    /// `process_block`. `from_slot` replays from an earlier slot if the server still
+    /// has it buffered.
+    pub async fn subscribe_blocks(
+        &self,
+        account_include: Vec<String>,
+        from_slot: Option<u64>,
+    ) -> Result<impl Stream<Item = Result<(u64, EncodedConfirmedBlock)>>> {
+        let request = SubscribeRequest {
+            blocks: HashMap::from([(
+                "raydium".to_string(),
+                SubscribeRequestFilterBlocks {
+                    account_include,
+                    include_transactions: Some(true),
+                    include_accounts: Some(false),
+                    include_entries: Some(false),
+                },
+            )]),
+            commitment: Some(self.commitment as i32),
+            from_slot,
+            ..Default::default()
+        };
+        let updates = self.subscribe(request).await?;
+
+        Ok(updates.filter_map(|update| match update {
+            Ok(UpdateOneof::Block(block)) => {
+                let slot = block.slot;
+                Some(convert_block(block).map(|block| (slot, block)))
+            }
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        }))
+    }
+
+    async fn subscribe(
+        &self,
+        request: SubscribeRequest,
+    ) -> Result<impl Stream<Item = Result<UpdateOneof>>> {
+        let mut endpoint = Endpoint::from_shared(self.endpoint.clone())?;
+        if self.endpoint.starts_with("https") {
+            endpoint = endpoint.tls_config(ClientTlsConfig::new().with_native_roots())?;
+        }
+        let channel = endpoint.connect().await?;
+        let mut grpc = tonic::client::Grpc::new(channel);
+        grpc.ready().await?;
+
+        // The request side stays open for the life of the subscription so we can
+        // answer server pings, which some load balancers require to keep the stream.
+        let (requests, receiver) = mpsc::channel(16);
+        requests.send(request).await?;
+
+        let mut grpc_request = tonic::Request::new(ReceiverStream::new(receiver));
+        if let Some(x_token) = &self.x_token {
+            let x_token: AsciiMetadataValue = x_token.parse()?;
+            grpc_request.metadata_mut().insert("x-token", x_token);
+        }
+
+        let mut updates = grpc
+            .streaming::<_, SubscribeRequest, SubscribeUpdate, _>(
+                grpc_request,
+                PathAndQuery::from_static(SUBSCRIBE_PATH),
+                ProstCodec::default(),
+            )
+            .await?
+            .into_inner();
+
+        Ok(async_stream::stream! {
+            loop {
+                match updates.message().await {
+                    Ok(Some(update)) => match update.update_oneof {
+                        Some(UpdateOneof::Ping(_)) => {
+                            let ping = SubscribeRequest {
+                                ping: Some(SubscribeRequestPing { id: 1 }),
+                                ..Default::default()
+                            };
+                            if requests.send(ping).await.is_err() {
+                                break;
+                            }
+                        }
+                        Some(update) => yield Ok(update),
+                        None => {}
+                    },
+                    Ok(None) => break,
+                    Err(status) => {
+                        yield Err(anyhow!("Geyser stream error: {}", status));
+                        break;
+                    }
+                }
+            }
+        })
+    }
+}
+
+pub fn convert_block(block: proto::SubscribeUpdateBlock) -> Result<EncodedConfirmedBlock> {
+    let transactions = block
+        .transactions
+        .into_iter()
+        .map(convert_transaction)
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(EncodedConfirmedBlock {
+        previous_blockhash: block.parent_blockhash,
+        blockhash: block.blockhash,
+        parent_slot: block.parent_slot,
+        transactions,
+        rewards: vec![],
+        num_partitions: None,
+        block_time: block.block_time.map(|t| t.timestamp),
+        block_height: block.block_height.map(|h| h.block_height),
+    })
+}
+
+pub fn convert_transaction(
+    info: proto::SubscribeUpdateTransactionInfo,
+) -> Result<EncodedTransactionWithStatusMeta> {
+    let transaction = info
+        .transaction
+        .ok_or_else(|| anyhow!("Transaction missing from update"))?;
+    let meta = info
+        .meta
+        .ok_or_else(|| anyhow!("Transaction meta missing from update"))?;
+    let message = transaction
+        .message
+        .ok_or_else(|| anyhow!("Transaction message missing from update"))?;
+
+    let header = message.header.unwrap_or_default();
+    let address_table_lookups = message.versioned.then(|| {
+        message
+            .address_table_lookups
+            .iter()
+            .map(|lookup| UiAddressTableLookup {
+                account_key: bs58::encode(&lookup.account_key).into_string(),
+                writable_indexes: lookup.writable_indexes.clone(),
+                readonly_indexes: lookup.readonly_indexes.clone(),
+            })
+            .collect()
+    });
+
+    let raw_message = UiRawMessage {
+        header: MessageHeader {
+            num_required_signatures: header.num_required_signatures as u8,
+            num_readonly_signed_accounts: header.num_readonly_signed_accounts as u8,
+            num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts as u8,
+        },
+        account_keys: message.account_keys.iter().map(encode_bs58).collect(),
+        recent_blockhash: encode_bs58(&message.recent_blockhash),
+        instructions: message
+            .instructions
+            .iter()
+            .map(|ix| UiCompiledInstruction {
+                program_id_index: ix.program_id_index as u8,
+                accounts: ix.accounts.clone(),
+                data: encode_bs58(&ix.data),
+                stack_height: None,
+            })
+            .collect(),
+        address_table_lookups,
+    };
+
+    let version = if message.versioned {
+        TransactionVersion::Number(0)
+    } else {
+        TransactionVersion::LEGACY
+    };
+
+    Ok(EncodedTransactionWithStatusMeta {
+        transaction: EncodedTransaction::Json(UiTransaction {
+            signatures: transaction.signatures.iter().map(encode_bs58).collect(),
+            message: UiMessage::Raw(raw_message),
+        }),
+        meta: Some(convert_meta(meta)?),
+        version: Some(version),
+    })
+}
+
+pub fn convert_meta(meta: proto::TransactionStatusMeta) -> Result<UiTransactionStatusMeta> {
+    let err: Option<solana_sdk::transaction::TransactionError> = match meta.err {
+        Some(err) => Some(bincode::deserialize(&err.err)?),
+        None => None,
+    };
+
+    let inner_instructions = if meta.inner_instructions_none {
+        OptionSerializer::None
+    } else {
+        OptionSerializer::Some(
+            meta.inner_instructions
+                .into_iter()
+                .map(|inner| UiInnerInstructions {
+                    index: inner.index as u8,
+                    instructions: inner
+                        .instructions
+                        .into_iter()
+                        .map(|ix| {
+                            UiInstruction::Compiled(UiCompiledInstruction {
+                                program_id_index: ix.program_id_index as u8,
+                                accounts: ix.accounts,
+                                data: encode_bs58(&ix.data),
+                                stack_height: ix.stack_height,
+                            })
+                        })
+                        .collect(),
+                })
+                .collect(),
+        )
+    };
+
+    let log_messages = if meta.log_messages_none {
+        OptionSerializer::None
+    } else {
+        OptionSerializer::Some(meta.log_messages)
+    };
+
+    Ok(UiTransactionStatusMeta {
+        status: match &err {
+            Some(err) => Err(err.clone()),
+            None => Ok(()),
+        },
+        err,
+        fee: meta.fee,
+        pre_balances: meta.pre_balances,
+        post_balances: meta.post_balances,
+        inner_instructions,
+        log_messages,
+        pre_token_balances: OptionSerializer::Some(convert_token_balances(meta.pre_token_balances)),
+        post_token_balances: OptionSerializer::Some(convert_token_balances(
+            meta.post_token_balances,
+        )),
+        rewards: OptionSerializer::None,
+        loaded_addresses: OptionSerializer::Some(UiLoadedAddresses {
+            writable: meta.loaded_writable_addresses.iter().map(encode_bs58).collect(),
+            readonly: meta.loaded_readonly_addresses.iter().map(encode_bs58).collect(),
+        }),
+        return_data: OptionSerializer::Skip,
+        compute_units_consumed: meta.compute_units_consumed.into(),
+    })
+}
+
+fn convert_token_balances(balances: Vec<proto::TokenBalance>) -> Vec<UiTransactionTokenBalance> {
+    balances
+        .into_iter()
+        .map(|balance| {
+            let amount = balance.ui_token_amount.unwrap_or_default();
+            UiTransactionTokenBalance {
+                account_index: balance.account_index as u8,
+                mint: balance.mint,
+                ui_token_amount: UiTokenAmount {
+                    ui_amount: Some(amount.ui_amount),
+                    decimals: amount.decimals as u8,
+                    amount: amount.amount,
+                    ui_amount_string: amount.ui_amount_string,
+                },
+                owner: non_empty(balance.owner),
+                program_id: non_empty(balance.program_id),
+            }
+        })
+        .collect()
+}
+
+fn non_empty(value: String) -> OptionSerializer<String> {
+    if value.is_empty() {
+        OptionSerializer::Skip
+    } else {
+        OptionSerializer::Some(value)
+    }
+}
+
+fn encode_bs58(bytes: impl AsRef<[u8]>) -> String {
+    bs58::encode(bytes).into_string()
+}
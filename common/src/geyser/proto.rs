@@ -0,0 +1,285 @@
+// Hand-written prost types for the subset of the Yellowstone `geyser.proto` and
+// `solana-storage.proto` (ConfirmedBlock) schemas we consume. Field tags must match
+// upstream; anything not listed here is skipped by prost when decoding.
+
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
+#[repr(i32)]
+pub enum CommitmentLevel {
+    Processed = 0,
+    Confirmed = 1,
+    Finalized = 2,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeRequest {
+    #[prost(map = "string, message", tag = "3")]
+    pub transactions: HashMap<String, SubscribeRequestFilterTransactions>,
+    #[prost(map = "string, message", tag = "4")]
+    pub blocks: HashMap<String, SubscribeRequestFilterBlocks>,
+    #[prost(enumeration = "CommitmentLevel", optional, tag = "6")]
+    pub commitment: Option<i32>,
+    #[prost(message, optional, tag = "9")]
+    pub ping: Option<SubscribeRequestPing>,
+    #[prost(uint64, optional, tag = "11")]
+    pub from_slot: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeRequestFilterTransactions {
+    #[prost(bool, optional, tag = "1")]
+    pub vote: Option<bool>,
+    #[prost(bool, optional, tag = "2")]
+    pub failed: Option<bool>,
+    #[prost(string, repeated, tag = "3")]
+    pub account_include: Vec<String>,
+    #[prost(string, repeated, tag = "4")]
+    pub account_exclude: Vec<String>,
+    #[prost(string, optional, tag = "5")]
+    pub signature: Option<String>,
+    #[prost(string, repeated, tag = "6")]
+    pub account_required: Vec<String>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeRequestFilterBlocks {
+    #[prost(string, repeated, tag = "1")]
+    pub account_include: Vec<String>,
+    #[prost(bool, optional, tag = "2")]
+    pub include_transactions: Option<bool>,
+    #[prost(bool, optional, tag = "3")]
+    pub include_accounts: Option<bool>,
+    #[prost(bool, optional, tag = "4")]
+    pub include_entries: Option<bool>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeRequestPing {
+    #[prost(int32, tag = "1")]
+    pub id: i32,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdate {
+    #[prost(string, repeated, tag = "1")]
+    pub filters: Vec<String>,
+    #[prost(oneof = "subscribe_update::UpdateOneof", tags = "4, 5, 6, 9")]
+    pub update_oneof: Option<subscribe_update::UpdateOneof>,
+}
+
+pub mod subscribe_update {
+    #[allow(clippy::large_enum_variant)]
+    #[derive(Clone, PartialEq, ::prost::Oneof)]
+    pub enum UpdateOneof {
+        #[prost(message, tag = "4")]
+        Transaction(super::SubscribeUpdateTransaction),
+        #[prost(message, tag = "5")]
+        Block(super::SubscribeUpdateBlock),
+        #[prost(message, tag = "6")]
+        Ping(super::SubscribeUpdatePing),
+        #[prost(message, tag = "9")]
+        Pong(super::SubscribeUpdatePong),
+    }
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdateTransaction {
+    #[prost(message, optional, tag = "1")]
+    pub transaction: Option<SubscribeUpdateTransactionInfo>,
+    #[prost(uint64, tag = "2")]
+    pub slot: u64,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdateTransactionInfo {
+    #[prost(bytes = "vec", tag = "1")]
+    pub signature: Vec<u8>,
+    #[prost(bool, tag = "2")]
+    pub is_vote: bool,
+    #[prost(message, optional, tag = "3")]
+    pub transaction: Option<Transaction>,
+    #[prost(message, optional, tag = "4")]
+    pub meta: Option<TransactionStatusMeta>,
+    #[prost(uint64, tag = "5")]
+    pub index: u64,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdateBlock {
+    #[prost(uint64, tag = "1")]
+    pub slot: u64,
+    #[prost(string, tag = "2")]
+    pub blockhash: String,
+    #[prost(message, optional, tag = "4")]
+    pub block_time: Option<UnixTimestamp>,
+    #[prost(message, optional, tag = "5")]
+    pub block_height: Option<BlockHeight>,
+    #[prost(message, repeated, tag = "6")]
+    pub transactions: Vec<SubscribeUpdateTransactionInfo>,
+    #[prost(uint64, tag = "7")]
+    pub parent_slot: u64,
+    #[prost(string, tag = "8")]
+    pub parent_blockhash: String,
+    #[prost(uint64, tag = "9")]
+    pub executed_transaction_count: u64,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdatePing {}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct SubscribeUpdatePong {
+    #[prost(int32, tag = "1")]
+    pub id: i32,
+}
+
+// solana.storage.ConfirmedBlock
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Transaction {
+    #[prost(bytes = "vec", repeated, tag = "1")]
+    pub signatures: Vec<Vec<u8>>,
+    #[prost(message, optional, tag = "2")]
+    pub message: Option<Message>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct Message {
+    #[prost(message, optional, tag = "1")]
+    pub header: Option<MessageHeader>,
+    #[prost(bytes = "vec", repeated, tag = "2")]
+    pub account_keys: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub recent_blockhash: Vec<u8>,
+    #[prost(message, repeated, tag = "4")]
+    pub instructions: Vec<CompiledInstruction>,
+    #[prost(bool, tag = "5")]
+    pub versioned: bool,
+    #[prost(message, repeated, tag = "6")]
+    pub address_table_lookups: Vec<MessageAddressTableLookup>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageHeader {
+    #[prost(uint32, tag = "1")]
+    pub num_required_signatures: u32,
+    #[prost(uint32, tag = "2")]
+    pub num_readonly_signed_accounts: u32,
+    #[prost(uint32, tag = "3")]
+    pub num_readonly_unsigned_accounts: u32,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct MessageAddressTableLookup {
+    #[prost(bytes = "vec", tag = "1")]
+    pub account_key: Vec<u8>,
+    #[prost(bytes = "vec", tag = "2")]
+    pub writable_indexes: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub readonly_indexes: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TransactionStatusMeta {
+    #[prost(message, optional, tag = "1")]
+    pub err: Option<TransactionError>,
+    #[prost(uint64, tag = "2")]
+    pub fee: u64,
+    #[prost(uint64, repeated, tag = "3")]
+    pub pre_balances: Vec<u64>,
+    #[prost(uint64, repeated, tag = "4")]
+    pub post_balances: Vec<u64>,
+    #[prost(message, repeated, tag = "5")]
+    pub inner_instructions: Vec<InnerInstructions>,
+    #[prost(string, repeated, tag = "6")]
+    pub log_messages: Vec<String>,
+    #[prost(message, repeated, tag = "7")]
+    pub pre_token_balances: Vec<TokenBalance>,
+    #[prost(message, repeated, tag = "8")]
+    pub post_token_balances: Vec<TokenBalance>,
+    #[prost(bool, tag = "10")]
+    pub inner_instructions_none: bool,
+    #[prost(bool, tag = "11")]
+    pub log_messages_none: bool,
+    #[prost(bytes = "vec", repeated, tag = "12")]
+    pub loaded_writable_addresses: Vec<Vec<u8>>,
+    #[prost(bytes = "vec", repeated, tag = "13")]
+    pub loaded_readonly_addresses: Vec<Vec<u8>>,
+    #[prost(uint64, optional, tag = "16")]
+    pub compute_units_consumed: Option<u64>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TransactionError {
+    #[prost(bytes = "vec", tag = "1")]
+    pub err: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct InnerInstructions {
+    #[prost(uint32, tag = "1")]
+    pub index: u32,
+    #[prost(message, repeated, tag = "2")]
+    pub instructions: Vec<InnerInstruction>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct InnerInstruction {
+    #[prost(uint32, tag = "1")]
+    pub program_id_index: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub accounts: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+    #[prost(uint32, optional, tag = "4")]
+    pub stack_height: Option<u32>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct CompiledInstruction {
+    #[prost(uint32, tag = "1")]
+    pub program_id_index: u32,
+    #[prost(bytes = "vec", tag = "2")]
+    pub accounts: Vec<u8>,
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct TokenBalance {
+    #[prost(uint32, tag = "1")]
+    pub account_index: u32,
+    #[prost(string, tag = "2")]
+    pub mint: String,
+    #[prost(message, optional, tag = "3")]
+    pub ui_token_amount: Option<UiTokenAmount>,
+    #[prost(string, tag = "4")]
+    pub owner: String,
+    #[prost(string, tag = "5")]
+    pub program_id: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UiTokenAmount {
+    #[prost(double, tag = "1")]
+    pub ui_amount: f64,
+    #[prost(uint32, tag = "2")]
+    pub decimals: u32,
+    #[prost(string, tag = "3")]
+    pub amount: String,
+    #[prost(string, tag = "4")]
+    pub ui_amount_string: String,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct UnixTimestamp {
+    #[prost(int64, tag = "1")]
+    pub timestamp: i64,
+}
+
+#[derive(Clone, PartialEq, ::prost::Message)]
+pub struct BlockHeight {
+    #[prost(uint64, tag = "1")]
+    pub block_height: u64,
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use solana_sdk::{
+    bs58,
+    instruction::InstructionError,
+    transaction::{TransactionError, TransactionVersion},
+};
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedTransaction, UiInstruction, UiMessage,
+};
+use tokio::{net::TcpListener, time::timeout};
+use tokio_stream::{wrappers::TcpListenerStream, Stream, StreamExt};
+use tonic::{
+    body::BoxBody,
+    codec::{ProstCodec, Streaming},
+    codegen::{http, BoxFuture, Service},
+    server::{NamedService, StreamingService},
+    transport::Server,
+    Status,
+};
+
+use super::{
+    convert_block,
+    proto::{self, subscribe_update::UpdateOneof, SubscribeRequest, SubscribeUpdate},
+    GeyserClient,
+};
+use crate::block_source::{BlockSource, GeyserBlockSource};
+
+// Scripted `geyser.Geyser/Subscribe`: each connection takes the next list of updates,
+// sends it and closes the stream. Once the scripts run out the stream stays open. Every
+// request the client sends is logged, along with each connection's x-token.
+#[derive(Clone, Default)]
+struct MockGeyser {
+    scripts: Arc<Mutex<VecDeque<Vec<UpdateOneof>>>>,
+    requests: Arc<Mutex<Vec<SubscribeRequest>>>,
+    x_tokens: Arc<Mutex<Vec<Option<String>>>>,
+}
+
+type UpdateStream = Pin<Box<dyn Stream<Item = Result<SubscribeUpdate, Status>> + Send>>;
+
+impl NamedService for MockGeyser {
+    const NAME: &'static str = "geyser.Geyser";
+}
+
+impl Service<http::Request<BoxBody>> for MockGeyser {
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<BoxBody>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let mut grpc =
+                tonic::server::Grpc::new(ProstCodec::<SubscribeUpdate, SubscribeRequest>::default());
+            Ok(grpc.streaming(service, request).await)
+        })
+    }
+}
+
+impl StreamingService<SubscribeRequest> for MockGeyser {
+    type Response = SubscribeUpdate;
+    type ResponseStream = UpdateStream;
+    type Future = BoxFuture<tonic::Response<UpdateStream>, Status>;
+
+    fn call(&mut self, request: tonic::Request<Streaming<SubscribeRequest>>) -> Self::Future {
+        let requests = self.requests.clone();
+        let x_token = request
+            .metadata()
+            .get("x-token")
+            .and_then(|token| token.to_str().ok())
+            .map(str::to_string);
+        self.x_tokens.lock().unwrap().push(x_token);
+        let script = self.scripts.lock().unwrap().pop_front();
+        Box::pin(async move {
+            let mut incoming = request.into_inner();
+            tokio::spawn(async move {
+                while let Ok(Some(request)) = incoming.message().await {
+                    requests.lock().unwrap().push(request);
+                }
+            });
+            let stream: UpdateStream = match script {
+                Some(updates) => Box::pin(tokio_stream::iter(updates.into_iter().map(update))),
+                None => Box::pin(tokio_stream::pending()),
+            };
+            Ok(tonic::Response::new(stream))
+        })
+    }
+}
+
+// The stream item type is fixed by tonic.
+#[allow(clippy::result_large_err)]
+fn update(update: UpdateOneof) -> Result<SubscribeUpdate, Status> {
+    Ok(SubscribeUpdate {
+        filters: vec!["raydium".to_string()],
+        update_oneof: Some(update),
+    })
+}
+
+async fn serve(mock: MockGeyser) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(mock)
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    format!("http://{}", addr)
+}
+
+fn key(byte: u8) -> Vec<u8> {
+    vec![byte; 32]
+}
+
+fn transaction(versioned: bool, err: Option<TransactionError>) -> proto::SubscribeUpdateTransactionInfo {
+    proto::SubscribeUpdateTransactionInfo {
+        signature: vec![9; 64],
+        is_vote: false,
+        transaction: Some(proto::Transaction {
+            signatures: vec![vec![9; 64]],
+            message: Some(proto::Message {
+                header: Some(proto::MessageHeader {
+                    num_required_signatures: 1,
+                    num_readonly_signed_accounts: 0,
+                    num_readonly_unsigned_accounts: 1,
+                }),
+                account_keys: vec![key(1), key(2), key(3)],
+                recent_blockhash: key(4),
+                instructions: vec![proto::CompiledInstruction {
+                    program_id_index: 2,
+                    accounts: vec![0, 1],
+                    data: vec![9, 1, 2, 3],
+                }],
+                versioned,
+                address_table_lookups: vec![proto::MessageAddressTableLookup {
+                    account_key: key(5),
+                    writable_indexes: vec![0],
+                    readonly_indexes: vec![1, 2],
+                }],
+            }),
+        }),
+        meta: Some(proto::TransactionStatusMeta {
+            err: err.map(|err| proto::TransactionError {
+                err: bincode::serialize(&err).unwrap(),
+            }),
+            fee: 5_000,
+            pre_balances: vec![1_000_000, 0, 1],
+            post_balances: vec![995_000, 0, 1],
+            inner_instructions: vec![proto::InnerInstructions {
+                index: 0,
+                instructions: vec![proto::InnerInstruction {
+                    program_id_index: 2,
+                    accounts: vec![1, 0],
+                    data: vec![3, 4],
+                    stack_height: Some(2),
+                }],
+            }],
+            log_messages: vec!["Program log: hello".to_string()],
+            pre_token_balances: vec![],
+            post_token_balances: vec![proto::TokenBalance {
+                account_index: 1,
+                mint: "So11111111111111111111111111111111111111112".to_string(),
+                ui_token_amount: Some(proto::UiTokenAmount {
+                    ui_amount: 1.5,
+                    decimals: 9,
+                    amount: "1500000000".to_string(),
+                    ui_amount_string: "1.5".to_string(),
+                }),
+                owner: bs58::encode(key(1)).into_string(),
+                program_id: String::new(),
+            }],
+            inner_instructions_none: false,
+            log_messages_none: false,
+            loaded_writable_addresses: vec![key(6)],
+            loaded_readonly_addresses: vec![],
+            compute_units_consumed: Some(42),
+        }),
+        index: 0,
+    }
+}
+
+fn block(slot: u64) -> proto::SubscribeUpdateBlock {
+    proto::SubscribeUpdateBlock {
+        slot,
+        blockhash: format!("hash-{}", slot),
+        block_time: Some(proto::UnixTimestamp {
+            timestamp: 1_700_000_000,
+        }),
+        block_height: Some(proto::BlockHeight { block_height: slot - 1 }),
+        transactions: vec![transaction(false, None)],
+        parent_slot: slot - 1,
+        parent_blockhash: format!("hash-{}", slot - 1),
+        executed_transaction_count: 1,
+    }
+}
+
+#[test]
+fn converts_block_with_meta_and_inner_instructions() {
+    let mut update = block(10);
+    update.transactions.push(transaction(
+        true,
+        Some(TransactionError::InstructionError(0, InstructionError::Custom(1))),
+    ));
+    let block = convert_block(update).unwrap();
+
+    assert_eq!(block.blockhash, "hash-10");
+    assert_eq!(block.previous_blockhash, "hash-9");
+    assert_eq!(block.parent_slot, 9);
+    assert_eq!(block.block_time, Some(1_700_000_000));
+    assert_eq!(block.block_height, Some(9));
+    assert_eq!(block.transactions.len(), 2);
+
+    let legacy = &block.transactions[0];
+    assert_eq!(legacy.version, Some(TransactionVersion::LEGACY));
+    let EncodedTransaction::Json(tx) = &legacy.transaction else {
+        panic!("expected a JSON transaction");
+    };
+    assert_eq!(tx.signatures, vec![bs58::encode([9u8; 64]).into_string()]);
+    let UiMessage::Raw(message) = &tx.message else {
+        panic!("expected a raw message");
+    };
+    assert_eq!(message.header.num_required_signatures, 1);
+    assert_eq!(message.header.num_readonly_unsigned_accounts, 1);
+    assert_eq!(message.account_keys[2], bs58::encode(key(3)).into_string());
+    assert_eq!(message.recent_blockhash, bs58::encode(key(4)).into_string());
+    assert_eq!(message.instructions[0].program_id_index, 2);
+    assert_eq!(message.instructions[0].accounts, vec![0, 1]);
+    assert_eq!(message.instructions[0].data, bs58::encode([9, 1, 2, 3]).into_string());
+    assert!(message.address_table_lookups.is_none());
+
+    let meta = legacy.meta.as_ref().unwrap();
+    assert!(meta.err.is_none() && meta.status.is_ok());
+    assert_eq!(meta.fee, 5_000);
+    assert_eq!(meta.pre_balances, vec![1_000_000, 0, 1]);
+    assert_eq!(meta.post_balances, vec![995_000, 0, 1]);
+    let OptionSerializer::Some(inner) = &meta.inner_instructions else {
+        panic!("expected inner instructions");
+    };
+    assert_eq!(inner[0].index, 0);
+    let UiInstruction::Compiled(ix) = &inner[0].instructions[0] else {
+        panic!("expected a compiled inner instruction");
+    };
+    assert_eq!((ix.program_id_index, ix.accounts.clone()), (2, vec![1, 0]));
+    assert_eq!(ix.data, bs58::encode([3, 4]).into_string());
+    assert_eq!(ix.stack_height, Some(2));
+    assert_eq!(
+        meta.log_messages,
+        OptionSerializer::Some(vec!["Program log: hello".to_string()])
+    );
+    let OptionSerializer::Some(balances) = &meta.post_token_balances else {
+        panic!("expected post token balances");
+    };
+    assert_eq!(balances[0].account_index, 1);
+    assert_eq!(balances[0].ui_token_amount.amount, "1500000000");
+    assert_eq!(balances[0].ui_token_amount.decimals, 9);
+    assert_eq!(balances[0].owner, OptionSerializer::Some(bs58::encode(key(1)).into_string()));
+    assert_eq!(balances[0].program_id, OptionSerializer::Skip);
+    let OptionSerializer::Some(loaded) = &meta.loaded_addresses else {
+        panic!("expected loaded addresses");
+    };
+    assert_eq!(loaded.writable, vec![bs58::encode(key(6)).into_string()]);
+    assert_eq!(meta.compute_units_consumed, OptionSerializer::Some(42));
+
+    let versioned = &block.transactions[1];
+    assert_eq!(versioned.version, Some(TransactionVersion::Number(0)));
+    let EncodedTransaction::Json(tx) = &versioned.transaction else {
+        panic!("expected a JSON transaction");
+    };
+    let UiMessage::Raw(message) = &tx.message else {
+        panic!("expected a raw message");
+    };
+    let lookups = message.address_table_lookups.as_ref().unwrap();
+    assert_eq!(lookups[0].account_key, bs58::encode(key(5)).into_string());
+    assert_eq!(lookups[0].readonly_indexes, vec![1, 2]);
+    let meta = versioned.meta.as_ref().unwrap();
+    let expected = TransactionError::InstructionError(0, InstructionError::Custom(1));
+    assert_eq!(meta.err, Some(expected.clone()));
+    assert_eq!(meta.status, Err(expected));
+}
+
+#[tokio::test]
+async fn subscribes_to_blocks_and_answers_pings() {
+    let mock = MockGeyser::default();
+    mock.scripts.lock().unwrap().push_back(vec![
+        UpdateOneof::Ping(proto::SubscribeUpdatePing {}),
+        UpdateOneof::Pong(proto::SubscribeUpdatePong { id: 1 }),
+        UpdateOneof::Block(block(10)),
+    ]);
+    let endpoint = serve(mock.clone()).await;
+
+    let client = GeyserClient::new(&endpoint).x_token(Some("secret".to_string()));
+    let blocks = client
+        .subscribe_blocks(vec!["program".to_string()], Some(7))
+        .await
+        .unwrap();
+    let blocks: Vec<_> = timeout(Duration::from_secs(5), blocks.collect())
+        .await
+        .unwrap();
+
+    // Pings and pongs are handled by the client; only the block comes through.
+    assert_eq!(blocks.len(), 1);
+    let (slot, block) = blocks.into_iter().next().unwrap().unwrap();
+    assert_eq!(slot, 10);
+    assert_eq!(block.transactions.len(), 1);
+
+    assert_eq!(*mock.x_tokens.lock().unwrap(), vec![Some("secret".to_string())]);
+    // The pong goes back over the request stream, which the server reads on its own.
+    let requests = timeout(Duration::from_secs(5), async {
+        loop {
+            let requests = mock.requests.lock().unwrap().clone();
+            if requests.len() == 2 {
+                return requests;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let filter = &requests[0].blocks["raydium"];
+    assert_eq!(filter.account_include, vec!["program".to_string()]);
+    assert_eq!(filter.include_transactions, Some(true));
+    assert_eq!(requests[0].from_slot, Some(7));
+    assert_eq!(requests[0].commitment, Some(proto::CommitmentLevel::Confirmed as i32));
+    assert!(requests[1].ping.is_some());
+}
+
+#[tokio::test]
+async fn reconnects_from_the_slot_after_the_last_block() {
+    let mock = MockGeyser::default();
+    mock.scripts.lock().unwrap().extend([
+        vec![UpdateOneof::Block(block(10)), UpdateOneof::Block(block(11))],
+        vec![UpdateOneof::Block(block(12))],
+    ]);
+    let endpoint = serve(mock.clone()).await;
+
+    let source = GeyserBlockSource::new(GeyserClient::new(&endpoint));
+    let slots: Vec<u64> = timeout(
+        Duration::from_secs(10),
+        source.follow(Some(5)).take(3).map(|block| block.unwrap().0).collect(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(slots, vec![10, 11, 12]);
+
+    let from_slots: Vec<_> = mock
+        .requests
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|request| request.from_slot)
+        .collect();
+    assert_eq!(from_slots, vec![Some(5), Some(12)]);
+}
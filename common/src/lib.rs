@@ -1,7 +1,22 @@
+pub mod block_archive;
 pub mod block_processor;
+pub mod block_source;
+pub mod checkpoint;
+pub mod config;
+pub mod decode_error;
+pub mod geyser;
 pub mod global;
+pub mod listener;
+pub mod logging;
+pub mod metrics;
 pub mod models;
+pub mod old_faithful;
+pub mod planner;
+pub mod reconcile;
+pub mod rate_control;
 pub mod rpc_client;
+pub mod rpc_pool;
+pub mod shutdown;
 pub mod trade_parser;
 pub mod tx_processor;
 pub mod utils;
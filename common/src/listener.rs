@@ -0,0 +1,204 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use serde::Deserialize;
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use tokio::time::sleep;
+use tokio_stream::{Stream, StreamExt};
+use tracing::{info, warn};
+
+use crate::{
+    block_processor::decode_block,
+    models::{LiquidityEvent, PoolCreated, TradeData},
+    rpc_client::{fetch_block_with_client, FetchError},
+};
+
+const MIN_RETRY_DELAY: Duration = Duration::from_millis(200);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Narrows the trades yielded by a `TradeListener`, or written by the indexer when set
+/// in the config's `[filters]`. Empty sets match everything.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TradeFilter {
+    pub pools: HashSet<String>,
+    pub mints: HashSet<String>,
+    pub instruction_types: HashSet<String>,
+}
+
+impl TradeFilter {
+    pub fn pool(mut self, pool: &str) -> Self {
+        self.pools.insert(pool.to_string());
+        self
+    }
+
+    pub fn mint(mut self, mint: &str) -> Self {
+        self.mints.insert(mint.to_string());
+        self
+    }
+
+    pub fn instruction_type(mut self, instruction_type: &str) -> Self {
+        self.instruction_types.insert(instruction_type.to_string());
+        self
+    }
+
+    pub fn matches(&self, trade: &TradeData) -> bool {
+        self.matches_fields(
+            &trade.pool_address,
+            &trade.base_mint,
+            &trade.quote_mint,
+            &trade.instruction_type,
+        )
+    }
+
+    /// Liquidity events are matched like trades, with the event type standing in for
+    /// the instruction type.
+    pub fn matches_liquidity(&self, event: &LiquidityEvent) -> bool {
+        self.matches_fields(
+            &event.pool_address,
+            &event.base_mint,
+            &event.quote_mint,
+            &event.event_type,
+        )
+    }
+
+    /// New pools are matched as the `Initialize2` instruction that created them.
+    pub fn matches_pool(&self, pool: &PoolCreated) -> bool {
+        self.matches_fields(&pool.pool_address, &pool.base_mint, &pool.quote_mint, "Initialize2")
+    }
+
+    fn matches_fields(&self, pool: &str, base_mint: &str, quote_mint: &str, kind: &str) -> bool {
+        (self.pools.is_empty() || self.pools.contains(pool))
+            && (self.mints.is_empty()
+                || self.mints.contains(base_mint)
+                || self.mints.contains(quote_mint))
+            && (self.instruction_types.is_empty() || self.instruction_types.contains(kind))
+    }
+}
+
+/// Follows the chain from a start slot and yields decoded trades in-process.
+pub struct TradeListener {
+    rpc_client: Arc<RpcClient>,
+    start_slot: Option<u64>,
+    commitment: CommitmentConfig,
+    filter: TradeFilter,
+    poll_interval: Duration,
+}
+
+impl TradeListener {
+    pub fn new(rpc_url: &str) -> Self {
+        TradeListener {
+            rpc_client: Arc::new(RpcClient::new_with_commitment(
+                rpc_url.to_string(),
+                CommitmentConfig::confirmed(),
+            )),
+            start_slot: None,
+            commitment: CommitmentConfig::confirmed(),
+            filter: TradeFilter::default(),
+            poll_interval: Duration::from_millis(400),
+        }
+    }
+
+    /// First slot to decode. Defaults to the current tip at the time the stream starts.
+    pub fn start_slot(mut self, slot: u64) -> Self {
+        self.start_slot = Some(slot);
+        self
+    }
+
+    /// Commitment used for both the tip and `getBlock`. `processed` is not served by
+    /// `getBlock`, so `confirmed` or `finalized` should be used.
+    pub fn commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    pub fn filter(mut self, filter: TradeFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// How long to wait for a new tip once the listener has caught up.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Yields matching trades slot by slot. A block that fails to fetch is retried
+    /// with backoff rather than passed over, so a flaky RPC delays trades instead of
+    /// dropping them.
+    pub fn stream(&self) -> impl Stream<Item = TradeData> {
+        let rpc_client = self.rpc_client.clone();
+        let start_slot = self.start_slot;
+        let commitment = self.commitment;
+        let filter = self.filter.clone();
+        let poll_interval = self.poll_interval;
+
+        async_stream::stream! {
+            let mut next_slot = start_slot;
+            loop {
+                let tip = match rpc_client.get_slot_with_commitment(commitment).await {
+                    Ok(tip) => tip,
+                    Err(e) => {
+                        warn!("Failed to get latest slot: {}", e);
+                        sleep(poll_interval).await;
+                        continue;
+                    }
+                };
+                let mut slot = next_slot.unwrap_or(tip);
+                let mut backoff = MIN_RETRY_DELAY;
+
+                while slot <= tip {
+                    match fetch_block_with_client(&rpc_client, slot, commitment.commitment).await {
+                        Ok(block) => match block.block_time {
+                            Some(timestamp) => {
+                                // decode_block logs the transactions it could not decode.
+                                let (events, _) =
+                                    decode_block(slot, timestamp, block.transactions).await;
+                                for trade in events.trades {
+                                    if filter.matches(&trade) {
+                                        yield trade;
+                                    }
+                                }
+                            }
+                            None => warn!(slot, "Block time not found"),
+                        },
+                        Err(FetchError::Skipped(_)) => {}
+                        // Not produced or not confirmed yet; try again on the next poll.
+                        Err(FetchError::NotAvailable(_)) => break,
+                        // Pruned blocks will not come back, whoever is asked.
+                        Err(FetchError::Pruned(_)) => warn!(slot, "Block has been pruned"),
+                        // The slot's trades would be lost by moving on, so the same slot
+                        // is asked for again until the RPC answers.
+                        Err(FetchError::Transport(e)) => {
+                            warn!(slot, "Failed to fetch block, retrying in {:?}: {:#}", backoff, e);
+                            sleep(backoff).await;
+                            backoff = (backoff * 2).min(MAX_RETRY_DELAY);
+                            continue;
+                        }
+                    }
+                    backoff = MIN_RETRY_DELAY;
+                    slot += 1;
+                }
+
+                next_slot = Some(slot);
+                sleep(poll_interval).await;
+            }
+        }
+    }
+
+    /// Logs every matching trade until the stream ends.
+    pub async fn run(&mut self) -> Result<()> {
+        let mut trades = Box::pin(self.stream());
+        while let Some(trade) = trades.next().await {
+            info!(
+                slot = trade.block_slot,
+                signature = %trade.signature,
+                pool = %trade.pool_address,
+                "{:?}",
+                trade
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,16 @@
+use std::env;
+
+use tracing_subscriber::EnvFilter;
+
+/// Installs the global tracing subscriber. `RUST_LOG` filters events per module
+/// (e.g. `info,common::rpc_pool=debug`, default `info`); `LOG_FORMAT=json` writes one
+/// JSON object per event, including the fields of the spans it happened in.
+pub fn init() {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    if env::var("LOG_FORMAT").is_ok_and(|format| format.eq_ignore_ascii_case("json")) {
+        builder.json().init();
+    } else {
+        builder.init();
+    }
+}
@@ -0,0 +1,169 @@
+use std::net::SocketAddr;
+
+use anyhow::Result;
+use lazy_static::lazy_static;
+use prometheus::{
+    register_gauge, register_histogram_vec, register_int_counter, register_int_counter_vec,
+    register_int_gauge, Encoder, Gauge, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    TextEncoder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{info, warn};
+
+use crate::models::{LiquidityEvent, PoolCreated, TradeData};
+
+lazy_static! {
+    /// Slots by outcome: fetched, then done, empty, skipped or failed.
+    pub static ref SLOTS: IntCounterVec = register_int_counter_vec!(
+        "raydium_slots_total",
+        "Slots by outcome (fetched, done, empty, skipped, failed)",
+        &["status"]
+    )
+    .unwrap();
+    pub static ref FETCH_LATENCY: HistogramVec = register_histogram_vec!(
+        "raydium_fetch_latency_seconds",
+        "getBlock latency by RPC endpoint host",
+        &["endpoint"],
+        vec![0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0]
+    )
+    .unwrap();
+    pub static ref TRANSACTIONS_DECODED: IntCounter = register_int_counter!(
+        "raydium_transactions_decoded_total",
+        "Transactions decoded without error"
+    )
+    .unwrap();
+    pub static ref TRADES: IntCounterVec = register_int_counter_vec!(
+        "raydium_trades_total",
+        "Trades emitted by Raydium program and instruction type",
+        &["program", "instruction"]
+    )
+    .unwrap();
+    pub static ref LIQUIDITY_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "raydium_liquidity_events_total",
+        "Liquidity events emitted by event type",
+        &["event"]
+    )
+    .unwrap();
+    pub static ref POOLS_CREATED: IntCounterVec = register_int_counter_vec!(
+        "raydium_pools_created_total",
+        "New pools by the Raydium program that created them",
+        &["program"]
+    )
+    .unwrap();
+    pub static ref DECODE_ERRORS: IntCounterVec = register_int_counter_vec!(
+        "raydium_decode_errors_total",
+        "Transactions that failed to decode, by reason",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref WRITE_LATENCY: HistogramVec = register_histogram_vec!(
+        "raydium_write_latency_seconds",
+        "Time to write a slot's trades, by output format",
+        &["format"],
+        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap();
+    pub static ref CHAIN_HEAD: IntGauge = register_int_gauge!(
+        "raydium_chain_head_slot",
+        "Latest confirmed slot reported by the RPC"
+    )
+    .unwrap();
+    pub static ref LAST_SLOT: IntGauge = register_int_gauge!(
+        "raydium_last_processed_slot",
+        "Slot of the last processed block"
+    )
+    .unwrap();
+    pub static ref HEAD_LAG: IntGauge = register_int_gauge!(
+        "raydium_head_lag_slots",
+        "Slots between the chain head and the last processed block"
+    )
+    .unwrap();
+    pub static ref BLOCK_AGE: Gauge = register_gauge!(
+        "raydium_block_age_seconds",
+        "Seconds between the last processed block's block time and its processing"
+    )
+    .unwrap();
+}
+
+pub fn record_trades(trades: &[TradeData]) {
+    for trade in trades {
+        TRADES
+            .with_label_values(&[trade.program(), &trade.instruction_type])
+            .inc();
+    }
+}
+
+pub fn record_liquidity(events: &[LiquidityEvent]) {
+    for event in events {
+        LIQUIDITY_EVENTS.with_label_values(&[&event.event_type]).inc();
+    }
+}
+
+pub fn record_pools(pools: &[PoolCreated]) {
+    for pool in pools {
+        POOLS_CREATED.with_label_values(&[&pool.program]).inc();
+    }
+}
+
+/// Updates the head-lag gauges after a block was processed. Lag in slots needs a chain
+/// head from RPC; the block age works for every block source.
+pub fn record_processed(slot: u64, block_time: Option<i64>) {
+    LAST_SLOT.set(slot as i64);
+    let head = CHAIN_HEAD.get();
+    if head > 0 {
+        HEAD_LAG.set((head - slot as i64).max(0));
+    }
+    if let Some(block_time) = block_time {
+        let now = chrono::Utc::now().timestamp_millis() as f64 / 1000.0;
+        BLOCK_AGE.set(now - block_time as f64);
+    }
+}
+
+// Label for an RPC url: its host only, since paths and query strings often carry API keys.
+pub fn endpoint_label(url: &str) -> String {
+    reqwest::Url::parse(url)
+        .ok()
+        .and_then(|url| url.host_str().map(str::to_string))
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Serves the Prometheus text format on `GET /metrics` until the process exits.
+pub async fn serve(addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!("Serving metrics on http://{}/metrics", addr);
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(async move {
+            if let Err(e) = respond(stream).await {
+                warn!("Failed to serve metrics: {:#}", e);
+            }
+        });
+    }
+}
+
+async fn respond(mut stream: TcpStream) -> Result<()> {
+    let mut request = [0u8; 1024];
+    let len = stream.read(&mut request).await?;
+    let request = String::from_utf8_lossy(&request[..len]);
+
+    let (status, body) = if request.starts_with("GET /metrics ") {
+        let mut body = vec![];
+        TextEncoder::new().encode(&prometheus::gather(), &mut body)?;
+        ("200 OK", body)
+    } else {
+        ("404 Not Found", b"Not found\n".to_vec())
+    };
+
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        body.len()
+    );
+    stream.write_all(header.as_bytes()).await?;
+    stream.write_all(&body).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
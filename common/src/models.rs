@@ -1,6 +1,10 @@
 use borsh::{BorshDeserialize, BorshSerialize};
 use serde::{Deserialize, Serialize};
 use solana_sdk::pubkey::Pubkey;
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+
+use crate::listener::TradeFilter;
 
 #[derive(Debug)]
 pub struct TradeInstruction {
@@ -9,6 +13,13 @@ pub struct TradeInstruction {
     pub amm: String,
     pub vault_a: String,
     pub vault_b: String,
+    /// Raw amounts from the instruction data; only the pair of its instruction is set.
+    pub amount_in: u64,
+    pub minimum_amount_out: u64,
+    pub max_amount_in: u64,
+    pub amount_out: u64,
+    /// CLMM only: the price limit of the swap, 0 for none.
+    pub sqrt_price_limit_x64: Option<u128>,
 }
 
 impl Default for TradeInstruction {
@@ -19,10 +30,58 @@ impl Default for TradeInstruction {
             amm: "".to_string(),
             vault_a: "".to_string(),
             vault_b: "".to_string(),
+            amount_in: 0,
+            minimum_amount_out: 0,
+            max_amount_in: 0,
+            amount_out: 0,
+            sqrt_price_limit_x64: None,
         }
     }
 }
 
+/// A Raydium AMM v4 instruction that changes a pool's liquidity or parameters, with
+/// the pool accounts it references.
+#[derive(Debug)]
+pub struct LiquidityInstruction {
+    pub name: String,
+    pub amm: String,
+    pub lp_mint: Option<String>,
+    pub base_vault: String,
+    pub quote_vault: String,
+}
+
+/// A Raydium CLMM instruction that opens, changes or closes a position. ClosePosition
+/// references neither the pool nor its vaults, only the position NFT.
+#[derive(Debug)]
+pub struct ClmmLiquidityInstruction {
+    pub name: String,
+    pub pool: Option<String>,
+    pub position_nft_mint: Option<String>,
+    pub position_nft_account: Option<String>,
+    pub vaults: Option<(String, String)>,
+    pub tick_lower_index: Option<i32>,
+    pub tick_upper_index: Option<i32>,
+    /// Liquidity requested by the instruction, negative when it is removed.
+    pub liquidity: f64,
+}
+
+/// The accounts and arguments of an instruction that creates a Raydium pool: AMM v4
+/// `initialize2` or CPMM `initialize`. Initial amounts are raw, as signed.
+#[derive(Debug)]
+pub struct PoolInitInstruction {
+    pub program: String,
+    pub amm: String,
+    pub lp_mint: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_vault: String,
+    pub quote_vault: String,
+    pub creator: String,
+    pub open_time: u64,
+    pub init_base_amount: u64,
+    pub init_quote_amount: u64,
+}
+
 #[derive(Clone, PartialEq, Debug)]
 pub struct UiTokenAmount {
     pub ui_amount: f64,
@@ -78,6 +137,146 @@ pub struct TradeData {
     pub inner_program: String,
     pub txn_fee_lamports: u64,
     pub signer_lamports_change: i64,
+    /// Amounts the trader signed, in UI units of their side: `amount_in` and
+    /// `minimum_amount_out` for SwapBaseIn, `max_amount_in` and `amount_out` for
+    /// SwapBaseOut, 0 otherwise or when the swap moved nothing.
+    #[serde(default)]
+    pub amount_in: f64,
+    #[serde(default)]
+    pub minimum_amount_out: f64,
+    #[serde(default)]
+    pub max_amount_in: f64,
+    #[serde(default)]
+    pub amount_out: f64,
+    /// How far the limit was from the actual fill, as a fraction of the fill: for
+    /// SwapBaseIn `1 - minimum_amount_out / received`, for SwapBaseOut
+    /// `max_amount_in / paid - 1`. 1.0 on SwapBaseIn means no limit at all.
+    #[serde(default)]
+    pub slippage_tolerance: Option<f64>,
+    /// The actual fill over the limit: received / minimum_amount_out for SwapBaseIn
+    /// (at least 1), paid / max_amount_in for SwapBaseOut (at most 1). None when the
+    /// limit is 0.
+    #[serde(default)]
+    pub fill_vs_limit: Option<f64>,
+    /// CLMM only, as decimal Q64.64 strings: the price limit the trader set (0 for
+    /// none) and the pool's sqrt price after the swap, with its tick.
+    #[serde(default)]
+    pub sqrt_price_limit_x64: Option<String>,
+    #[serde(default)]
+    pub sqrt_price_x64: Option<String>,
+    #[serde(default)]
+    pub tick: Option<i32>,
+    /// Token-2022 transfer fees withheld on each side, in UI units. The base and quote
+    /// amounts are what the pool vaults received or paid; the trader's side of the
+    /// swap is that plus the fee.
+    #[serde(default)]
+    pub base_transfer_fee: f64,
+    #[serde(default)]
+    pub quote_transfer_fee: f64,
+}
+
+impl TradeData {
+    /// The Raydium program that executed the trade. `outer_program` is the program the
+    /// transaction called, which for routed swaps is the aggregator.
+    pub fn program(&self) -> &str {
+        if self.inner_program.is_empty() {
+            &self.outer_program
+        } else {
+            &self.inner_program
+        }
+    }
+}
+
+/// Liquidity added to or removed from a Raydium AMM v4 pool (Initialize2, Deposit,
+/// Withdraw, WithdrawPnl) or a change of its parameters (SetParams). Amounts are the
+/// signed changes of the pool vaults and of the LP supply: a deposit is positive on all
+/// three, a withdrawal negative.
+///
+/// Raydium CLMM positions use the same record: the LP mint is the position NFT, the LP
+/// amount the position liquidity requested and the ticks bound the position's range,
+/// when the instruction carries them.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct LiquidityEvent {
+    pub block_date: String,
+    pub block_time: i64,
+    pub block_slot: u64,
+    pub signature: String,
+    pub signer: String,
+    pub pool_address: String,
+    pub event_type: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_vault: String,
+    pub quote_vault: String,
+    pub base_amount: f64,
+    pub quote_amount: f64,
+    /// Empty for WithdrawPnl and SetParams, which don't reference the LP mint.
+    pub lp_mint: String,
+    pub lp_amount: f64,
+    pub is_inner_instruction: bool,
+    pub instruction_index: u32,
+    pub outer_program: String,
+    #[serde(default)]
+    pub tick_lower_index: Option<i32>,
+    #[serde(default)]
+    pub tick_upper_index: Option<i32>,
+    /// Token-2022 transfer fees withheld on each side, in UI units, as for trades.
+    #[serde(default)]
+    pub base_transfer_fee: f64,
+    #[serde(default)]
+    pub quote_transfer_fee: f64,
+}
+
+/// A new Raydium pool, from AMM v4 `initialize2` or CPMM `initialize`; `program` is
+/// the one that owns the pool. The initial amounts are the creator's deposit on each
+/// side in UI units; trading opens at `open_time` (unix seconds, 0 when it opens right
+/// away).
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct PoolCreated {
+    pub block_date: String,
+    pub block_time: i64,
+    pub block_slot: u64,
+    pub signature: String,
+    pub creator: String,
+    pub pool_address: String,
+    pub base_mint: String,
+    pub quote_mint: String,
+    pub base_vault: String,
+    pub quote_vault: String,
+    pub lp_mint: String,
+    pub open_time: i64,
+    pub init_base_amount: f64,
+    pub init_quote_amount: f64,
+    pub is_inner_instruction: bool,
+    pub instruction_index: u32,
+    pub outer_program: String,
+    #[serde(default)]
+    pub program: String,
+}
+
+/// Everything decoded from a transaction or block, one list per output stream.
+#[derive(Clone, Debug, Default)]
+pub struct DecodedEvents {
+    pub trades: Vec<TradeData>,
+    pub liquidity: Vec<LiquidityEvent>,
+    pub pools: Vec<PoolCreated>,
+}
+
+impl DecodedEvents {
+    pub fn extend(&mut self, other: DecodedEvents) {
+        self.trades.extend(other.trades);
+        self.liquidity.extend(other.liquidity);
+        self.pools.extend(other.pools);
+    }
+}
+
+/// A transaction that could not be decoded, as written to `<output-dir>/dead_letters`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub slot: u64,
+    pub signature: String,
+    pub reason: String,
+    pub error: String,
 }
 
 #[derive(Clone, PartialEq)]
@@ -85,11 +284,177 @@ pub struct Output {
     pub data: Vec<TradeData>,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Avro,
+    Csv,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Avro => "avro",
+            OutputFormat::Csv => "csv",
+        }
+    }
+}
+
+/// Output stream of liquidity events, a folder next to the trades' date folders.
+pub const LIQUIDITY_STREAM: &str = "liquidity";
+/// Output stream of new pools.
+pub const POOLS_STREAM: &str = "pools";
+/// ZMQ topic of trades. Liquidity events and new pools go out on the topics named
+/// after their streams, retractions on `CORRECTION_TOPIC`.
+pub const TRADES_TOPIC: &str = "trades";
+/// ZMQ topic of `CorrectionEvent`s.
+pub const CORRECTION_TOPIC: &str = "correction";
+
+/// Where process_block writes the per-slot trade files: `<dir>/<date>/<slot>.<ext>`,
+/// and which trades it keeps. Other event streams go to `<dir>/<stream>/<date>/`.
+#[derive(Clone, Debug)]
+pub struct OutputConfig {
+    pub dir: String,
+    pub format: OutputFormat,
+    pub filter: TradeFilter,
+}
+
+impl OutputConfig {
+    /// The file of `slot` in the trades stream (`None`) or in a named event stream.
+    pub fn slot_file(&self, stream: Option<&str>, date: &str, slot: u64) -> PathBuf {
+        let mut path = PathBuf::from(&self.dir);
+        if let Some(stream) = stream {
+            path.push(stream);
+        }
+        path.join(date).join(format!("{}.{}", slot, self.format.extension()))
+    }
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
 pub struct Transfer {
     pub amount: u64,
 }
 
+/// Instruction data of Raydium AMM v4 `swap_base_in` after the tag byte.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct SwapBaseInArgs {
+    pub amount_in: u64,
+    pub minimum_amount_out: u64,
+}
+
+/// Instruction data of Raydium AMM v4 `swap_base_out` after the tag byte.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct SwapBaseOutArgs {
+    pub max_amount_in: u64,
+    pub amount_out: u64,
+}
+
+/// Instruction data of Raydium CLMM `swap` and `swap_v2` after the discriminator.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct ClmmSwapArgs {
+    pub amount: u64,
+    pub other_amount_threshold: u64,
+    pub sqrt_price_limit_x64: u128,
+    pub is_base_input: bool,
+}
+
+/// Leading instruction data of the Raydium CLMM `open_position` variants; the v2
+/// variants append flags that are not needed here.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct ClmmOpenPositionArgs {
+    pub tick_lower_index: i32,
+    pub tick_upper_index: i32,
+    pub tick_array_lower_start_index: i32,
+    pub tick_array_upper_start_index: i32,
+    pub liquidity: u128,
+    pub amount_0_max: u64,
+    pub amount_1_max: u64,
+}
+
+/// Leading instruction data of Raydium CLMM `increase_liquidity` and
+/// `decrease_liquidity` (and v2). The amounts are maximums to deposit or minimums to
+/// withdraw.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct ClmmLiquidityArgs {
+    pub liquidity: u128,
+    pub amount_0: u64,
+    pub amount_1: u64,
+}
+
+/// `SwapEvent` logged by Raydium CLMM after every swap, with the pool's price after it.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone)]
+pub struct ClmmSwapEvent {
+    pub pool_state: Pubkey,
+    pub sender: Pubkey,
+    pub token_account_0: Pubkey,
+    pub token_account_1: Pubkey,
+    pub amount_0: u64,
+    pub transfer_fee_0: u64,
+    pub amount_1: u64,
+    pub transfer_fee_1: u64,
+    pub zero_for_one: bool,
+    pub sqrt_price_x64: u128,
+    pub liquidity: u128,
+    pub tick: i32,
+}
+
+/// `SwapEvent` logged by Raydium CPMM after every swap. Amounts exclude the Token-2022
+/// transfer fees, which are reported next to them.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone)]
+pub struct CpmmSwapEvent {
+    pub pool_id: Pubkey,
+    pub input_vault_before: u64,
+    pub output_vault_before: u64,
+    pub input_amount: u64,
+    pub output_amount: u64,
+    pub input_transfer_fee: u64,
+    pub output_transfer_fee: u64,
+    pub base_input: bool,
+}
+
+/// `LpChangeEvent` logged by Raydium CPMM on every deposit (`change_type` 0) and
+/// withdrawal (1).
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone)]
+pub struct CpmmLpChangeEvent {
+    pub pool_id: Pubkey,
+    pub lp_amount_before: u64,
+    pub token_0_vault_before: u64,
+    pub token_1_vault_before: u64,
+    pub token_0_amount: u64,
+    pub token_1_amount: u64,
+    pub token_0_transfer_fee: u64,
+    pub token_1_transfer_fee: u64,
+    pub change_type: u8,
+}
+
+/// Events the Raydium programs logged in one transaction, per pool in log order, so
+/// each decoded instruction can take the next event of its pool.
+#[derive(Debug, Default)]
+pub struct LoggedEvents {
+    pub clmm_swaps: HashMap<String, VecDeque<ClmmSwapEvent>>,
+    pub cpmm_swaps: HashMap<String, VecDeque<CpmmSwapEvent>>,
+    pub cpmm_lp_changes: HashMap<String, VecDeque<CpmmLpChangeEvent>>,
+}
+
+/// Instruction data of Raydium CPMM `initialize` after the discriminator.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct CpmmInitializeArgs {
+    pub init_amount_0: u64,
+    pub init_amount_1: u64,
+    pub open_time: u64,
+}
+
+/// Instruction data of Raydium AMM v4 `initialize2` after the tag byte. `pc` is the
+/// quote side and `coin` the base side.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default)]
+pub struct Initialize2Args {
+    pub nonce: u8,
+    pub open_time: u64,
+    pub init_pc_amount: u64,
+    pub init_coin_amount: u64,
+}
+
 // TODO: This works but requires 1 extra call, the sama data can be parsed
 // out of the inner intructions/instructions from Raydium
 #[derive(Debug, BorshDeserialize, BorshSerialize)]
@@ -212,11 +577,25 @@ pub struct PoolData {
     pub user_source_owner: Pubkey,
 }
 
+/// A slot's records of one stream: trades on `TRADES_TOPIC`, other streams on the
+/// topic named after them.
 #[derive(Debug, Serialize, Deserialize)]
-pub struct ZmqData {
+pub struct ZmqData<T = TradeData> {
     pub slot: u64,
     pub date: String,
-    pub data: Vec<TradeData>,
+    pub data: Vec<T>,
+}
+
+/// Published on `CORRECTION_TOPIC` when a confirmed slot turns out not to be part
+/// of the finalized chain. Consumers should drop the retracted trades; if the slot was
+/// finalized with a different block, its trades follow as a regular slot message.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CorrectionEvent {
+    pub slot: u64,
+    pub date: String,
+    pub orphaned_blockhash: String,
+    pub finalized_blockhash: Option<String>,
+    pub retracted_signatures: Vec<String>,
 }
 
 #[derive(Debug, Deserialize, Serialize)]
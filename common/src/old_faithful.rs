@@ -0,0 +1,346 @@
+// Reader for Old Faithful epoch archives: CARv1 files of DAG-CBOR nodes, one tree per
+// block (transactions and entries first, then the block that links them). The node
+// layouts follow `ledger.ipldsch` from the yellowstone-faithful repository; each node
+// is a CBOR array whose first element is its kind.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, ErrorKind, Read},
+    path::Path,
+};
+
+use anyhow::{anyhow, Context, Result};
+use ciborium::Value;
+use prost::Message as _;
+use solana_sdk::{bs58, message::VersionedMessage, transaction::VersionedTransaction};
+use solana_transaction_status::{EncodedConfirmedBlock, EncodedTransactionWithStatusMeta};
+
+use crate::geyser::{convert_transaction, proto};
+
+const KIND_TRANSACTION: u64 = 0;
+const KIND_ENTRY: u64 = 1;
+const KIND_BLOCK: u64 = 2;
+const KIND_DATA_FRAME: u64 = 6;
+// IPLD links are CBOR tag 42 around the CID bytes, prefixed with a 0x00 multibase byte.
+const CID_TAG: u64 = 42;
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+type Cid = Vec<u8>;
+
+/// Streams the blocks of a single CAR file in the order they were written, which is
+/// ascending slot order for Old Faithful epoch archives.
+pub struct CarReader<R: Read> {
+    reader: R,
+    // Nodes seen since the last block, waiting for the block that links them.
+    pending: HashMap<Cid, Value>,
+    previous_block: Option<(u64, String)>,
+}
+
+impl CarReader<BufReader<File>> {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        Self::new(BufReader::with_capacity(1 << 20, file))
+    }
+}
+
+impl<R: Read> CarReader<R> {
+    /// Reads and checks the CAR header.
+    pub fn new(mut reader: R) -> Result<Self> {
+        let header_len = read_varint(&mut reader)?.ok_or_else(|| anyhow!("Empty CAR file"))?;
+        let mut header = vec![0; header_len as usize];
+        reader.read_exact(&mut header)?;
+        let header: Value = ciborium::from_reader(header.as_slice())?;
+        let version = header
+            .as_map()
+            .and_then(|map| {
+                map.iter()
+                    .find(|(key, _)| key.as_text() == Some("version"))
+                    .and_then(|(_, value)| as_u64(value))
+            })
+            .ok_or_else(|| anyhow!("CAR header has no version"))?;
+        if version != 1 {
+            return Err(anyhow!("Unsupported CAR version {}", version));
+        }
+
+        Ok(CarReader {
+            reader,
+            pending: HashMap::new(),
+            previous_block: None,
+        })
+    }
+
+    /// The next block's slot and the block, or `None` at the end of the file. The outer
+    /// error means the file can't be read any further; the inner one only concerns
+    /// that block.
+    pub fn next_block(&mut self) -> Result<Option<(u64, Result<EncodedConfirmedBlock>)>> {
+        while let Some((cid, node)) = self.next_node()? {
+            match node_kind(&node) {
+                Some(KIND_BLOCK) => {
+                    let slot = node
+                        .as_array()
+                        .and_then(|fields| fields.get(1))
+                        .and_then(as_u64)
+                        .ok_or_else(|| anyhow!("Block node has no slot"))?;
+                    let block = self.assemble_block(slot, &node);
+                    self.pending.clear();
+                    return Ok(Some((slot, block)));
+                }
+                Some(KIND_TRANSACTION | KIND_ENTRY | KIND_DATA_FRAME) => {
+                    self.pending.insert(cid, node);
+                }
+                // Rewards, subsets and the epoch root are not needed to decode trades.
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    fn next_node(&mut self) -> Result<Option<(Cid, Value)>> {
+        let Some(section_len) = read_varint(&mut self.reader)? else {
+            return Ok(None);
+        };
+        let mut section = vec![0; section_len as usize];
+        self.reader.read_exact(&mut section)?;
+        let cid_len = cid_length(&section)?;
+        let node: Value = ciborium::from_reader(&section[cid_len..])?;
+        section.truncate(cid_len);
+        Ok(Some((section, node)))
+    }
+
+    fn assemble_block(&mut self, slot: u64, node: &Value) -> Result<EncodedConfirmedBlock> {
+        // [kind, slot, shredding, entries, meta, rewards]
+        let fields = node.as_array().ok_or_else(|| anyhow!("Block node is not an array"))?;
+        let entries = fields.get(3).map(links).unwrap_or_default();
+        // meta: [parent_slot, blocktime, block_height?]
+        let meta = fields.get(4).and_then(|meta| meta.as_array());
+        let parent_slot = meta.and_then(|meta| meta.first()).and_then(as_u64).unwrap_or(0);
+        let block_time = meta
+            .and_then(|meta| meta.get(1))
+            .and_then(as_u64)
+            .filter(|time| *time > 0)
+            .map(|time| time as i64);
+        let block_height = meta.and_then(|meta| meta.get(2)).and_then(as_u64);
+
+        let mut transactions = vec![];
+        let mut blockhash = String::new();
+        for entry_cid in entries {
+            // [kind, num_hashes, hash, transactions]
+            let entry = self
+                .pending
+                .get(&entry_cid)
+                .and_then(|entry| entry.as_array())
+                .ok_or_else(|| anyhow!("Entry missing from archive"))?;
+            if let Some(hash) = entry.get(2).and_then(|hash| hash.as_bytes()) {
+                blockhash = bs58::encode(hash).into_string();
+            }
+            for tx_cid in entry.get(3).map(links).unwrap_or_default() {
+                let tx = self
+                    .pending
+                    .get(&tx_cid)
+                    .ok_or_else(|| anyhow!("Transaction missing from archive"))?;
+                transactions.push(
+                    self.decode_transaction(tx, transactions.len())
+                        .context("Failed to decode transaction")?,
+                );
+            }
+        }
+
+        // The archive only links a block to its parent slot; the parent's hash is known
+        // when that block came just before this one in the same file.
+        let previous_blockhash = match &self.previous_block {
+            Some((previous_slot, hash)) if *previous_slot == parent_slot => hash.clone(),
+            _ => String::new(),
+        };
+        self.previous_block = Some((slot, blockhash.clone()));
+
+        Ok(EncodedConfirmedBlock {
+            previous_blockhash,
+            blockhash,
+            parent_slot,
+            transactions,
+            rewards: vec![],
+            num_partitions: None,
+            block_time,
+            block_height,
+        })
+    }
+
+    fn decode_transaction(
+        &self,
+        node: &Value,
+        index: usize,
+    ) -> Result<EncodedTransactionWithStatusMeta> {
+        // [kind, data, metadata, slot, index?]
+        let fields = node
+            .as_array()
+            .ok_or_else(|| anyhow!("Transaction node is not an array"))?;
+        let data = self.frame_bytes(fields.get(1))?;
+        let metadata = self.frame_bytes(fields.get(2))?;
+
+        let transaction: VersionedTransaction = bincode::deserialize(&data)?;
+        let metadata = if metadata.starts_with(&ZSTD_MAGIC) {
+            zstd::decode_all(metadata.as_slice())?
+        } else {
+            metadata
+        };
+        // Epochs before protobuf metadata was introduced store a bincode struct instead,
+        // which is not supported.
+        let meta = proto::TransactionStatusMeta::decode(metadata.as_slice())
+            .context("Transaction metadata is not protobuf")?;
+
+        convert_transaction(proto::SubscribeUpdateTransactionInfo {
+            signature: transaction
+                .signatures
+                .first()
+                .map(|signature| signature.as_ref().to_vec())
+                .unwrap_or_default(),
+            is_vote: false,
+            transaction: Some(to_proto_transaction(&transaction)),
+            meta: Some(meta),
+            index: index as u64,
+        })
+    }
+
+    // Concatenates a data frame with the frames it links to through `next`.
+    fn frame_bytes(&self, frame: Option<&Value>) -> Result<Vec<u8>> {
+        let Some(frame) = frame else {
+            return Ok(vec![]);
+        };
+        let mut bytes = vec![];
+        append_frame(frame, &mut bytes);
+        // [kind, hash?, index?, total?, data, next?]
+        let next = frame
+            .as_array()
+            .and_then(|fields| fields.get(5))
+            .map(links)
+            .unwrap_or_default();
+        for cid in next {
+            let part = self
+                .pending
+                .get(&cid)
+                .ok_or_else(|| anyhow!("Data frame continuation missing from archive"))?;
+            bytes.extend(self.frame_bytes(Some(part))?);
+        }
+        Ok(bytes)
+    }
+}
+
+fn append_frame(frame: &Value, bytes: &mut Vec<u8>) {
+    if let Some(data) = frame
+        .as_array()
+        .and_then(|fields| fields.get(4))
+        .and_then(|data| data.as_bytes())
+    {
+        bytes.extend_from_slice(data);
+    }
+}
+
+fn to_proto_transaction(transaction: &VersionedTransaction) -> proto::Transaction {
+    let message = &transaction.message;
+    let header = message.header();
+    proto::Transaction {
+        signatures: transaction
+            .signatures
+            .iter()
+            .map(|signature| signature.as_ref().to_vec())
+            .collect(),
+        message: Some(proto::Message {
+            header: Some(proto::MessageHeader {
+                num_required_signatures: header.num_required_signatures as u32,
+                num_readonly_signed_accounts: header.num_readonly_signed_accounts as u32,
+                num_readonly_unsigned_accounts: header.num_readonly_unsigned_accounts as u32,
+            }),
+            account_keys: message
+                .static_account_keys()
+                .iter()
+                .map(|key| key.to_bytes().to_vec())
+                .collect(),
+            recent_blockhash: message.recent_blockhash().to_bytes().to_vec(),
+            instructions: message
+                .instructions()
+                .iter()
+                .map(|ix| proto::CompiledInstruction {
+                    program_id_index: ix.program_id_index as u32,
+                    accounts: ix.accounts.clone(),
+                    data: ix.data.clone(),
+                })
+                .collect(),
+            versioned: matches!(message, VersionedMessage::V0(_)),
+            address_table_lookups: message
+                .address_table_lookups()
+                .unwrap_or_default()
+                .iter()
+                .map(|lookup| proto::MessageAddressTableLookup {
+                    account_key: lookup.account_key.to_bytes().to_vec(),
+                    writable_indexes: lookup.writable_indexes.clone(),
+                    readonly_indexes: lookup.readonly_indexes.clone(),
+                })
+                .collect(),
+        }),
+    }
+}
+
+fn node_kind(node: &Value) -> Option<u64> {
+    node.as_array()?.first().and_then(as_u64)
+}
+
+fn as_u64(value: &Value) -> Option<u64> {
+    value.as_integer().and_then(|integer| u64::try_from(integer).ok())
+}
+
+// CIDs of a list of IPLD links.
+fn links(value: &Value) -> Vec<Cid> {
+    value
+        .as_array()
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|item| match item {
+                    Value::Tag(CID_TAG, inner) => inner
+                        .as_bytes()
+                        .and_then(|bytes| bytes.split_first())
+                        .map(|(_, cid)| cid.to_vec()),
+                    _ => None,
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Unsigned LEB128, as used for CAR section lengths and inside CIDs. `None` at a clean
+// end of input.
+fn read_varint(reader: &mut impl Read) -> Result<Option<u64>> {
+    let mut value = 0u64;
+    let mut byte = [0u8];
+    for shift in (0..64).step_by(7) {
+        match reader.read_exact(&mut byte) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof && shift == 0 => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err(anyhow!("Varint too long"))
+}
+
+// Length of the CID at the start of a CAR section.
+fn cid_length(section: &[u8]) -> Result<usize> {
+    // CIDv0 is a bare sha2-256 multihash.
+    if section.starts_with(&[0x12, 0x20]) {
+        return Ok(34);
+    }
+    let mut cursor = section;
+    let _version = read_varint(&mut cursor)?;
+    let _codec = read_varint(&mut cursor)?;
+    let _hash_code = read_varint(&mut cursor)?;
+    let digest_len = read_varint(&mut cursor)?.ok_or_else(|| anyhow!("Truncated CID"))?;
+    let len = section.len() - cursor.len() + digest_len as usize;
+    if len > section.len() {
+        return Err(anyhow!("Truncated CID"));
+    }
+    Ok(len)
+}
@@ -0,0 +1,178 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File},
+    io::{BufRead, BufReader, BufWriter, Write},
+    ops::Range,
+    path::Path,
+};
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    block_processor::load_skipped_slots,
+    checkpoint::Checkpoint,
+    models::{LIQUIDITY_STREAM, POOLS_STREAM},
+};
+
+/// A slot range for one indexer run, `end_slot` exclusive like `--end-slot`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FetchJob {
+    pub start_slot: u64,
+    pub end_slot: u64,
+}
+
+/// Slots with an output file under `<dir>/<YYYY-MM-DD>/<slot>.{avro,csv}`, or under the
+/// same layout in the liquidity and pools stream folders, limited to the date folders
+/// within `dates` (inclusive) when given. A slot that only produced pool or liquidity
+/// records has no trades file but is still archived.
+pub fn archived_slots(dir: &Path, dates: Option<(NaiveDate, NaiveDate)>) -> Result<BTreeSet<u64>> {
+    let mut slots = BTreeSet::new();
+    scan_date_folders(dir, dates, &mut slots)?;
+    for stream in [LIQUIDITY_STREAM, POOLS_STREAM] {
+        let stream_dir = dir.join(stream);
+        if stream_dir.is_dir() {
+            scan_date_folders(&stream_dir, dates, &mut slots)?;
+        }
+    }
+    Ok(slots)
+}
+
+fn scan_date_folders(
+    dir: &Path,
+    dates: Option<(NaiveDate, NaiveDate)>,
+    slots: &mut BTreeSet<u64>,
+) -> Result<()> {
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {}", dir.display()))? {
+        let path = entry?.path();
+        let date = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| NaiveDate::parse_from_str(name, "%Y-%m-%d").ok());
+        let Some(date) = date else {
+            continue;
+        };
+        if dates.is_some_and(|(from, to)| date < from || date > to) || !path.is_dir() {
+            continue;
+        }
+
+        for file in fs::read_dir(&path)? {
+            let file = file?.path();
+            if !file
+                .extension()
+                .is_some_and(|ext| ext == "avro" || ext == "csv")
+            {
+                continue;
+            }
+            if let Some(slot) = file
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok())
+            {
+                slots.insert(slot);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Every slot accounted for: the `archived` ones, plus slots the checkpoint or the
+/// skipped slot list mark as finished without writing a file (empty and skipped blocks).
+pub fn covered_slots(
+    dir: &Path,
+    checkpoint: &Checkpoint,
+    archived: &BTreeSet<u64>,
+) -> Result<BTreeSet<u64>> {
+    let mut covered = archived.clone();
+    covered.extend(checkpoint.finished_slots());
+    covered.extend(load_skipped_slots(dir)?);
+    Ok(covered)
+}
+
+/// Maximal runs of slots in `range` that are not in `covered`.
+pub fn find_gaps(covered: &BTreeSet<u64>, range: Range<u64>) -> Vec<Range<u64>> {
+    let mut gaps = vec![];
+    let mut next = range.start;
+    for &slot in covered.range(range.clone()) {
+        if slot > next {
+            gaps.push(next..slot);
+        }
+        next = slot + 1;
+    }
+    if next < range.end {
+        gaps.push(next..range.end);
+    }
+    gaps
+}
+
+/// Splits gaps into jobs of at most `max_slots` slots each.
+pub fn plan_jobs(gaps: &[Range<u64>], max_slots: u64) -> Vec<FetchJob> {
+    let max_slots = max_slots.max(1);
+    let mut jobs = vec![];
+    for gap in gaps {
+        let mut start_slot = gap.start;
+        while start_slot < gap.end {
+            let end_slot = (start_slot + max_slots).min(gap.end);
+            jobs.push(FetchJob {
+                start_slot,
+                end_slot,
+            });
+            start_slot = end_slot;
+        }
+    }
+    jobs
+}
+
+/// Writes one JSON job per line.
+pub fn write_jobs(path: &Path, jobs: &[FetchJob]) -> Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    for job in jobs {
+        serde_json::to_writer(&mut writer, job)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn read_jobs(path: &Path) -> Result<Vec<FetchJob>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let mut jobs = vec![];
+    for line in BufReader::new(file).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        jobs.push(serde_json::from_str(&line)?);
+    }
+    Ok(jobs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archived_slots_include_stream_folders() {
+        let dir = std::env::temp_dir().join(format!("raydium-planner-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        for file in [
+            "2025-01-30/10.avro",
+            "liquidity/2025-01-30/11.csv",
+            "pools/2025-01-30/12.avro",
+            "pools/2025-01-31/13.avro",
+            "2025-01-30/14.avro.tmp",
+        ] {
+            let path = dir.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            File::create(path).unwrap();
+        }
+
+        let day = NaiveDate::from_ymd_opt(2025, 1, 30).unwrap();
+        let slots = archived_slots(&dir, Some((day, day))).unwrap();
+        assert_eq!(slots, BTreeSet::from([10, 11, 12]));
+        assert_eq!(find_gaps(&slots, 9..14), vec![9..10, 13..14]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -6,6 +6,7 @@ use std::{
 };
 use csv::ReaderBuilder;
 use crate::models::{KlineData, KlineRecord};
+use tracing::info;
 use zip::ZipArchive;
 
 pub async fn fetch_klines_for_date(symbol: &str, date: NaiveDate) -> Result<Vec<KlineData>> {
@@ -25,7 +26,7 @@ pub async fn fetch_klines_for_date(symbol: &str, date: NaiveDate) -> Result<Vec<
         date_str = date_str
     );
 
-    println!("Downloading {} klines data from {}", symbol, url);
+    info!(symbol, %url, "Downloading klines");
     let response = reqwest::get(&url).await?;
     if !response.status().is_success() {
         return Err(anyhow::anyhow!("HTTP error: {}", response.status()));
@@ -56,6 +57,6 @@ pub fn store_klines(symbol: &str, date: &str, klines: &Vec<KlineData>) -> Result
     let encoded: Vec<u8> = bincode::serialize(klines)?;
     let mut file = File::create(filename.clone())?;
     file.write_all(&encoded)?;
-    println!("Storing {} klines for {} to {}", klines.len(), symbol, filename);
+    info!(symbol, klines = klines.len(), path = %filename, "Storing klines");
     Ok(())
 }
\ No newline at end of file
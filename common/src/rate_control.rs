@@ -0,0 +1,305 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::Error;
+use solana_client::client_error::{ClientError, ClientErrorKind};
+use solana_client::rpc_request::RpcError;
+use solana_transaction_status::EncodedConfirmedBlock;
+use tokio::sync::Notify;
+use tokio_retry::{
+    strategy::{jitter, ExponentialBackoff},
+    RetryIf,
+};
+use tracing::{debug_span, warn, Instrument};
+
+use crate::rpc_client::{fetch_block_with_version, FetchError};
+
+// Concurrent requests that overlap with the one that got throttled would all report the
+// same overload, so the limit is halved at most once per window.
+const DECREASE_WINDOW: Duration = Duration::from_secs(1);
+const MAX_RETRIES: usize = 6;
+
+struct LimitState {
+    limit: f64,
+    in_flight: usize,
+    last_decrease: Option<Instant>,
+}
+
+/// AIMD concurrency limit for RPC requests: each success raises the limit by
+/// `1 / limit` (about one extra request per round trip), and each 429 or timeout
+/// halves it.
+pub struct RateController {
+    state: Mutex<LimitState>,
+    released: Notify,
+    min: usize,
+    max: usize,
+}
+
+/// A slot in the concurrency limit, given back when dropped.
+pub struct Permit {
+    controller: Arc<RateController>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.controller.state.lock().unwrap().in_flight -= 1;
+        self.controller.released.notify_waiters();
+    }
+}
+
+impl RateController {
+    /// Starts at `max` concurrent requests and never drops below `min`.
+    pub fn new(min: usize, max: usize) -> Arc<Self> {
+        let min = min.max(1);
+        Arc::new(RateController {
+            state: Mutex::new(LimitState {
+                limit: max.max(min) as f64,
+                in_flight: 0,
+                last_decrease: None,
+            }),
+            released: Notify::new(),
+            min,
+            max: max.max(min),
+        })
+    }
+
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit as usize
+    }
+
+    /// Waits until fewer than `limit` requests are in flight.
+    pub async fn acquire(self: &Arc<Self>) -> Permit {
+        loop {
+            let released = self.released.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.in_flight < state.limit as usize {
+                    state.in_flight += 1;
+                    return Permit {
+                        controller: self.clone(),
+                    };
+                }
+            }
+            released.await;
+        }
+    }
+
+    fn on_success(&self) {
+        let mut state = self.state.lock().unwrap();
+        let previous = state.limit as usize;
+        state.limit = (state.limit + 1.0 / state.limit).min(self.max as f64);
+        if state.limit as usize > previous {
+            drop(state);
+            self.released.notify_waiters();
+        }
+    }
+
+    fn on_throttled(&self) {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        if state
+            .last_decrease
+            .is_some_and(|last| now.duration_since(last) < DECREASE_WINDOW)
+        {
+            return;
+        }
+        state.limit = (state.limit / 2.0).max(self.min as f64);
+        state.last_decrease = Some(now);
+        warn!(limit = state.limit as usize, "RPC throttled, lowering concurrency");
+    }
+
+    /// Fetches `slot` within the concurrency limit, retrying transient failures with
+    /// jittered exponential backoff. Skipped and pruned slots are returned straight away.
+    pub async fn fetch_block(
+        self: &Arc<Self>,
+        slot: u64,
+    ) -> Result<EncodedConfirmedBlock, FetchError> {
+        let strategy = ExponentialBackoff::from_millis(2)
+            .factor(100)
+            .max_delay(Duration::from_secs(10))
+            .map(jitter)
+            .take(MAX_RETRIES);
+
+        let mut attempt = 0;
+        RetryIf::spawn(
+            strategy,
+            || {
+                attempt += 1;
+                let span = debug_span!("fetch", slot, attempt);
+                async move {
+                    let _permit = self.acquire().await;
+                    match fetch_block_with_version(slot).await {
+                        Ok(block) => {
+                            self.on_success();
+                            Ok(block)
+                        }
+                        Err(FetchError::Transport(e)) => {
+                            if is_throttled(&e) {
+                                self.on_throttled();
+                            }
+                            warn!("Fetching slot failed, will retry: {}", e);
+                            Err(FetchError::Transport(e))
+                        }
+                        Err(e) => Err(e),
+                    }
+                }
+                .instrument(span)
+            },
+            |e: &FetchError| matches!(e, FetchError::NotAvailable(_) | FetchError::Transport(_)),
+        )
+        .await
+    }
+}
+
+/// Whether the RPC turned the request away because of load: HTTP 429, a JSON-RPC
+/// rate-limit error, or a timeout.
+pub fn is_throttled(e: &Error) -> bool {
+    if let Some(client_error) = e.downcast_ref::<ClientError>() {
+        match client_error.kind() {
+            ClientErrorKind::Reqwest(e)
+                if e.is_timeout() || e.status().is_some_and(|status| status.as_u16() == 429) =>
+            {
+                return true;
+            }
+            ClientErrorKind::RpcError(RpcError::RpcResponseError { code: 429, .. }) => {
+                return true;
+            }
+            _ => {}
+        }
+    }
+    let message = e.to_string().to_lowercase();
+    message.contains("429") || message.contains("too many requests") || message.contains("rate limit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use futures::FutureExt;
+    use solana_client::rpc_request::RpcResponseErrorData;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    // Sends one request to a local server that answers with `status`, or never answers
+    // when `status` is None, and returns the resulting reqwest error.
+    async fn http_error(status: Option<u16>) -> Error {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            match status {
+                Some(status) => {
+                    let response =
+                        format!("HTTP/1.1 {} Status\r\ncontent-length: 0\r\n\r\n", status);
+                    socket.write_all(response.as_bytes()).await.unwrap();
+                }
+                None => tokio::time::sleep(Duration::from_secs(5)).await,
+            }
+        });
+
+        let error = reqwest::Client::new()
+            .get(url)
+            .timeout(Duration::from_millis(50))
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .unwrap_err();
+        // The url carries a random port, which must not leak into the message check.
+        ClientError::from(error.without_url()).into()
+    }
+
+    fn rpc_error(code: i64, message: &str) -> Error {
+        ClientError::from(RpcError::RpcResponseError {
+            code,
+            message: message.to_string(),
+            data: RpcResponseErrorData::Empty,
+        })
+        .into()
+    }
+
+    #[test]
+    fn test_limit_bounds() {
+        assert_eq!(RateController::new(0, 0).limit(), 1);
+        assert_eq!(RateController::new(4, 2).limit(), 4);
+        assert_eq!(RateController::new(1, 8).limit(), 8);
+    }
+
+    #[test]
+    fn test_success_raises_limit_by_one_per_round() {
+        let controller = RateController::new(1, 10);
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 5);
+
+        // Each success adds 1 / limit, so it takes a sixth success at 5 to reach 6.
+        for _ in 0..5 {
+            controller.on_success();
+        }
+        assert_eq!(controller.limit(), 5);
+        controller.on_success();
+        assert_eq!(controller.limit(), 6);
+    }
+
+    #[test]
+    fn test_success_stops_at_max() {
+        let controller = RateController::new(1, 4);
+        for _ in 0..100 {
+            controller.on_success();
+        }
+        assert_eq!(controller.limit(), 4);
+    }
+
+    #[test]
+    fn test_throttle_halves_once_per_window() {
+        let controller = RateController::new(1, 16);
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 8);
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 8);
+
+        controller.state.lock().unwrap().last_decrease = Some(Instant::now() - DECREASE_WINDOW);
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 4);
+    }
+
+    #[test]
+    fn test_throttle_stops_at_min() {
+        let controller = RateController::new(3, 4);
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 3);
+
+        controller.state.lock().unwrap().last_decrease = None;
+        controller.on_throttled();
+        assert_eq!(controller.limit(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_permit() {
+        let controller = RateController::new(1, 1);
+        let permit = controller.acquire().await;
+        assert!(controller.acquire().now_or_never().is_none());
+
+        drop(permit);
+        assert!(controller.acquire().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_is_throttled() {
+        assert!(is_throttled(&http_error(Some(429)).await));
+        assert!(is_throttled(&http_error(None).await));
+        assert!(is_throttled(&rpc_error(429, "Request limit exceeded")));
+        assert!(is_throttled(&anyhow!("HTTP 429 Too Many Requests")));
+
+        assert!(!is_throttled(&http_error(Some(500)).await));
+        assert!(!is_throttled(&rpc_error(
+            -32005,
+            "Node is behind by 42 slots"
+        )));
+        assert!(!is_throttled(&anyhow!("Slot 5 was skipped")));
+    }
+}
@@ -0,0 +1,216 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use chrono::DateTime;
+use solana_sdk::commitment_config::CommitmentLevel;
+use solana_transaction_status::EncodedConfirmedBlock;
+use tracing::{error, warn};
+
+use crate::{
+    block_processor::{process_block, record_skipped_slot},
+    checkpoint::{Checkpoint, SlotStatus},
+    models::{
+        CorrectionEvent, LiquidityEvent, OutputConfig, PoolCreated, TradeData, CORRECTION_TOPIC,
+        LIQUIDITY_STREAM, POOLS_STREAM,
+    },
+    rpc_client::{fetch_block_at, fetch_block_header, get_finalized_slot, FetchError},
+    utils::load_records,
+};
+
+struct TrackedBlock {
+    blockhash: String,
+    parent_slot: u64,
+    date: String,
+}
+
+/// Re-checks slots processed at `confirmed` once they are finalized. A slot whose
+/// finalized blockhash or parent differs, or that was finalized as skipped, came from a
+/// dropped fork: its output files are tombstoned (renamed to `<slot>.<ext>.orphaned`), a
+/// `CorrectionEvent` goes out on the publisher, and the finalized block, if any, is
+/// processed in its place.
+pub struct Reconciler {
+    output: OutputConfig,
+    publisher: Option<Arc<Mutex<zmq::Socket>>>,
+    pending: BTreeMap<u64, TrackedBlock>,
+    finalized: u64,
+}
+
+impl Reconciler {
+    pub async fn new(
+        output: OutputConfig,
+        publisher: Option<Arc<Mutex<zmq::Socket>>>,
+    ) -> Result<Self> {
+        Ok(Reconciler {
+            output,
+            publisher,
+            pending: BTreeMap::new(),
+            finalized: get_finalized_slot().await?,
+        })
+    }
+
+    /// Remembers a processed block if it is newer than the last finalized slot seen.
+    /// Older blocks were already finalized when fetched and need no second look.
+    pub fn track(&mut self, slot: u64, block: &EncodedConfirmedBlock) {
+        if slot <= self.finalized {
+            return;
+        }
+        let Some(date) = block
+            .block_time
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp, 0))
+            .map(|datetime| datetime.format("%Y-%m-%d").to_string())
+        else {
+            return;
+        };
+        self.pending.insert(
+            slot,
+            TrackedBlock {
+                blockhash: block.blockhash.clone(),
+                parent_slot: block.parent_slot,
+                date,
+            },
+        );
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Checks every tracked slot that has reached finalization. Slots the RPC cannot
+    /// answer for yet, or whose correction failed, stay tracked for the next pass.
+    pub async fn reconcile(&mut self, checkpoint: &Checkpoint) -> Result<()> {
+        self.finalized = get_finalized_slot().await?;
+        let still_pending = self.pending.split_off(&(self.finalized + 1));
+        let ready = std::mem::replace(&mut self.pending, still_pending);
+
+        for (slot, tracked) in ready {
+            match self.reconcile_slot(slot, &tracked, checkpoint).await {
+                Ok(true) => {}
+                Ok(false) => {
+                    self.pending.insert(slot, tracked);
+                }
+                Err(e) => {
+                    error!(slot, "Failed to correct slot: {:#}", e);
+                    self.pending.insert(slot, tracked);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Compares one finalized slot with what was processed and corrects the output if
+    // they differ; false if the slot could not be checked yet.
+    async fn reconcile_slot(
+        &self,
+        slot: u64,
+        tracked: &TrackedBlock,
+        checkpoint: &Checkpoint,
+    ) -> Result<bool> {
+        match fetch_block_header(slot, CommitmentLevel::Finalized).await {
+            Ok((blockhash, parent_slot))
+                if blockhash == tracked.blockhash && parent_slot == tracked.parent_slot => {}
+            Ok((blockhash, _)) => {
+                warn!(
+                    slot,
+                    "Slot was finalized with block {}, not {}",
+                    blockhash,
+                    tracked.blockhash
+                );
+                self.retract(slot, tracked, Some(blockhash))?;
+                let status = match fetch_block_at(slot, CommitmentLevel::Finalized).await {
+                    Ok(block) => {
+                        match process_block(slot, block, &self.output, self.publisher.clone())
+                            .await?
+                        {
+                            0 => SlotStatus::Empty,
+                            _ => SlotStatus::Done,
+                        }
+                    }
+                    Err(e) => {
+                        error!(slot, "Failed to fetch finalized block: {}", e);
+                        SlotStatus::Failed
+                    }
+                };
+                checkpoint.record(slot, status)?;
+            }
+            Err(FetchError::Skipped(_)) => {
+                warn!(slot, "Slot was dropped before finalization");
+                self.retract(slot, tracked, None)?;
+                record_skipped_slot(slot, &self.output)?;
+                checkpoint.record(slot, SlotStatus::Skipped)?;
+            }
+            Err(FetchError::Pruned(_)) => {
+                warn!(slot, "Slot was pruned before it could be reconciled");
+            }
+            Err(e) => {
+                warn!(slot, "Failed to reconcile slot: {}", e);
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    fn retract(
+        &self,
+        slot: u64,
+        tracked: &TrackedBlock,
+        finalized_blockhash: Option<String>,
+    ) -> Result<()> {
+        let mut retracted_signatures: Vec<String> = vec![];
+        let trades_file = self.output.slot_file(None, &tracked.date, slot);
+        if trades_file.exists() {
+            retracted_signatures.extend(
+                load_records::<TradeData>(&trades_file)?
+                    .into_iter()
+                    .map(|trade| trade.signature),
+            );
+            tombstone(&trades_file)?;
+        }
+        let liquidity_file = self.output.slot_file(Some(LIQUIDITY_STREAM), &tracked.date, slot);
+        if liquidity_file.exists() {
+            retracted_signatures.extend(
+                load_records::<LiquidityEvent>(&liquidity_file)?
+                    .into_iter()
+                    .map(|event| event.signature),
+            );
+            tombstone(&liquidity_file)?;
+        }
+        let pools_file = self.output.slot_file(Some(POOLS_STREAM), &tracked.date, slot);
+        if pools_file.exists() {
+            retracted_signatures.extend(
+                load_records::<PoolCreated>(&pools_file)?
+                    .into_iter()
+                    .map(|pool| pool.signature),
+            );
+            tombstone(&pools_file)?;
+        }
+        retracted_signatures.sort();
+        retracted_signatures.dedup();
+
+        if let Some(publisher) = &self.publisher {
+            let event = CorrectionEvent {
+                slot,
+                date: tracked.date.clone(),
+                orphaned_blockhash: tracked.blockhash.clone(),
+                finalized_blockhash,
+                retracted_signatures,
+            };
+            let json_str = serde_json::to_string(&event)?;
+            let sock = publisher.lock().unwrap();
+            sock.send(CORRECTION_TOPIC, zmq::SNDMORE)?;
+            sock.send(&json_str, 0)?;
+        }
+        Ok(())
+    }
+}
+
+fn tombstone(path: &Path) -> Result<()> {
+    let mut tombstone = path.as_os_str().to_owned();
+    tombstone.push(".orphaned");
+    fs::rename(path, tombstone)?;
+    Ok(())
+}
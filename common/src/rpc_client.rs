@@ -1,54 +1,226 @@
 use std::str::FromStr;
 
-use crate::global::RPC_CLIENT;
+use crate::global::RPC_POOL;
+use crate::metrics::{endpoint_label, CHAIN_HEAD, FETCH_LATENCY};
 use anyhow::{Error, Result};
 use serde_json::json;
-use solana_client::{client_error::ClientError, rpc_client::RpcClient};
+use tracing::{debug, instrument};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    nonblocking::rpc_client::RpcClient,
+    rpc_request::RpcError,
+};
 use solana_sdk::{
     bs58,
     commitment_config::{CommitmentConfig, CommitmentLevel},
     signature::Signature,
 };
 use solana_transaction_status::{
-    EncodedConfirmedBlock, EncodedConfirmedTransactionWithStatusMeta,
+    EncodedConfirmedBlock, EncodedConfirmedTransactionWithStatusMeta, UiConfirmedBlock,
     EncodedTransactionWithStatusMeta,
 };
 
-pub async fn fetch_block_with_version(block_slot: u64) -> Result<EncodedConfirmedBlock, Error> {
-    println!("Fetch block for slot: {}", block_slot);
-    let rpc_client = RPC_CLIENT.clone();
+// JSON-RPC error codes `getBlock` uses for slots it cannot serve.
+const SLOT_SKIPPED: i64 = -32007;
+const LONG_TERM_STORAGE_SLOT_SKIPPED: i64 = -32009;
+const BLOCK_NOT_AVAILABLE: i64 = -32004;
+const BLOCK_STATUS_NOT_AVAILABLE_YET: i64 = -32014;
+const BLOCK_CLEANED_UP: i64 = -32001;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FetchError {
+    /// The leader produced no block; no node will ever have one.
+    #[error("Slot {0} was skipped")]
+    Skipped(u64),
+    /// The node has not seen the block yet, or not at the requested commitment.
+    #[error("Block for slot {0} is not available yet")]
+    NotAvailable(u64),
+    /// The block is older than the node's ledger history.
+    #[error("Block for slot {0} has been pruned")]
+    Pruned(u64),
+    #[error(transparent)]
+    Transport(#[from] Error),
+}
+
+impl FetchError {
+    fn from_client_error(slot: u64, e: ClientError) -> Self {
+        match rpc_error_code(&e) {
+            Some(SLOT_SKIPPED | LONG_TERM_STORAGE_SLOT_SKIPPED) => FetchError::Skipped(slot),
+            Some(BLOCK_NOT_AVAILABLE | BLOCK_STATUS_NOT_AVAILABLE_YET) => {
+                FetchError::NotAvailable(slot)
+            }
+            Some(BLOCK_CLEANED_UP) => FetchError::Pruned(slot),
+            _ => FetchError::Transport(e.into()),
+        }
+    }
+}
+
+fn rpc_error_code(e: &ClientError) -> Option<i64> {
+    match e.kind() {
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. }) => Some(*code),
+        _ => None,
+    }
+}
+
+pub async fn fetch_block_with_version(block_slot: u64) -> Result<EncodedConfirmedBlock, FetchError> {
+    fetch_block_at(block_slot, CommitmentLevel::Confirmed).await
+}
+
+pub async fn fetch_block_at(
+    block_slot: u64,
+    commitment: CommitmentLevel,
+) -> Result<EncodedConfirmedBlock, FetchError> {
+    RPC_POOL
+        .call(is_skipped_slot, |rpc_client| async move {
+            Ok(fetch_block_with_client(&rpc_client, block_slot, commitment).await?)
+        })
+        .await
+        .map_err(|e| e.downcast::<FetchError>().unwrap_or_else(FetchError::Transport))
+}
+
+/// Blockhash and parent slot of the block at `block_slot`, without its transactions.
+pub async fn fetch_block_header(
+    block_slot: u64,
+    commitment: CommitmentLevel,
+) -> Result<(String, u64), FetchError> {
+    RPC_POOL
+        .call(is_skipped_slot, |rpc_client| async move {
+            let params = json!([
+                block_slot,
+                { "maxSupportedTransactionVersion": 0,
+                  "transactionDetails": "none",
+                  "rewards": false,
+                  "commitment": commitment }
+            ]);
+            let response: serde_json::Value = rpc_client
+                .send(solana_client::rpc_request::RpcRequest::GetBlock, params)
+                .await
+                .map_err(|e| FetchError::from_client_error(block_slot, e))?;
+            let block: UiConfirmedBlock = serde_json::from_value(response)?;
+            Ok((block.blockhash, block.parent_slot))
+        })
+        .await
+        .map_err(|e| e.downcast::<FetchError>().unwrap_or_else(FetchError::Transport))
+}
+
+// A skipped slot has no block on any node, so there is no point failing over.
+pub fn is_skipped_slot(e: &Error) -> bool {
+    if let Some(e) = e.downcast_ref::<FetchError>() {
+        return matches!(e, FetchError::Skipped(_));
+    }
+    e.downcast_ref::<ClientError>()
+        .and_then(rpc_error_code)
+        .is_some_and(|code| code == SLOT_SKIPPED || code == LONG_TERM_STORAGE_SLOT_SKIPPED)
+}
+
+#[instrument(skip_all, fields(slot = block_slot, endpoint = %endpoint_label(&rpc_client.url())))]
+pub async fn fetch_block_with_client(
+    rpc_client: &RpcClient,
+    block_slot: u64,
+    commitment: CommitmentLevel,
+) -> Result<EncodedConfirmedBlock, FetchError> {
+    debug!("Fetching block");
+    let _timer = FETCH_LATENCY
+        .with_label_values(&[&endpoint_label(&rpc_client.url())])
+        .start_timer();
     let params = json!([
         block_slot,
         { "maxSupportedTransactionVersion": 0 ,
-          "commitment": CommitmentLevel::Confirmed }
+          "encoding": "base64",
+          "commitment": commitment }
     ]);
 
-    let response: serde_json::Value =
-        rpc_client.send(solana_client::rpc_request::RpcRequest::GetBlock, params)?;
+    let response: serde_json::Value = rpc_client
+        .send(solana_client::rpc_request::RpcRequest::GetBlock, params)
+        .await
+        .map_err(|e| FetchError::from_client_error(block_slot, e))?;
 
-    let block: EncodedConfirmedBlock = serde_json::from_value(response)?;
+    let block: EncodedConfirmedBlock =
+        serde_json::from_value(response).map_err(|e| FetchError::Transport(e.into()))?;
 
     Ok(block)
 }
 
-pub async fn get_latest_slot() -> Result<u64, ClientError> {
-    let rpc_client = RPC_CLIENT.clone();
-    let slot = rpc_client.get_slot_with_commitment(CommitmentConfig::confirmed());
-    // let response: serde_json::Value = rpc_client.send(solana_client::rpc_request::RpcRequest::GetSlot, json!([]))?;
-    slot
+pub async fn get_latest_slot() -> Result<u64, Error> {
+    let slot = RPC_POOL
+        .call(|_| false, |rpc_client| async move {
+            Ok(rpc_client.get_slot_with_commitment(CommitmentConfig::confirmed()).await?)
+        })
+        .await?;
+    CHAIN_HEAD.set(slot as i64);
+    Ok(slot)
+}
+
+pub async fn get_finalized_slot() -> Result<u64, Error> {
+    RPC_POOL
+        .call(|_| false, |rpc_client| async move {
+            Ok(rpc_client.get_slot_with_commitment(CommitmentConfig::finalized()).await?)
+        })
+        .await
+}
+
+pub async fn get_block_time(slot: u64) -> Result<i64, Error> {
+    RPC_POOL
+        .call(is_skipped_slot, |rpc_client| async move {
+            Ok(rpc_client.get_block_time(slot).await?)
+        })
+        .await
+}
+
+// Approximate lower bound on how long a slot takes, used to bound the search window.
+const MIN_SLOT_TIME_SECS: f64 = 0.35;
+// How far past a probe we look for a slot that actually produced a block.
+const MAX_PROBE_DISTANCE: u64 = 100;
+
+/// Returns the first slot whose block time is at or after `timestamp`.
+pub async fn find_slot_for_timestamp(timestamp: i64) -> Result<u64, Error> {
+    let tip = get_latest_slot().await?;
+    let tip_time = probe_block_time(tip.saturating_sub(MAX_PROBE_DISTANCE), tip).await?;
+    let (_, tip_time) = tip_time.ok_or_else(|| anyhow::anyhow!("No block time near tip {}", tip))?;
+    if timestamp > tip_time {
+        return Err(anyhow::anyhow!("Timestamp {} is after the chain tip", timestamp));
+    }
+
+    let max_slots_back = ((tip_time - timestamp) as f64 / MIN_SLOT_TIME_SECS) as u64;
+    let mut lo = tip.saturating_sub(max_slots_back);
+    let mut hi = tip;
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        match probe_block_time(mid, hi).await? {
+            Some((probe, block_time)) if block_time < timestamp => lo = probe + 1,
+            // Either the block at or after mid is late enough, or everything in
+            // mid..hi was skipped, so the first matching slot is at most mid.
+            _ => hi = mid,
+        }
+    }
+    Ok(lo)
+}
+
+// Finds the first slot in `from..=to` that has a block time, skipping leader gaps.
+async fn probe_block_time(from: u64, to: u64) -> Result<Option<(u64, i64)>, Error> {
+    let mut slot = from;
+    while slot <= to && slot - from <= MAX_PROBE_DISTANCE {
+        if let Ok(block_time) = get_block_time(slot).await {
+            return Ok(Some((slot, block_time)));
+        }
+        slot += 1;
+    }
+    if slot <= to {
+        return Err(anyhow::anyhow!("No block time found in slots {}..{}", from, slot));
+    }
+    Ok(None)
 }
 
 pub async fn get_signature(tx: &str) -> Result<EncodedConfirmedTransactionWithStatusMeta, Error> {
-    let rpc_client = RPC_CLIENT.clone();
+    let rpc_client = RPC_POOL.primary();
     let signature = Signature::from_str(tx).unwrap();
     let params = json!([
         tx,
         { "maxSupportedTransactionVersion": 0 }
     ]);
-    let res = rpc_client.send(
-        solana_client::rpc_request::RpcRequest::GetTransaction,
-        params,
-    )?;
+    let res = rpc_client
+        .send(solana_client::rpc_request::RpcRequest::GetTransaction, params)
+        .await?;
     // let res = rpc_client.get_transaction(&signature, solana_transaction_status::UiTransactionEncoding::Base58)?;
     // let response: serde_json::Value = rpc_client.send(solana_client::rpc_request::RpcRequest::GetSignatureStatus, json!([tx]))?;
     // let block: EncodedConfirmedBlock = serde_json::from_value(response)?;
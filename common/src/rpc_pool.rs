@@ -0,0 +1,381 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Error, Result};
+use solana_client::nonblocking::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use serde::Deserialize;
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::{config::RpcConfig, metrics::endpoint_label};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+// Weight given to the newest sample in the latency and error-rate moving averages.
+const EWMA_ALPHA: f64 = 0.2;
+// Endpoints above this error rate are only used once every healthy endpoint failed.
+const UNHEALTHY_ERROR_RATE: f64 = 0.5;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RpcEndpoint {
+    pub url: String,
+    /// Relative share of traffic among healthy endpoints.
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Maximum requests per second, if the provider enforces one.
+    pub rate_limit: Option<u32>,
+}
+
+impl RpcEndpoint {
+    /// Parses `url[|weight[|requests_per_second]]`.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut parts = spec.trim().split('|');
+        let url = parts
+            .next()
+            .filter(|url| !url.is_empty())
+            .ok_or_else(|| anyhow!("Empty RPC endpoint"))?
+            .to_string();
+        let weight = match parts.next() {
+            Some(weight) => weight.parse()?,
+            None => 1,
+        };
+        let rate_limit = match parts.next() {
+            Some(rate_limit) => Some(rate_limit.parse()?),
+            None => None,
+        };
+        Ok(RpcEndpoint {
+            url,
+            weight,
+            rate_limit,
+        })
+    }
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct EndpointStats {
+    pub latency_ms: f64,
+    pub error_rate: f64,
+    pub requests: u64,
+    pub errors: u64,
+}
+
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(per_second: u32) -> Self {
+        TokenBucket {
+            capacity: per_second as f64,
+            tokens: per_second as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.capacity).min(self.capacity);
+        self.last_refill = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+struct EndpointState {
+    endpoint: RpcEndpoint,
+    client: Arc<RpcClient>,
+    limiter: Option<Mutex<TokenBucket>>,
+    stats: Mutex<EndpointStats>,
+}
+
+impl EndpointState {
+    fn score(&self) -> f64 {
+        let stats = self.stats.lock().unwrap();
+        // Untried endpoints start at a nominal 100ms so they get sampled.
+        let latency = if stats.requests == 0 { 100.0 } else { stats.latency_ms.max(1.0) };
+        self.endpoint.weight as f64 / (latency * (1.0 + 10.0 * stats.error_rate))
+    }
+
+    fn is_healthy(&self) -> bool {
+        self.stats.lock().unwrap().error_rate < UNHEALTHY_ERROR_RATE
+    }
+
+    fn try_acquire(&self) -> bool {
+        match &self.limiter {
+            Some(limiter) => limiter.lock().unwrap().try_acquire(),
+            None => true,
+        }
+    }
+
+    fn record(&self, latency: Duration, success: bool) {
+        let mut stats = self.stats.lock().unwrap();
+        let latency_ms = latency.as_secs_f64() * 1000.0;
+        let error = if success { 0.0 } else { 1.0 };
+        if stats.requests == 0 {
+            stats.latency_ms = latency_ms;
+            stats.error_rate = error;
+        } else {
+            stats.latency_ms = EWMA_ALPHA * latency_ms + (1.0 - EWMA_ALPHA) * stats.latency_ms;
+            stats.error_rate = EWMA_ALPHA * error + (1.0 - EWMA_ALPHA) * stats.error_rate;
+        }
+        stats.requests += 1;
+        if !success {
+            stats.errors += 1;
+        }
+    }
+}
+
+/// A set of RPC endpoints that routes each call to the healthiest one and fails over
+/// to the next when a call errors or times out.
+pub struct RpcPool {
+    endpoints: Vec<EndpointState>,
+}
+
+impl RpcPool {
+    pub fn new(endpoints: Vec<RpcEndpoint>, commitment: CommitmentConfig) -> Self {
+        let endpoints = endpoints
+            .into_iter()
+            .map(|endpoint| EndpointState {
+                client: Arc::new(RpcClient::new_with_timeout_and_commitment(
+                    endpoint.url.clone(),
+                    DEFAULT_TIMEOUT,
+                    commitment,
+                )),
+                limiter: endpoint.rate_limit.map(|rps| Mutex::new(TokenBucket::new(rps))),
+                stats: Mutex::new(EndpointStats::default()),
+                endpoint,
+            })
+            .collect();
+        RpcPool { endpoints }
+    }
+
+    /// Builds the pool from `rpc.endpoints` (or SOLANA_RPC_URLS / SOLANA_RPC_URL).
+    pub fn from_config(config: &RpcConfig, commitment: CommitmentConfig) -> Result<Self> {
+        if config.endpoints.is_empty() {
+            return Err(anyhow!(
+                "No RPC endpoints configured: set rpc.endpoints, SOLANA_RPC_URLS or SOLANA_RPC_URL"
+            ));
+        }
+        Ok(Self::new(config.endpoints.clone(), commitment))
+    }
+
+    /// Client of the highest-weighted endpoint, for calls that don't need failover.
+    pub fn primary(&self) -> Arc<RpcClient> {
+        self.endpoints
+            .iter()
+            .max_by_key(|state| state.endpoint.weight)
+            .expect("RPC pool has no endpoints")
+            .client
+            .clone()
+    }
+
+    pub fn stats(&self) -> Vec<(String, EndpointStats)> {
+        self.endpoints
+            .iter()
+            .map(|state| (state.endpoint.url.clone(), state.stats.lock().unwrap().clone()))
+            .collect()
+    }
+
+    // Healthy endpoints by descending score, then unhealthy ones as a last resort.
+    fn ranked(&self) -> Vec<&EndpointState> {
+        let mut ranked: Vec<(&EndpointState, bool, f64)> = self
+            .endpoints
+            .iter()
+            .map(|state| (state, state.is_healthy(), state.score()))
+            .collect();
+        ranked.sort_by(|a, b| b.1.cmp(&a.1).then(b.2.total_cmp(&a.2)));
+        ranked.into_iter().map(|(state, _, _)| state).collect()
+    }
+
+    /// Runs `op` against the best endpoint with rate-limit headroom, moving on to the
+    /// next endpoint whenever it fails. Errors for which `is_final` returns true are
+    /// returned immediately, without counting against the endpoint.
+    pub async fn call<T, F, Fut>(&self, is_final: fn(&Error) -> bool, op: F) -> Result<T>
+    where
+        F: Fn(Arc<RpcClient>) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut remaining = self.ranked();
+        let mut last_error = None;
+
+        while !remaining.is_empty() {
+            let position = remaining.iter().position(|state| state.try_acquire());
+            let state = match position {
+                Some(position) => remaining.remove(position),
+                None => {
+                    // Every remaining endpoint is at its rate limit.
+                    sleep(Duration::from_millis(50)).await;
+                    continue;
+                }
+            };
+
+            let start = Instant::now();
+            match op(state.client.clone()).await {
+                Ok(result) => {
+                    state.record(start.elapsed(), true);
+                    return Ok(result);
+                }
+                Err(e) if is_final(&e) => {
+                    state.record(start.elapsed(), true);
+                    return Err(e);
+                }
+                Err(e) => {
+                    state.record(start.elapsed(), false);
+                    warn!(endpoint = %endpoint_label(&state.endpoint.url), "RPC call failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| anyhow!("No RPC endpoints configured")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::{ready, Ready};
+
+    fn pool(specs: &[&str]) -> RpcPool {
+        let endpoints = specs
+            .iter()
+            .map(|spec| RpcEndpoint::parse(spec).unwrap())
+            .collect();
+        RpcPool::new(endpoints, CommitmentConfig::confirmed())
+    }
+
+    // An op that records which endpoint it ran against and fails on the `failing` ones.
+    fn stub<'a>(
+        calls: &'a Mutex<Vec<String>>,
+        failing: &'a [&str],
+    ) -> impl Fn(Arc<RpcClient>) -> Ready<Result<String>> + 'a {
+        move |client| {
+            let url = client.url();
+            calls.lock().unwrap().push(url.clone());
+            if failing.contains(&url.as_str()) {
+                ready(Err(anyhow!("Slot 42 was skipped by {}", url)))
+            } else {
+                ready(Ok(url))
+            }
+        }
+    }
+
+    fn never_final(_: &Error) -> bool {
+        false
+    }
+
+    fn stats(pool: &RpcPool, url: &str) -> EndpointStats {
+        pool.stats().into_iter().find(|(u, _)| u == url).unwrap().1
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        let endpoint = RpcEndpoint::parse(" http://a|3|25 ").unwrap();
+        assert_eq!(endpoint.url, "http://a");
+        assert_eq!(endpoint.weight, 3);
+        assert_eq!(endpoint.rate_limit, Some(25));
+
+        let endpoint = RpcEndpoint::parse("http://a").unwrap();
+        assert_eq!(endpoint.weight, 1);
+        assert_eq!(endpoint.rate_limit, None);
+
+        assert!(RpcEndpoint::parse("").is_err());
+        assert!(RpcEndpoint::parse("http://a|heavy").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_fails_over_to_next_endpoint() {
+        let pool = pool(&["http://a|2", "http://b"]);
+        let calls = Mutex::new(Vec::new());
+
+        let result = pool.call(never_final, stub(&calls, &["http://a"])).await;
+        assert_eq!(result.unwrap(), "http://b");
+        assert_eq!(*calls.lock().unwrap(), ["http://a", "http://b"]);
+        assert_eq!(stats(&pool, "http://a").errors, 1);
+        assert_eq!(stats(&pool, "http://b").errors, 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_returns_last_error_when_all_fail() {
+        let pool = pool(&["http://a|2", "http://b"]);
+        let calls = Mutex::new(Vec::new());
+
+        let result = pool
+            .call(never_final, stub(&calls, &["http://a", "http://b"]))
+            .await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Slot 42 was skipped by http://b"
+        );
+        assert_eq!(*calls.lock().unwrap(), ["http://a", "http://b"]);
+    }
+
+    #[tokio::test]
+    async fn test_call_does_not_retry_final_error() {
+        let pool = pool(&["http://a|2", "http://b"]);
+        let calls = Mutex::new(Vec::new());
+
+        let is_final = |e: &Error| e.to_string().contains("was skipped");
+        let result = pool.call(is_final, stub(&calls, &["http://a"])).await;
+        assert!(result.is_err());
+        assert_eq!(*calls.lock().unwrap(), ["http://a"]);
+        // The endpoint answered, so it is not held against it.
+        let stats = stats(&pool, "http://a");
+        assert_eq!((stats.requests, stats.errors), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_call_demotes_failing_endpoint() {
+        let pool = pool(&["http://a|2", "http://b"]);
+        let calls = Mutex::new(Vec::new());
+
+        pool.call(never_final, stub(&calls, &["http://a"]))
+            .await
+            .unwrap();
+        assert!(stats(&pool, "http://a").error_rate >= UNHEALTHY_ERROR_RATE);
+
+        calls.lock().unwrap().clear();
+        let result = pool.call(never_final, stub(&calls, &[])).await;
+        assert_eq!(result.unwrap(), "http://b");
+        assert_eq!(*calls.lock().unwrap(), ["http://b"]);
+    }
+
+    #[tokio::test]
+    async fn test_call_skips_endpoint_at_rate_limit() {
+        let pool = pool(&["http://a|2|1", "http://b"]);
+        let calls = Mutex::new(Vec::new());
+
+        for _ in 0..2 {
+            pool.call(never_final, stub(&calls, &[])).await.unwrap();
+        }
+        // The single token of `a` is spent on the first call.
+        assert_eq!(*calls.lock().unwrap(), ["http://a", "http://b"]);
+    }
+
+    #[test]
+    fn test_token_bucket_refills() {
+        let mut bucket = TokenBucket::new(2);
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        bucket.last_refill -= Duration::from_millis(500);
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+}
@@ -0,0 +1,77 @@
+use std::{future, io, time::Duration};
+
+use tokio::{signal, sync::watch, time::sleep};
+use tracing::{error, warn};
+
+/// Set once SIGINT or SIGTERM arrives. Long-running loops stop taking new work when it
+/// is and finish what they started; a second signal, or running past the drain
+/// deadline, forces them to abandon that work too, so they still sync and flush on
+/// the way out.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    forced: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    /// Installs the signal handlers. After the first signal the process has `deadline`
+    /// to drain before in-flight work is cancelled.
+    pub fn listen(deadline: Duration) -> Self {
+        let (sender, requested) = watch::channel(false);
+        let (force, forced) = watch::channel(false);
+        tokio::spawn(async move {
+            if let Err(e) = wait_for_signal().await {
+                error!("Failed to install signal handlers: {:#}", e);
+                // Keep the sender so `requested` never resolves.
+                return future::pending().await;
+            }
+            warn!(
+                deadline_secs = deadline.as_secs(),
+                "Shutdown requested, draining in-flight work"
+            );
+            let _ = sender.send(true);
+            tokio::select! {
+                _ = wait_for_signal() => {
+                    warn!("Second signal, cancelling in-flight work");
+                }
+                _ = sleep(deadline) => {
+                    error!("In-flight work did not drain within the deadline, cancelling it");
+                }
+            }
+            let _ = force.send(true);
+        });
+        Shutdown { requested, forced }
+    }
+
+    pub fn is_requested(&self) -> bool {
+        *self.requested.borrow()
+    }
+
+    /// Resolves once shutdown was requested.
+    pub async fn requested(&mut self) {
+        if self.requested.wait_for(|requested| *requested).await.is_err() {
+            future::pending::<()>().await;
+        }
+    }
+
+    /// Resolves once in-flight work has to be abandoned: on a second signal or when
+    /// the drain deadline passes.
+    pub async fn forced(&mut self) {
+        if self.forced.wait_for(|forced| *forced).await.is_err() {
+            future::pending::<()>().await;
+        }
+    }
+}
+
+async fn wait_for_signal() -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal::unix::signal(signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    signal::ctrl_c().await
+}
@@ -1,9 +1,375 @@
-use solana_sdk::pubkey::Pubkey;
+use std::str::FromStr;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use borsh::BorshDeserialize;
+use lazy_static::lazy_static;
+use solana_sdk::{hash::hashv, pubkey::Pubkey};
 use solana_transaction_status::UiInstruction;
 
-use crate::models::{TokenBalance, TradeInstruction};
+use crate::config::config;
+use crate::decode_error::DecodeError;
+use crate::models::{
+    ClmmLiquidityArgs, ClmmLiquidityInstruction, ClmmOpenPositionArgs, ClmmSwapArgs,
+    ClmmSwapEvent, CpmmInitializeArgs, CpmmLpChangeEvent, CpmmSwapEvent, Initialize2Args,
+    LiquidityInstruction, LoggedEvents, PoolInitInstruction, SwapBaseInArgs, SwapBaseOutArgs,
+    TokenBalance, TradeInstruction,
+};
 use crate::utils::prepare_input_accounts;
 
+// Anchor prefixes instruction data with the first 8 bytes of sha256("global:<name>")
+// and logged events with those of sha256("event:<Name>").
+fn anchor_discriminator(namespace: &str, name: &str) -> [u8; 8] {
+    let hash = hashv(&[namespace.as_bytes(), b":", name.as_bytes()]);
+    let mut discriminator = [0u8; 8];
+    discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+    discriminator
+}
+
+lazy_static! {
+    // (instruction name, discriminator) of the Raydium CLMM instructions decoded here.
+    static ref CLMM_INSTRUCTIONS: Vec<(&'static str, [u8; 8])> = [
+        ("swap", "Swap"),
+        ("swap_v2", "SwapV2"),
+        ("open_position", "OpenPosition"),
+        ("open_position_v2", "OpenPositionV2"),
+        ("open_position_with_token22_nft", "OpenPositionWithToken22Nft"),
+        ("increase_liquidity", "IncreaseLiquidity"),
+        ("increase_liquidity_v2", "IncreaseLiquidityV2"),
+        ("decrease_liquidity", "DecreaseLiquidity"),
+        ("decrease_liquidity_v2", "DecreaseLiquidityV2"),
+        ("close_position", "ClosePosition"),
+    ]
+    .into_iter()
+    .map(|(instruction, name)| (name, anchor_discriminator("global", instruction)))
+    .collect();
+    // Same for Raydium CPMM.
+    static ref CPMM_INSTRUCTIONS: Vec<(&'static str, [u8; 8])> = [
+        ("swap_base_input", "SwapBaseIn"),
+        ("swap_base_output", "SwapBaseOut"),
+        ("initialize", "Initialize"),
+        ("deposit", "Deposit"),
+        ("withdraw", "Withdraw"),
+    ]
+    .into_iter()
+    .map(|(instruction, name)| (name, anchor_discriminator("global", instruction)))
+    .collect();
+    // CLMM and CPMM both name their swap event SwapEvent, so events are told apart by
+    // the program that logged them.
+    static ref SWAP_EVENT: [u8; 8] = anchor_discriminator("event", "SwapEvent");
+    static ref LP_CHANGE_EVENT: [u8; 8] = anchor_discriminator("event", "LpChangeEvent");
+}
+
+// The name of an Anchor instruction from `known` and its arguments, if it is one of
+// them.
+fn anchor_instruction<'a>(
+    known: &[(&'static str, [u8; 8])],
+    bytes_stream: &'a [u8],
+) -> Option<(&'static str, &'a [u8])> {
+    let (discriminator, args) = bytes_stream.split_at_checked(8)?;
+    known
+        .iter()
+        .find(|(_, known)| known == discriminator)
+        .map(|(name, _)| (*name, args))
+}
+
+fn clmm_instruction(bytes_stream: &[u8]) -> Option<(&'static str, &[u8])> {
+    anchor_instruction(&CLMM_INSTRUCTIONS, bytes_stream)
+}
+
+fn cpmm_instruction(bytes_stream: &[u8]) -> Option<(&'static str, &[u8])> {
+    anchor_instruction(&CPMM_INSTRUCTIONS, bytes_stream)
+}
+
+/// Decodes a Raydium CLMM `swap` or `swap_v2`. `vault_a` and `vault_b` are the pool's
+/// token 0 and token 1 vaults (the mint with the lower address is token 0), and the
+/// instruction counts as SwapBaseIn when the trader fixed the input amount.
+pub fn parse_clmm_swap_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+    post_token_balances: &[TokenBalance],
+) -> Result<Option<TradeInstruction>, DecodeError> {
+    let Some((name, mut args)) = clmm_instruction(bytes_stream) else {
+        return Ok(None);
+    };
+    if name != "Swap" && name != "SwapV2" {
+        return Ok(None);
+    }
+    let args = ClmmSwapArgs::deserialize(&mut args)?;
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+    let input_vault = account(5)?;
+    let output_vault = account(6)?;
+
+    let mint = |vault: &String| {
+        post_token_balances
+            .iter()
+            .find(|balance| balance.address == *vault)
+            .and_then(|balance| Pubkey::from_str(&balance.mint).ok())
+            .ok_or_else(|| DecodeError::MissingVaultBalance(vault.clone()))
+    };
+    let (vault_a, vault_b) = if mint(&input_vault)? < mint(&output_vault)? {
+        (input_vault, output_vault)
+    } else {
+        (output_vault, input_vault)
+    };
+
+    let instruction = TradeInstruction {
+        dapp_address: config().programs.raydium_clmm.clone(),
+        amm: account(2)?,
+        vault_a,
+        vault_b,
+        sqrt_price_limit_x64: Some(args.sqrt_price_limit_x64),
+        ..Default::default()
+    };
+    Ok(Some(if args.is_base_input {
+        TradeInstruction {
+            name: String::from("SwapBaseIn"),
+            amount_in: args.amount,
+            minimum_amount_out: args.other_amount_threshold,
+            ..instruction
+        }
+    } else {
+        TradeInstruction {
+            name: String::from("SwapBaseOut"),
+            max_amount_in: args.other_amount_threshold,
+            amount_out: args.amount,
+            ..instruction
+        }
+    }))
+}
+
+/// Decodes the Raydium CLMM instructions that open, change or close a position. Ticks
+/// are only known when the position is opened; later instructions reference the
+/// position account instead.
+pub fn parse_clmm_liquidity_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<ClmmLiquidityInstruction>, DecodeError> {
+    let Some((name, mut args)) = clmm_instruction(bytes_stream) else {
+        return Ok(None);
+    };
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+
+    // (pool, position NFT mint, position NFT account, vault 0, vault 1)
+    let (pool, nft_mint, nft_account, vaults) = match name {
+        "OpenPosition" | "OpenPositionV2" => (Some(5), Some(2), Some(3), Some((12, 13))),
+        "OpenPositionWithToken22Nft" => (Some(4), Some(2), Some(3), Some((11, 12))),
+        "IncreaseLiquidity" | "IncreaseLiquidityV2" => (Some(2), None, Some(1), Some((9, 10))),
+        "DecreaseLiquidity" | "DecreaseLiquidityV2" => (Some(3), None, Some(1), Some((5, 6))),
+        "ClosePosition" => (None, Some(1), Some(2), None),
+        _ => return Ok(None),
+    };
+
+    let (tick_lower_index, tick_upper_index, liquidity) = match name {
+        "OpenPosition" | "OpenPositionV2" | "OpenPositionWithToken22Nft" => {
+            let args = ClmmOpenPositionArgs::deserialize(&mut args)?;
+            (Some(args.tick_lower_index), Some(args.tick_upper_index), args.liquidity as f64)
+        }
+        "IncreaseLiquidity" | "IncreaseLiquidityV2" => {
+            (None, None, ClmmLiquidityArgs::deserialize(&mut args)?.liquidity as f64)
+        }
+        "DecreaseLiquidity" | "DecreaseLiquidityV2" => {
+            (None, None, -(ClmmLiquidityArgs::deserialize(&mut args)?.liquidity as f64))
+        }
+        _ => (None, None, 0.0),
+    };
+
+    Ok(Some(ClmmLiquidityInstruction {
+        name: name.to_string(),
+        pool: pool.map(account).transpose()?,
+        position_nft_mint: nft_mint.map(account).transpose()?,
+        position_nft_account: nft_account.map(account).transpose()?,
+        vaults: vaults
+            .map(|(vault_0, vault_1)| Ok::<_, DecodeError>((account(vault_0)?, account(vault_1)?)))
+            .transpose()?,
+        tick_lower_index,
+        tick_upper_index,
+        liquidity,
+    }))
+}
+
+/// Decodes a Raydium CPMM `swap_base_input` or `swap_base_output`. `vault_a` and
+/// `vault_b` are the pool's token 0 and token 1 vaults, as for CLMM.
+pub fn parse_cpmm_swap_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<TradeInstruction>, DecodeError> {
+    let Some((name, mut args)) = cpmm_instruction(bytes_stream) else {
+        return Ok(None);
+    };
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+    let mint = |position: usize| {
+        let address = account(position)?;
+        Pubkey::from_str(&address)
+            .map_err(|_| DecodeError::InstructionData(format!("invalid mint {}", address)))
+    };
+    let (input_vault, output_vault) = (account(6)?, account(7)?);
+    let (vault_a, vault_b) = if mint(10)? < mint(11)? {
+        (input_vault, output_vault)
+    } else {
+        (output_vault, input_vault)
+    };
+
+    let instruction = TradeInstruction {
+        dapp_address: config().programs.raydium_cpmm.clone(),
+        name: name.to_string(),
+        amm: account(3)?,
+        vault_a,
+        vault_b,
+        ..Default::default()
+    };
+    match name {
+        "SwapBaseIn" => {
+            let args = SwapBaseInArgs::deserialize(&mut args)?;
+            Ok(Some(TradeInstruction {
+                amount_in: args.amount_in,
+                minimum_amount_out: args.minimum_amount_out,
+                ..instruction
+            }))
+        }
+        "SwapBaseOut" => {
+            let args = SwapBaseOutArgs::deserialize(&mut args)?;
+            Ok(Some(TradeInstruction {
+                max_amount_in: args.max_amount_in,
+                amount_out: args.amount_out,
+                ..instruction
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Raydium CPMM instructions that create, fund or drain a pool, with the pool accounts
+/// they reference; base is token 0 and quote token 1.
+pub fn parse_cpmm_liquidity_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<LiquidityInstruction>, DecodeError> {
+    let Some((name, _)) = cpmm_instruction(bytes_stream) else {
+        return Ok(None);
+    };
+    // (pool, lp mint, base vault, quote vault)
+    let (amm, lp_mint, base_vault, quote_vault) = match name {
+        "Initialize" => (3, 6, 10, 11),
+        "Deposit" | "Withdraw" => (2, 12, 6, 7),
+        _ => return Ok(None),
+    };
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+
+    Ok(Some(LiquidityInstruction {
+        name: name.to_string(),
+        amm: account(amm)?,
+        lp_mint: Some(account(lp_mint)?),
+        base_vault: account(base_vault)?,
+        quote_vault: account(quote_vault)?,
+    }))
+}
+
+/// Decodes a Raydium CPMM `initialize`; `None` for any other instruction.
+pub fn parse_cpmm_pool_init_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<PoolInitInstruction>, DecodeError> {
+    let Some(("Initialize", mut args)) = cpmm_instruction(bytes_stream) else {
+        return Ok(None);
+    };
+    let args = CpmmInitializeArgs::deserialize(&mut args)?;
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+
+    Ok(Some(PoolInitInstruction {
+        program: config().programs.raydium_cpmm.clone(),
+        amm: account(3)?,
+        lp_mint: account(6)?,
+        base_mint: account(4)?,
+        quote_mint: account(5)?,
+        base_vault: account(10)?,
+        quote_vault: account(11)?,
+        creator: account(0)?,
+        open_time: args.open_time,
+        init_base_amount: args.init_amount_0,
+        init_quote_amount: args.init_amount_1,
+    }))
+}
+
+/// The events Raydium CLMM and CPMM logged in a transaction. "Program data:" lines
+/// belong to the program on top of the invoke stack the logs describe.
+pub fn parse_logged_events(log_messages: &[String]) -> LoggedEvents {
+    let programs = &config().programs;
+    let mut events = LoggedEvents::default();
+    let mut stack: Vec<&str> = vec![];
+    for message in log_messages {
+        if let Some(data) = message.strip_prefix("Program data: ") {
+            let Some(&program) = stack.last() else {
+                continue;
+            };
+            let Ok(data) = STANDARD.decode(data) else {
+                continue;
+            };
+            let Some((discriminator, mut event)) = data.split_at_checked(8) else {
+                continue;
+            };
+            if program == programs.raydium_clmm && discriminator == SWAP_EVENT.as_slice() {
+                if let Ok(event) = ClmmSwapEvent::deserialize(&mut event) {
+                    let pool = event.pool_state.to_string();
+                    events.clmm_swaps.entry(pool).or_default().push_back(event);
+                }
+            } else if program == programs.raydium_cpmm && discriminator == SWAP_EVENT.as_slice() {
+                if let Ok(event) = CpmmSwapEvent::deserialize(&mut event) {
+                    let pool = event.pool_id.to_string();
+                    events.cpmm_swaps.entry(pool).or_default().push_back(event);
+                }
+            } else if program == programs.raydium_cpmm
+                && discriminator == LP_CHANGE_EVENT.as_slice()
+            {
+                if let Ok(event) = CpmmLpChangeEvent::deserialize(&mut event) {
+                    let pool = event.pool_id.to_string();
+                    events.cpmm_lp_changes.entry(pool).or_default().push_back(event);
+                }
+            }
+            continue;
+        }
+        // "Program <id> invoke [<depth>]", then "Program <id> success" or
+        // "Program <id> failed: <error>" when it returns.
+        let mut words = message.split_whitespace();
+        let (Some("Program"), Some(program), Some(status)) = (words.next(), words.next(), words.next())
+        else {
+            continue;
+        };
+        match status {
+            "invoke" => stack.push(program),
+            "success" | "failed:" => {
+                stack.pop();
+            }
+            _ => {}
+        }
+    }
+    events
+}
+
 pub fn parse_trade_instruction(
     bytes_stream: &Vec<u8>,
     input_accounts: Vec<String>,
@@ -11,37 +377,117 @@ pub fn parse_trade_instruction(
     accounts: &Vec<String>,
     base_address: &String,
     quote_address: &String,
-) -> Option<TradeInstruction> {
-    let (disc_bytes, rest) = bytes_stream.split_at(1);
-    let discriminator: u8 = u8::from(disc_bytes[0]);
+) -> Result<Option<TradeInstruction>, DecodeError> {
+    let Some((&discriminator, mut rest)) = bytes_stream.split_first() else {
+        return Ok(None);
+    };
 
     let mut result = None;
 
     match discriminator {
         9 => {
+            let args = SwapBaseInArgs::deserialize(&mut rest)?;
             result = Some(TradeInstruction {
-                dapp_address: String::from("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8"),
+                dapp_address: config().programs.raydium_amm.clone(),
                 name: String::from("SwapBaseIn"),
-                amm: input_accounts.get(1).unwrap().to_string(),
+                amm: input_accounts
+                    .get(1)
+                    .ok_or(DecodeError::MissingAccount(1))?
+                    .to_string(),
                 vault_a: base_address.to_string(),
                 vault_b: quote_address.to_string(),
+                amount_in: args.amount_in,
+                minimum_amount_out: args.minimum_amount_out,
                 ..Default::default()
             });
         }
         11 => {
+            let args = SwapBaseOutArgs::deserialize(&mut rest)?;
             result = Some(TradeInstruction {
-                dapp_address: String::from("675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8"),
+                dapp_address: config().programs.raydium_amm.clone(),
                 name: String::from("SwapBaseOut"),
-                amm: input_accounts.get(1).unwrap().to_string(),
+                amm: input_accounts
+                    .get(1)
+                    .ok_or(DecodeError::MissingAccount(1))?
+                    .to_string(),
                 vault_a: base_address.to_string(),
                 vault_b: quote_address.to_string(),
+                max_amount_in: args.max_amount_in,
+                amount_out: args.amount_out,
                 ..Default::default()
             });
         }
         _ => {}
     }
 
-    return result;
+    Ok(result)
+}
+
+/// Raydium AMM v4 instructions that add, remove or reconfigure liquidity. Account
+/// positions follow the program's instruction layouts; base is the pool's coin side and
+/// quote its pc side.
+pub fn parse_liquidity_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<LiquidityInstruction>, DecodeError> {
+    let Some(&discriminator) = bytes_stream.first() else {
+        return Ok(None);
+    };
+
+    // (name, amm, lp mint, base vault, quote vault)
+    let (name, amm, lp_mint, base_vault, quote_vault) = match discriminator {
+        1 => ("Initialize2", 4, Some(7), 10, 11),
+        3 => ("Deposit", 1, Some(5), 6, 7),
+        4 => ("Withdraw", 1, Some(5), 6, 7),
+        6 => ("SetParams", 1, None, 5, 6),
+        7 => ("WithdrawPnl", 1, None, 5, 6),
+        _ => return Ok(None),
+    };
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+
+    Ok(Some(LiquidityInstruction {
+        name: name.to_string(),
+        amm: account(amm)?,
+        lp_mint: lp_mint.map(account).transpose()?,
+        base_vault: account(base_vault)?,
+        quote_vault: account(quote_vault)?,
+    }))
+}
+
+/// Decodes a Raydium AMM v4 `initialize2`; `None` for any other instruction.
+pub fn parse_pool_init_instruction(
+    bytes_stream: &[u8],
+    input_accounts: &[String],
+) -> Result<Option<PoolInitInstruction>, DecodeError> {
+    let Some((1, mut rest)) = bytes_stream.split_first() else {
+        return Ok(None);
+    };
+    let args = Initialize2Args::deserialize(&mut rest)?;
+    let account = |position: usize| {
+        input_accounts
+            .get(position)
+            .cloned()
+            .ok_or(DecodeError::MissingAccount(position))
+    };
+
+    Ok(Some(PoolInitInstruction {
+        program: config().programs.raydium_amm.clone(),
+        amm: account(4)?,
+        lp_mint: account(7)?,
+        base_mint: account(8)?,
+        quote_mint: account(9)?,
+        base_vault: account(10)?,
+        quote_vault: account(11)?,
+        creator: account(17)?,
+        open_time: args.open_time,
+        init_base_amount: args.init_coin_amount,
+        init_quote_amount: args.init_pc_amount,
+    }))
 }
 
 pub fn get_trade_instruction(
@@ -57,11 +503,11 @@ pub fn get_trade_instruction(
     input_inner_idx: u32,
     base_address: &String,
     quote_address: &String,
-) -> Option<TradeInstruction> {
+) -> Result<Option<TradeInstruction>, DecodeError> {
     let input_accounts = prepare_input_accounts(account_indices, accounts);
     let mut result = None;
     match address.as_str() {
-        "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8" => {
+        program if program == config().programs.raydium_amm => {
             result = parse_trade_instruction(
                 &instruction_data,
                 input_accounts,
@@ -69,10 +515,636 @@ pub fn get_trade_instruction(
                 accounts,
                 base_address,
                 quote_address,
-            );
+            )?;
         }
         _ => {}
     }
 
-    result
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::UiTokenAmount;
+
+    // Instruction accounts named after their position.
+    fn accounts(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|position| format!("account{}", position))
+            .collect()
+    }
+
+    fn data(parts: &[&[u8]]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    // Pubkeys are 32 copies of one byte.
+    fn key(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn vault_balance(address: &str, mint: Pubkey) -> TokenBalance {
+        TokenBalance {
+            account_index: 0,
+            address: address.to_string(),
+            mint: mint.to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: 0.0,
+                decimals: 6,
+                amount: "0".to_string(),
+                ui_amount_string: "0".to_string(),
+            },
+            owner: String::new(),
+            program_id: String::new(),
+        }
+    }
+
+    fn program_data(discriminator: [u8; 8], event: &[u8]) -> String {
+        format!(
+            "Program data: {}",
+            STANDARD.encode(data(&[&discriminator, event]))
+        )
+    }
+
+    // A ClmmSwapEvent of `pool`, laid out field by field.
+    fn clmm_swap_event(pool: Pubkey, tick: i32) -> Vec<u8> {
+        data(&[
+            pool.as_ref(),
+            key(20).as_ref(),
+            key(21).as_ref(),
+            key(22).as_ref(),
+            &1_000u64.to_le_bytes(),
+            &10u64.to_le_bytes(),
+            &2_000u64.to_le_bytes(),
+            &0u64.to_le_bytes(),
+            &[1],
+            &(1u128 << 64).to_le_bytes(),
+            &5_000u128.to_le_bytes(),
+            &tick.to_le_bytes(),
+        ])
+    }
+
+    #[test]
+    fn amm_v4_swaps_decode_their_amounts() {
+        let accounts = accounts(18);
+        let (base, quote) = ("base_vault".to_string(), "quote_vault".to_string());
+        let parse = |data: &[u8]| {
+            parse_trade_instruction(
+                &data.to_vec(),
+                accounts.clone(),
+                &vec![],
+                &accounts,
+                &base,
+                &quote,
+            )
+        };
+
+        // swap_base_in: tag 9, amount_in, minimum_amount_out
+        let swap = parse(&data(&[
+            &[9],
+            &1_000u64.to_le_bytes(),
+            &1_800u64.to_le_bytes(),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            (swap.name.as_str(), swap.amm.as_str()),
+            ("SwapBaseIn", "account1")
+        );
+        assert_eq!((swap.vault_a, swap.vault_b), (base.clone(), quote.clone()));
+        assert_eq!((swap.amount_in, swap.minimum_amount_out), (1_000, 1_800));
+
+        // swap_base_out: tag 11, max_amount_in, amount_out
+        let swap = parse(&data(&[
+            &[11],
+            &1_100u64.to_le_bytes(),
+            &2_000u64.to_le_bytes(),
+        ]))
+        .unwrap()
+        .unwrap();
+        assert_eq!(swap.name, "SwapBaseOut");
+        assert_eq!((swap.max_amount_in, swap.amount_out), (1_100, 2_000));
+
+        assert!(parse(&[]).unwrap().is_none());
+        assert!(parse(&[4]).unwrap().is_none());
+        assert!(parse(&data(&[&[9], &1_000u64.to_le_bytes()])).is_err());
+    }
+
+    #[test]
+    fn amm_v4_liquidity_accounts_follow_the_instruction_layouts() {
+        let accounts = accounts(21);
+        // initialize2: tag 1, nonce, open_time, init_pc_amount, init_coin_amount
+        let initialize2 = data(&[
+            &[1, 254],
+            &0u64.to_le_bytes(),
+            &1_000u64.to_le_bytes(),
+            &2_000u64.to_le_bytes(),
+        ]);
+        let ix = parse_liquidity_instruction(&initialize2, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "Initialize2");
+        assert_eq!(
+            (ix.amm, ix.lp_mint, ix.base_vault, ix.quote_vault),
+            (
+                "account4".into(),
+                Some("account7".into()),
+                "account10".into(),
+                "account11".into()
+            )
+        );
+
+        // deposit: tag 3, max_coin_amount, max_pc_amount, base_side
+        let deposit = data(&[
+            &[3],
+            &5u64.to_le_bytes(),
+            &6u64.to_le_bytes(),
+            &0u64.to_le_bytes(),
+        ]);
+        let ix = parse_liquidity_instruction(&deposit, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "Deposit");
+        assert_eq!(
+            (ix.amm, ix.lp_mint, ix.base_vault, ix.quote_vault),
+            (
+                "account1".into(),
+                Some("account5".into()),
+                "account6".into(),
+                "account7".into()
+            )
+        );
+
+        // withdraw: tag 4, amount
+        let withdraw = data(&[&[4], &7u64.to_le_bytes()]);
+        let ix = parse_liquidity_instruction(&withdraw, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "Withdraw");
+        assert_eq!(
+            (ix.amm, ix.lp_mint, ix.base_vault, ix.quote_vault),
+            (
+                "account1".into(),
+                Some("account5".into()),
+                "account6".into(),
+                "account7".into()
+            )
+        );
+
+        let ix = parse_liquidity_instruction(&[7], &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "WithdrawPnl");
+        assert_eq!((ix.lp_mint, ix.base_vault), (None, "account5".into()));
+    }
+
+    #[test]
+    fn amm_v4_initialize2_creates_a_pool() {
+        let accounts = accounts(21);
+        let initialize2 = data(&[
+            &[1, 254],
+            &1_738_195_200u64.to_le_bytes(),
+            &1_000u64.to_le_bytes(),
+            &2_000u64.to_le_bytes(),
+        ]);
+        let pool = parse_pool_init_instruction(&initialize2, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(pool.program, config().programs.raydium_amm);
+        assert_eq!(
+            (pool.amm, pool.lp_mint),
+            ("account4".into(), "account7".into())
+        );
+        assert_eq!(
+            (pool.base_mint, pool.quote_mint),
+            ("account8".into(), "account9".into())
+        );
+        assert_eq!(
+            (pool.base_vault, pool.quote_vault),
+            ("account10".into(), "account11".into())
+        );
+        assert_eq!(pool.creator, "account17");
+        assert_eq!(pool.open_time, 1_738_195_200);
+        // pc is the quote side, coin the base side.
+        assert_eq!(
+            (pool.init_base_amount, pool.init_quote_amount),
+            (2_000, 1_000)
+        );
+    }
+
+    #[test]
+    fn amm_v4_initialize2_must_be_complete() {
+        let accounts = accounts(21);
+        assert!(parse_pool_init_instruction(&[], &accounts)
+            .unwrap()
+            .is_none());
+        assert!(parse_pool_init_instruction(&[3], &accounts)
+            .unwrap()
+            .is_none());
+        // The tag is initialize2's but the amounts are cut off.
+        let truncated = data(&[&[1, 254], &1_738_195_200u64.to_le_bytes()]);
+        assert!(parse_pool_init_instruction(&truncated, &accounts).is_err());
+    }
+
+    #[test]
+    fn amm_v4_liquidity_ignores_other_instructions() {
+        let accounts = accounts(21);
+        assert!(parse_liquidity_instruction(&[], &accounts)
+            .unwrap()
+            .is_none());
+        // swap_base_in
+        assert!(parse_liquidity_instruction(&[9], &accounts)
+            .unwrap()
+            .is_none());
+        // A deposit with too few accounts is malformed rather than ignored.
+        assert!(matches!(
+            parse_liquidity_instruction(&[3], &accounts[..4]),
+            Err(DecodeError::MissingAccount(5))
+        ));
+    }
+
+    #[test]
+    fn clmm_discriminators_are_anchor_hashes() {
+        assert_eq!(
+            anchor_discriminator("global", "swap"),
+            [248, 198, 158, 145, 225, 117, 135, 200]
+        );
+        assert_eq!(
+            anchor_discriminator("global", "swap_v2"),
+            [43, 4, 237, 11, 26, 201, 30, 98]
+        );
+        assert_eq!(
+            anchor_discriminator("global", "open_position_v2"),
+            [77, 184, 74, 214, 112, 86, 241, 199]
+        );
+        assert_eq!(*SWAP_EVENT, [64, 198, 205, 232, 38, 8, 113, 226]);
+    }
+
+    #[test]
+    fn clmm_swap_orders_vaults_by_mint() {
+        let accounts = accounts(13);
+        // The input vault holds the mint with the higher address, so it is token 1.
+        let balances = [
+            vault_balance("account5", key(9)),
+            vault_balance("account6", key(1)),
+        ];
+        let swap_v2 = data(&[
+            &anchor_discriminator("global", "swap_v2"),
+            &1_000u64.to_le_bytes(),
+            &1_900u64.to_le_bytes(),
+            &7u128.to_le_bytes(),
+            &[1],
+        ]);
+        let swap = parse_clmm_swap_instruction(&swap_v2, &accounts, &balances)
+            .unwrap()
+            .unwrap();
+        assert_eq!(swap.dapp_address, config().programs.raydium_clmm);
+        assert_eq!(
+            (swap.name.as_str(), swap.amm.as_str()),
+            ("SwapBaseIn", "account2")
+        );
+        assert_eq!(
+            (swap.vault_a.as_str(), swap.vault_b.as_str()),
+            ("account6", "account5")
+        );
+        assert_eq!((swap.amount_in, swap.minimum_amount_out), (1_000, 1_900));
+        assert_eq!(swap.sqrt_price_limit_x64, Some(7));
+
+        // With is_base_input false the amount is the output and the threshold a maximum.
+        let swap = data(&[
+            &anchor_discriminator("global", "swap"),
+            &2_000u64.to_le_bytes(),
+            &1_100u64.to_le_bytes(),
+            &0u128.to_le_bytes(),
+            &[0],
+        ]);
+        let swap = parse_clmm_swap_instruction(&swap, &accounts, &balances)
+            .unwrap()
+            .unwrap();
+        assert_eq!(swap.name, "SwapBaseOut");
+        assert_eq!((swap.max_amount_in, swap.amount_out), (1_100, 2_000));
+    }
+
+    #[test]
+    fn clmm_swap_ignores_short_and_unknown_data() {
+        let accounts = accounts(13);
+        assert!(parse_clmm_swap_instruction(&[], &accounts, &[])
+            .unwrap()
+            .is_none());
+        assert!(
+            parse_clmm_swap_instruction(&[248, 198, 158], &accounts, &[])
+                .unwrap()
+                .is_none()
+        );
+        let open_position = anchor_discriminator("global", "open_position_v2");
+        assert!(parse_clmm_swap_instruction(&open_position, &accounts, &[])
+            .unwrap()
+            .is_none());
+        // A swap whose arguments are cut off is malformed.
+        let truncated = data(&[&anchor_discriminator("global", "swap"), &1u64.to_le_bytes()]);
+        assert!(parse_clmm_swap_instruction(&truncated, &accounts, &[]).is_err());
+    }
+
+    #[test]
+    fn clmm_positions_follow_the_instruction_layouts() {
+        let accounts = accounts(22);
+        let open_position_v2 = data(&[
+            &anchor_discriminator("global", "open_position_v2"),
+            &(-120i32).to_le_bytes(),
+            &240i32.to_le_bytes(),
+            &(-3600i32).to_le_bytes(),
+            &0i32.to_le_bytes(),
+            &5_000u128.to_le_bytes(),
+            &100u64.to_le_bytes(),
+            &200u64.to_le_bytes(),
+            // with_metadata and base_flag, not decoded
+            &[1, 0],
+        ]);
+        let ix = parse_clmm_liquidity_instruction(&open_position_v2, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "OpenPositionV2");
+        assert_eq!(ix.pool.as_deref(), Some("account5"));
+        assert_eq!(ix.position_nft_mint.as_deref(), Some("account2"));
+        assert_eq!(ix.position_nft_account.as_deref(), Some("account3"));
+        assert_eq!(ix.vaults, Some(("account12".into(), "account13".into())));
+        assert_eq!(
+            (ix.tick_lower_index, ix.tick_upper_index),
+            (Some(-120), Some(240))
+        );
+        assert_eq!(ix.liquidity, 5_000.0);
+
+        let decrease_liquidity_v2 = data(&[
+            &anchor_discriminator("global", "decrease_liquidity_v2"),
+            &3_000u128.to_le_bytes(),
+            &1u64.to_le_bytes(),
+            &2u64.to_le_bytes(),
+        ]);
+        let ix = parse_clmm_liquidity_instruction(&decrease_liquidity_v2, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(ix.name, "DecreaseLiquidityV2");
+        assert_eq!(ix.pool.as_deref(), Some("account3"));
+        assert_eq!(ix.position_nft_mint, None);
+        assert_eq!(ix.vaults, Some(("account5".into(), "account6".into())));
+        assert_eq!(ix.liquidity, -3_000.0);
+
+        let close_position = anchor_discriminator("global", "close_position");
+        let ix = parse_clmm_liquidity_instruction(&close_position, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!((ix.pool, ix.vaults), (None, None));
+        assert_eq!(ix.position_nft_mint.as_deref(), Some("account1"));
+
+        assert!(parse_clmm_liquidity_instruction(&[], &accounts)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn logged_events_belong_to_the_program_on_top_of_the_stack() {
+        let programs = &config().programs;
+        let (clmm, cpmm) = (&programs.raydium_clmm, &programs.raydium_cpmm);
+        let aggregator = key(30).to_string();
+        let event = |tick| program_data(*SWAP_EVENT, &clmm_swap_event(key(40), tick));
+        let logs = [
+            format!("Program {} invoke [1]", aggregator),
+            format!("Program {} invoke [2]", clmm),
+            format!("Program {} invoke [3]", key(31)),
+            format!("Program {} success", key(31)),
+            // Back in CLMM after its CPI returned.
+            event(-5),
+            format!("Program {} success", clmm),
+            format!("Program {} invoke [2]", cpmm),
+            format!("Program {} failed: custom program error: 0x1", cpmm),
+            // Logged by the aggregator, which only mimics the event.
+            event(6),
+            format!("Program {} success", aggregator),
+        ];
+
+        let events = parse_logged_events(&logs);
+        let swaps = &events.clmm_swaps[&key(40).to_string()];
+        assert_eq!(swaps.len(), 1);
+        let swap = &swaps[0];
+        assert_eq!(swap.sender, key(20));
+        assert_eq!(
+            (swap.amount_0, swap.transfer_fee_0, swap.amount_1),
+            (1_000, 10, 2_000)
+        );
+        assert!(swap.zero_for_one);
+        assert_eq!(
+            (swap.sqrt_price_x64, swap.liquidity, swap.tick),
+            (1 << 64, 5_000, -5)
+        );
+    }
+
+    // CPMM instructions take the pool's mints as accounts 10 and 11.
+    fn cpmm_accounts() -> Vec<String> {
+        (0..13).map(|byte| key(byte).to_string()).collect()
+    }
+
+    #[test]
+    fn cpmm_discriminators_are_anchor_hashes() {
+        let discriminator = |name| {
+            CPMM_INSTRUCTIONS
+                .iter()
+                .find(|(known, _)| *known == name)
+                .map(|(_, discriminator)| *discriminator)
+        };
+        assert_eq!(
+            discriminator("SwapBaseIn"),
+            Some([143, 190, 90, 218, 196, 30, 51, 222])
+        );
+        assert_eq!(
+            discriminator("Initialize"),
+            Some([175, 175, 109, 31, 13, 152, 155, 237])
+        );
+        assert_eq!(
+            discriminator("Deposit"),
+            Some([242, 35, 198, 137, 82, 225, 242, 182])
+        );
+        assert_eq!(
+            discriminator("Withdraw"),
+            Some([183, 18, 70, 156, 148, 109, 161, 34])
+        );
+        assert_eq!(*LP_CHANGE_EVENT, [121, 163, 205, 201, 57, 218, 117, 60]);
+    }
+
+    #[test]
+    fn cpmm_swap_orders_vaults_by_mint() {
+        let mut accounts = cpmm_accounts();
+        let swap_base_input = data(&[
+            &anchor_discriminator("global", "swap_base_input"),
+            &1_000u64.to_le_bytes(),
+            &450u64.to_le_bytes(),
+        ]);
+        let swap = parse_cpmm_swap_instruction(&swap_base_input, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(swap.dapp_address, config().programs.raydium_cpmm);
+        assert_eq!(
+            (swap.name.as_str(), swap.amm.clone()),
+            ("SwapBaseIn", key(3).to_string())
+        );
+        // The input mint sorts first, so the input vault is token 0.
+        assert_eq!(
+            (swap.vault_a, swap.vault_b),
+            (key(6).to_string(), key(7).to_string())
+        );
+        assert_eq!((swap.amount_in, swap.minimum_amount_out), (1_000, 450));
+
+        accounts.swap(10, 11);
+        let swap_base_output = data(&[
+            &anchor_discriminator("global", "swap_base_output"),
+            &1_100u64.to_le_bytes(),
+            &500u64.to_le_bytes(),
+        ]);
+        let swap = parse_cpmm_swap_instruction(&swap_base_output, &accounts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(swap.name, "SwapBaseOut");
+        assert_eq!(
+            (swap.vault_a, swap.vault_b),
+            (key(7).to_string(), key(6).to_string())
+        );
+        assert_eq!((swap.max_amount_in, swap.amount_out), (1_100, 500));
+
+        assert!(parse_cpmm_swap_instruction(&[143, 190], &accounts)
+            .unwrap()
+            .is_none());
+        let truncated = &swap_base_output[..12];
+        assert!(parse_cpmm_swap_instruction(truncated, &accounts).is_err());
+    }
+
+    #[test]
+    fn cpmm_liquidity_follows_the_instruction_layouts() {
+        let accounts = cpmm_accounts();
+        let table = |name| {
+            let ix =
+                parse_cpmm_liquidity_instruction(&anchor_discriminator("global", name), &accounts)
+                    .unwrap()
+                    .unwrap();
+            (ix.name, ix.amm, ix.lp_mint, ix.base_vault, ix.quote_vault)
+        };
+        let key = |byte| key(byte).to_string();
+        assert_eq!(
+            table("initialize"),
+            ("Initialize".into(), key(3), Some(key(6)), key(10), key(11))
+        );
+        assert_eq!(
+            table("deposit"),
+            ("Deposit".into(), key(2), Some(key(12)), key(6), key(7))
+        );
+        assert_eq!(
+            table("withdraw"),
+            ("Withdraw".into(), key(2), Some(key(12)), key(6), key(7))
+        );
+
+        let swap = anchor_discriminator("global", "swap_base_input");
+        assert!(parse_cpmm_liquidity_instruction(&swap, &accounts)
+            .unwrap()
+            .is_none());
+        assert!(parse_cpmm_liquidity_instruction(&[], &accounts)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cpmm_initialize_creates_a_pool() {
+        let accounts = cpmm_accounts();
+        let initialize = data(&[
+            &anchor_discriminator("global", "initialize"),
+            &1_000u64.to_le_bytes(),
+            &2_000u64.to_le_bytes(),
+            &1_738_195_200u64.to_le_bytes(),
+        ]);
+        let pool = parse_cpmm_pool_init_instruction(&initialize, &accounts)
+            .unwrap()
+            .unwrap();
+        let key = |byte| key(byte).to_string();
+        assert_eq!(pool.program, config().programs.raydium_cpmm);
+        assert_eq!(
+            (pool.amm, pool.lp_mint, pool.creator),
+            (key(3), key(6), key(0))
+        );
+        assert_eq!((pool.base_mint, pool.quote_mint), (key(4), key(5)));
+        assert_eq!((pool.base_vault, pool.quote_vault), (key(10), key(11)));
+        assert_eq!(
+            (pool.init_base_amount, pool.init_quote_amount),
+            (1_000, 2_000)
+        );
+        assert_eq!(pool.open_time, 1_738_195_200);
+
+        assert!(parse_cpmm_pool_init_instruction(&initialize[..8 + 16], &accounts).is_err());
+        assert!(
+            parse_cpmm_pool_init_instruction(&initialize[..4], &accounts)
+                .unwrap()
+                .is_none()
+        );
+        let deposit = anchor_discriminator("global", "deposit");
+        assert!(parse_cpmm_pool_init_instruction(&deposit, &accounts)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn cpmm_logs_swap_and_lp_change_events() {
+        let cpmm = &config().programs.raydium_cpmm;
+        let pool = key(40);
+        let swap = data(&[
+            pool.as_ref(),
+            &10_000_000u64.to_le_bytes(),
+            &10_000_000u64.to_le_bytes(),
+            &1_000_000u64.to_le_bytes(),
+            &500_000u64.to_le_bytes(),
+            &10_000u64.to_le_bytes(),
+            &5_000u64.to_le_bytes(),
+            &[1],
+        ]);
+        let lp_change = data(&[
+            pool.as_ref(),
+            &7_000_000u64.to_le_bytes(),
+            &10_000_000u64.to_le_bytes(),
+            &10_000_000u64.to_le_bytes(),
+            &1_000_000u64.to_le_bytes(),
+            &2_000_000u64.to_le_bytes(),
+            &0u64.to_le_bytes(),
+            &20_000u64.to_le_bytes(),
+            &[0],
+        ]);
+        let logs = [
+            format!("Program {} invoke [1]", cpmm),
+            program_data(*SWAP_EVENT, &swap),
+            program_data(*LP_CHANGE_EVENT, &lp_change),
+            format!("Program {} success", cpmm),
+        ];
+
+        let events = parse_logged_events(&logs);
+        assert!(events.clmm_swaps.is_empty());
+        let swap = &events.cpmm_swaps[&pool.to_string()][0];
+        assert_eq!(
+            (swap.input_amount, swap.output_amount),
+            (1_000_000, 500_000)
+        );
+        assert_eq!(
+            (swap.input_transfer_fee, swap.output_transfer_fee),
+            (10_000, 5_000)
+        );
+        assert!(swap.base_input);
+        let lp_change = &events.cpmm_lp_changes[&pool.to_string()][0];
+        assert_eq!(lp_change.lp_amount_before, 7_000_000);
+        assert_eq!(
+            (lp_change.token_0_amount, lp_change.token_1_amount),
+            (1_000_000, 2_000_000)
+        );
+        assert_eq!(
+            (
+                lp_change.token_0_transfer_fee,
+                lp_change.token_1_transfer_fee
+            ),
+            (0, 20_000)
+        );
+        assert_eq!(lp_change.change_type, 0);
+    }
 }
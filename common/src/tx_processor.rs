@@ -1,140 +1,148 @@
+use std::sync::Mutex;
+
 use anyhow::Result;
-use borsh::BorshDeserialize;
-use solana_sdk::{address_lookup_table::program, bs58};
+use solana_sdk::bs58;
 use solana_transaction_status::{
-    EncodedConfirmedBlock, EncodedTransactionWithStatusMeta, UiInnerInstructions, UiInstruction,
-    UiParsedInstruction,
+    EncodedTransaction, EncodedTransactionWithStatusMeta, UiAddressTableLookup,
+    UiCompiledInstruction, UiInnerInstructions, UiInstruction, UiLoadedAddresses, UiMessage,
+    UiRawMessage, UiTransactionTokenBalance,
 };
-use spl_token::instruction::TokenInstruction;
+use tracing::debug;
 
 use crate::{
-    models::{PoolData, TokenBalance, TradeData, UiTokenAmount},
-    trade_parser::get_trade_instruction,
-    utils::{convert_to_date, get_amount, get_amt, get_mint, get_signer_balance_change},
+    config::config,
+    decode_error::DecodeError,
+    models::{
+        DecodedEvents, LiquidityEvent, LiquidityInstruction, LoggedEvents, PoolCreated,
+        PoolInitInstruction, TokenBalance, TradeData, TradeInstruction, UiTokenAmount,
+    },
+    trade_parser::{
+        get_trade_instruction, parse_clmm_liquidity_instruction, parse_clmm_swap_instruction,
+        parse_cpmm_liquidity_instruction, parse_cpmm_pool_init_instruction,
+        parse_cpmm_swap_instruction, parse_liquidity_instruction, parse_logged_events,
+        parse_pool_init_instruction,
+    },
+    utils::{
+        convert_to_date, get_amount, get_mint, get_signer_balance_change,
+        get_supply_change, get_vault_change, prepare_input_accounts, to_ui_amount,
+    },
 };
 
-const RAYDIUM_PROGRAM_ID: &str = "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8";
-const JUPITER_PROGRAM_ID: &str = "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4";
-const SERUM_ADD: &str = "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX";
-
+/// Decodes the Raydium (AMM v4, CLMM and CPMM) trades, liquidity events and new pools
+/// of one transaction. Failed transactions have none; malformed ones are reported as a
+/// `DecodeError` so the rest of the block still decodes.
 pub async fn process_tx(
     trx: EncodedTransactionWithStatusMeta,
     slot: u64,
     timestamp: i64,
-) -> Option<Vec<TradeData>> {
-    let trx_meta = trx.meta.unwrap();
+) -> Result<DecodedEvents, DecodeError> {
+    let trx_meta = trx.meta.ok_or(DecodeError::MissingMeta)?;
     if trx_meta.err.is_some() {
-        return None;
+        return Ok(DecodedEvents::default());
     }
 
-    let transaction = trx.transaction.clone();
-    let ui = match transaction {
-        solana_transaction_status::EncodedTransaction::Json(ui_transaction) => ui_transaction,
-        _ => return None,
-    };
-
-    let signature = ui.signatures[0].clone();
-
-    let msg = match ui.message {
-        solana_transaction_status::UiMessage::Raw(raw_msg) => raw_msg,
-        _ => return None,
-    };
+    let (signature, msg) = raw_message(trx.transaction)?;
 
     let accounts = msg.account_keys;
 
     let mut all_addresses = accounts.clone();
 
-    let loaded_addresses = trx_meta.loaded_addresses.clone();
+    if let Some(loaded_addresses) = Option::<UiLoadedAddresses>::from(trx_meta.loaded_addresses) {
+        all_addresses.extend(loaded_addresses.writable);
+        all_addresses.extend(loaded_addresses.readonly);
+    }
+
+    let trx_meta_inner: Vec<UiInnerInstructions> =
+        Option::from(trx_meta.inner_instructions).unwrap_or_default();
 
-    if loaded_addresses.is_some() {
-        let loaded_addresses = loaded_addresses.unwrap();
-        loaded_addresses.writable.iter().for_each(|add| {
-            all_addresses.push(add.clone());
+    // Most transactions never reach Raydium; their balances are not looked at.
+    let programs = &config().programs;
+    let raydium = programs.raydium();
+    let inner_program_ids = trx_meta_inner
+        .iter()
+        .flat_map(|inner| inner.instructions.iter())
+        .filter_map(|inner_inst| match inner_inst {
+            UiInstruction::Compiled(compiled) => Some(compiled.program_id_index),
+            _ => None,
         });
-        loaded_addresses.readonly.iter().for_each(|add| {
-            all_addresses.push(add.clone());
+    let calls_raydium = msg
+        .instructions
+        .iter()
+        .map(|inst| inst.program_id_index)
+        .chain(inner_program_ids)
+        .any(|index| {
+            all_addresses
+                .get(index as usize)
+                .is_some_and(|program| raydium.contains(program))
         });
+    if !calls_raydium {
+        return Ok(DecodedEvents::default());
     }
 
     let pre_balances = trx_meta.pre_balances;
     let post_balances = trx_meta.post_balances;
-    let pre_token_balances = trx_meta
-        .pre_token_balances
-        .expect("Pre token balances not found");
-    // convert to Vec<TokenBalance>
-    let mut pre_token_balances_vec: Vec<TokenBalance> = vec![];
-    for (idx, balance) in pre_token_balances.iter().enumerate() {
-        let token_balance = TokenBalance {
-            account_index: idx as u32,
-            address: all_addresses
-                .get(balance.account_index as usize)
-                .unwrap_or(&"".to_string())
-                .to_string(),
-            mint: balance.mint.clone(),
-            ui_token_amount: UiTokenAmount {
-                ui_amount: balance.ui_token_amount.ui_amount.unwrap_or(0.0),
-                decimals: balance.ui_token_amount.decimals as u32,
-                amount: balance.ui_token_amount.amount.clone(),
-                ui_amount_string: balance.ui_token_amount.ui_amount_string.clone(),
-            },
-            owner: balance.owner.clone().unwrap(),
-            program_id: balance.program_id.clone().unwrap(),
-        };
-        pre_token_balances_vec.push(token_balance);
-    }
-
-    let post_token_balances = trx_meta
-        .post_token_balances
-        .expect("Post token balances not found");
-    let mut post_token_balances_vec: Vec<TokenBalance> = vec![];
-    for (idx, balance) in post_token_balances.iter().enumerate() {
-        let token_balance = TokenBalance {
-            account_index: idx as u32,
-            address: all_addresses
-                .get(balance.account_index as usize)
-                .unwrap_or(&"".to_string())
-                .to_string(),
-            mint: balance.mint.clone(),
-            ui_token_amount: UiTokenAmount {
-                ui_amount: balance.ui_token_amount.ui_amount.unwrap_or(0.0),
-                decimals: balance.ui_token_amount.decimals as u32,
-                amount: balance.ui_token_amount.amount.clone(),
-                ui_amount_string: balance.ui_token_amount.ui_amount_string.clone(),
-            },
-            owner: balance.owner.clone().unwrap(),
-            program_id: balance.program_id.clone().unwrap(),
-        };
-        post_token_balances_vec.push(token_balance);
-    }
+    let pre_token_balances: Vec<UiTransactionTokenBalance> =
+        Option::from(trx_meta.pre_token_balances)
+            .ok_or(DecodeError::MissingTokenBalances("pre"))?;
+    let pre_token_balances_vec = convert_token_balances(&pre_token_balances, &all_addresses);
 
-    let mut trades = vec![];
+    let post_token_balances: Vec<UiTransactionTokenBalance> =
+        Option::from(trx_meta.post_token_balances)
+            .ok_or(DecodeError::MissingTokenBalances("post"))?;
+    let post_token_balances_vec = convert_token_balances(&post_token_balances, &all_addresses);
 
-    for (idx, inst) in msg.instructions.into_iter().enumerate() {
-        let trx_meta_inner = trx_meta.inner_instructions.clone().unwrap_or(vec![]);
-        let fee = trx_meta.fee;
+    let fee = trx_meta.fee;
+    let log_messages: Vec<String> = Option::from(trx_meta.log_messages).unwrap_or_default();
 
-        let decoded_data = bs58::decode(inst.data.clone()).into_vec().unwrap();
+    let tx = TxContext {
+        accounts: &all_addresses,
+        pre_token_balances: &pre_token_balances_vec,
+        post_token_balances: &post_token_balances_vec,
+        pre_balances: &pre_balances,
+        post_balances: &post_balances,
+        fee,
+        timestamp,
+        slot,
+        signature: &signature,
+        logged: Mutex::new(parse_logged_events(&log_messages)),
+    };
+    let mut events = DecodedEvents::default();
 
-        let main_program = all_addresses.get(inst.program_id_index as usize).unwrap();
+    for (idx, inst) in msg.instructions.into_iter().enumerate() {
+        let main_program = address_at(&all_addresses, inst.program_id_index as usize)?;
+        // Only Raydium's own instruction data is decoded; other programs' may be anything.
+        let decode_data = || bs58::decode(&inst.data).into_vec();
 
         match main_program.as_str() {
-            RAYDIUM_PROGRAM_ID => {
-                // standard raydium - srmq add
-                if let Some(pos) = inst
-                    .accounts
-                    .iter()
-                    .position(|&ix| all_addresses[ix as usize] == SERUM_ADD)
+            program if program == programs.raydium_amm => {
+                let decoded_data = decode_data()?;
+                if let Some(pool) =
+                    build_pool_created(&tx, &decoded_data, &inst.accounts, idx, main_program, false)
+                        .await?
+                {
+                    events.pools.push(pool);
+                }
+                if let Some(event) =
+                    build_liquidity_event(&tx, &decoded_data, &inst.accounts, idx, main_program, false)
+                        .await?
                 {
+                    events.liquidity.push(event);
+                } else if let Some(pos) = inst.accounts.iter().position(|&ix| {
+                    // standard raydium swap - srmq add
+                    all_addresses.get(ix as usize).map(String::as_str) == Some(programs.serum.as_str())
+                }).filter(|_| is_amm_v4_swap(&decoded_data)) {
                     // no extra checks, just do pos-2 and pos-1 as before
-                    let base_add = all_addresses
-                        .get(inst.accounts[pos - 2] as usize)
-                        .expect("Base account not found")
-                        .clone();
+                    let base_add =
+                        instruction_account(&inst.accounts, &all_addresses, pos.checked_sub(2))?;
+                    let quote_add =
+                        instruction_account(&inst.accounts, &all_addresses, pos.checked_sub(1))?;
 
-                    let quote_add = all_addresses
-                        .get(inst.accounts[pos - 1] as usize)
-                        .expect("Quote account not found")
-                        .clone();
+                    // The swap's token transfers, listed under its own instruction index.
+                    let inner_instructions = &trx_meta_inner
+                        .iter()
+                        .find(|inner| inner.index as usize == idx)
+                        .ok_or(DecodeError::MissingInnerInstructions(idx))?
+                        .instructions;
 
                     if let Some(trade) = build_trade_data(
                         main_program,
@@ -145,10 +153,7 @@ pub async fn process_tx(
                         &post_token_balances_vec,
                         &base_add,
                         &quote_add,
-                        &trx_meta_inner
-                            .first()
-                            .expect("Inner instructions not found")
-                            .instructions,
+                        inner_instructions,
                         timestamp,
                         slot,
                         &signature,
@@ -157,12 +162,22 @@ pub async fn process_tx(
                         &pre_balances,
                         &post_balances,
                         fee,
-                    ).await {
-                        trades.push(trade);
+                    ).await? {
+                        events.trades.push(trade);
                     }
                 }
             }
-            JUPITER_PROGRAM_ID => {
+            program if program == programs.raydium_clmm => {
+                let decoded_data = decode_data()?;
+                decode_clmm(&tx, &decoded_data, &inst.accounts, idx, main_program, false, &mut events)
+                    .await?;
+            }
+            program if program == programs.raydium_cpmm => {
+                let decoded_data = decode_data()?;
+                decode_cpmm(&tx, &decoded_data, &inst.accounts, idx, main_program, false, &mut events)
+                    .await?;
+            }
+            program if program == programs.jupiter => {
                 for inner in trx_meta_inner.iter() {
                     for inner_inst in inner.instructions.iter() {
                         if let solana_transaction_status::UiInstruction::Compiled(compiled) = inner_inst {
@@ -170,10 +185,13 @@ pub async fn process_tx(
                                 Ok(data) => data,
                                 Err(_) => continue,
                             };
-                            let program_add = all_addresses.get(compiled.program_id_index as usize)?;
-                            if program_add == RAYDIUM_PROGRAM_ID {
-                                let base_add = all_addresses.get(compiled.accounts[4] as usize)?.clone();
-                                let quote_add = all_addresses.get(compiled.accounts[5] as usize)?.clone();
+                            let program_add = address_at(&all_addresses, compiled.program_id_index as usize)?;
+                            // Routes also call the AMM for things other than swaps.
+                            if *program_add == programs.raydium_amm
+                                && is_amm_v4_swap(&program_data)
+                            {
+                                let base_add = instruction_account(&compiled.accounts, &all_addresses, Some(4))?;
+                                let quote_add = instruction_account(&compiled.accounts, &all_addresses, Some(5))?;
                                 if let Some(trade) = build_trade_data(
                                     program_add,
                                     &program_data,
@@ -192,8 +210,8 @@ pub async fn process_tx(
                                     &pre_balances,
                                     &post_balances,
                                     fee,
-                                ).await {
-                                    trades.push(trade);
+                                ).await? {
+                                    events.trades.push(trade);
                                 }
                             }
                         }
@@ -203,8 +221,540 @@ pub async fn process_tx(
 
             _ => {}
         };
+
+        // Pools are also created and funded through other programs (e.g. launchpad
+        // migrations), which call Raydium from their own instruction; aggregators
+        // route swaps through CLMM and CPMM the same way.
+        let inner_instructions = trx_meta_inner
+            .iter()
+            .filter(|inner| inner.index as usize == idx)
+            .flat_map(|inner| inner.instructions.iter());
+        for inner_inst in inner_instructions {
+            let UiInstruction::Compiled(compiled) = inner_inst else {
+                continue;
+            };
+            let inner_program = address_at(&all_addresses, compiled.program_id_index as usize)?;
+            if inner_program == main_program || !programs.raydium().contains(inner_program) {
+                continue;
+            }
+            let Ok(program_data) = bs58::decode(&compiled.data).into_vec() else {
+                continue;
+            };
+            if *inner_program == programs.raydium_clmm {
+                decode_clmm(&tx, &program_data, &compiled.accounts, idx, main_program, true, &mut events)
+                    .await?;
+                continue;
+            }
+            if *inner_program == programs.raydium_cpmm {
+                decode_cpmm(&tx, &program_data, &compiled.accounts, idx, main_program, true, &mut events)
+                    .await?;
+                continue;
+            }
+            if let Some(pool) =
+                build_pool_created(&tx, &program_data, &compiled.accounts, idx, main_program, true)
+                    .await?
+            {
+                events.pools.push(pool);
+            }
+            if let Some(event) =
+                build_liquidity_event(&tx, &program_data, &compiled.accounts, idx, main_program, true)
+                    .await?
+            {
+                events.liquidity.push(event);
+            }
+        }
+    }
+    Ok(events)
+}
+
+/// The first signature of a transaction in any encoding, for reporting failures.
+pub fn transaction_signature(transaction: &EncodedTransaction) -> Option<String> {
+    match transaction {
+        EncodedTransaction::Json(ui) => ui.signatures.first().cloned(),
+        _ => transaction
+            .decode()?
+            .signatures
+            .first()
+            .map(|signature| signature.to_string()),
+    }
+}
+
+// Owner and program id are missing from older blocks (e.g. early epoch archives) and
+// are left empty then.
+fn convert_token_balances(
+    balances: &[UiTransactionTokenBalance],
+    all_addresses: &[String],
+) -> Vec<TokenBalance> {
+    // convert to Vec<TokenBalance>
+    let mut token_balances: Vec<TokenBalance> = vec![];
+    for (idx, balance) in balances.iter().enumerate() {
+        let token_balance = TokenBalance {
+            account_index: idx as u32,
+            address: all_addresses
+                .get(balance.account_index as usize)
+                .cloned()
+                .unwrap_or_default(),
+            mint: balance.mint.clone(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: balance.ui_token_amount.ui_amount.unwrap_or(0.0),
+                decimals: balance.ui_token_amount.decimals as u32,
+                amount: balance.ui_token_amount.amount.clone(),
+                ui_amount_string: balance.ui_token_amount.ui_amount_string.clone(),
+            },
+            owner: Option::from(balance.owner.clone()).unwrap_or_default(),
+            program_id: Option::from(balance.program_id.clone()).unwrap_or_default(),
+        };
+        token_balances.push(token_balance);
+    }
+    token_balances
+}
+
+// AMM v4 swap_base_in (tag 9) and swap_base_out (11); its other instructions are no
+// trades.
+fn is_amm_v4_swap(data: &[u8]) -> bool {
+    matches!(data.first(), Some(9 | 11))
+}
+
+fn address_at(all_addresses: &[String], index: usize) -> Result<&String, DecodeError> {
+    all_addresses.get(index).ok_or(DecodeError::AccountIndex(index))
+}
+
+// The address of the instruction account at `position`, if the instruction has one.
+fn instruction_account(
+    instruction_accounts: &[u8],
+    all_addresses: &[String],
+    position: Option<usize>,
+) -> Result<String, DecodeError> {
+    let index = position
+        .and_then(|position| instruction_accounts.get(position))
+        .ok_or(DecodeError::MissingAccount(position.unwrap_or(0)))?;
+    address_at(all_addresses, *index as usize).cloned()
+}
+
+/// The first signature and the compiled message of a transaction fetched either as JSON
+/// or as base64/base58, which is decoded here. Lookup-table addresses are not part of
+/// the message; they come from the `loaded_addresses` in the meta.
+fn raw_message(transaction: EncodedTransaction) -> Result<(String, UiRawMessage), DecodeError> {
+    if let EncodedTransaction::Json(ui) = transaction {
+        let signature = ui.signatures.first().ok_or(DecodeError::MissingSignature)?.clone();
+        return match ui.message {
+            UiMessage::Raw(raw_msg) => Ok((signature, raw_msg)),
+            _ => Err(DecodeError::UnsupportedEncoding),
+        };
+    }
+
+    let transaction = transaction.decode().ok_or(DecodeError::UnsupportedEncoding)?;
+    let message = &transaction.message;
+    let header = *message.header();
+    let raw_msg = UiRawMessage {
+        header,
+        account_keys: message
+            .static_account_keys()
+            .iter()
+            .map(|key| key.to_string())
+            .collect(),
+        recent_blockhash: message.recent_blockhash().to_string(),
+        instructions: message
+            .instructions()
+            .iter()
+            .map(|ix| UiCompiledInstruction::from(ix, None))
+            .collect(),
+        address_table_lookups: message.address_table_lookups().map(|lookups| {
+            lookups.iter().map(UiAddressTableLookup::from).collect()
+        }),
+    };
+    let signature = transaction
+        .signatures
+        .first()
+        .ok_or(DecodeError::MissingSignature)?;
+    Ok((signature.to_string(), raw_msg))
+}
+
+// Transaction-wide inputs of the per-instruction event builders.
+struct TxContext<'a> {
+    accounts: &'a Vec<String>,
+    pre_token_balances: &'a Vec<TokenBalance>,
+    post_token_balances: &'a Vec<TokenBalance>,
+    pre_balances: &'a Vec<u64>,
+    post_balances: &'a Vec<u64>,
+    fee: u64,
+    timestamp: i64,
+    slot: u64,
+    signature: &'a String,
+    // Taken by the builders as they decode the instructions the events belong to.
+    logged: Mutex<LoggedEvents>,
+}
+
+// Extras of a CLMM or CPMM swap beyond its instruction; transfer fees are raw.
+#[derive(Default)]
+struct SwapDetails {
+    sqrt_price_x64: Option<u128>,
+    tick: Option<i32>,
+    base_transfer_fee: u64,
+    quote_transfer_fee: u64,
+}
+
+async fn decode_clmm(
+    tx: &TxContext<'_>,
+    decoded_data: &[u8],
+    inst_accounts: &Vec<u8>,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+    events: &mut DecodedEvents,
+) -> Result<(), DecodeError> {
+    let input_accounts = prepare_input_accounts(inst_accounts, tx.accounts);
+    if let Some(td) = parse_clmm_swap_instruction(decoded_data, &input_accounts, tx.post_token_balances)? {
+        // Logs can be truncated, in which case the swap has no event and no price.
+        let swap_event = tx
+            .logged
+            .lock()
+            .unwrap()
+            .clmm_swaps
+            .get_mut(&td.amm)
+            .and_then(|events| events.pop_front());
+        let details = swap_event
+            .map(|event| SwapDetails {
+                sqrt_price_x64: Some(event.sqrt_price_x64),
+                tick: Some(event.tick),
+                base_transfer_fee: event.transfer_fee_0,
+                quote_transfer_fee: event.transfer_fee_1,
+            })
+            .unwrap_or_default();
+        events
+            .trades
+            .push(pool_trade(tx, td, idx, outer_program, is_inner, details).await?);
+    } else if let Some(event) = build_clmm_liquidity_event(tx, decoded_data, inst_accounts, idx, outer_program, is_inner).await? {
+        events.liquidity.push(event);
+    }
+    Ok(())
+}
+
+async fn decode_cpmm(
+    tx: &TxContext<'_>,
+    decoded_data: &[u8],
+    inst_accounts: &Vec<u8>,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+    events: &mut DecodedEvents,
+) -> Result<(), DecodeError> {
+    let input_accounts = prepare_input_accounts(inst_accounts, tx.accounts);
+    if let Some(td) = parse_cpmm_swap_instruction(decoded_data, &input_accounts)? {
+        let swap_event = tx
+            .logged
+            .lock()
+            .unwrap()
+            .cpmm_swaps
+            .get_mut(&td.amm)
+            .and_then(|events| events.pop_front());
+        // The event reports fees by direction; the vault that grew took the input.
+        let mut details = SwapDetails::default();
+        if let Some(event) = swap_event {
+            let base_is_input =
+                get_vault_change(&td.vault_a, tx.pre_token_balances, tx.post_token_balances)? > 0.0;
+            (details.base_transfer_fee, details.quote_transfer_fee) = if base_is_input {
+                (event.input_transfer_fee, event.output_transfer_fee)
+            } else {
+                (event.output_transfer_fee, event.input_transfer_fee)
+            };
+        }
+        events
+            .trades
+            .push(pool_trade(tx, td, idx, outer_program, is_inner, details).await?);
+        return Ok(());
+    }
+
+    if let Some(ix) = parse_cpmm_pool_init_instruction(decoded_data, &input_accounts)? {
+        events
+            .pools
+            .push(pool_created(tx, ix, idx, outer_program, is_inner).await?);
+    }
+    if let Some(ix) = parse_cpmm_liquidity_instruction(decoded_data, &input_accounts)? {
+        let mut event = liquidity_event(tx, ix, idx, outer_program, is_inner).await?;
+        if event.event_type != "Initialize" {
+            let lp_change = tx
+                .logged
+                .lock()
+                .unwrap()
+                .cpmm_lp_changes
+                .get_mut(&event.pool_address)
+                .and_then(|events| events.pop_front());
+            if let Some(lp_change) = lp_change {
+                event.base_transfer_fee =
+                    to_ui_amount(lp_change.token_0_transfer_fee, &event.base_vault, tx.post_token_balances)?;
+                event.quote_transfer_fee =
+                    to_ui_amount(lp_change.token_1_transfer_fee, &event.quote_vault, tx.post_token_balances)?;
+            }
+        }
+        events.liquidity.push(event);
+    }
+    Ok(())
+}
+
+async fn build_liquidity_event(
+    tx: &TxContext<'_>,
+    decoded_data: &[u8],
+    inst_accounts: &Vec<u8>,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+) -> Result<Option<LiquidityEvent>, DecodeError> {
+    let input_accounts = prepare_input_accounts(inst_accounts, tx.accounts);
+    let Some(ix) = parse_liquidity_instruction(decoded_data, &input_accounts)? else {
+        return Ok(None);
+    };
+    Ok(Some(liquidity_event(tx, ix, idx, outer_program, is_inner).await?))
+}
+
+// A liquidity event from vault and LP supply changes, shared by AMM v4 and CPMM.
+async fn liquidity_event(
+    tx: &TxContext<'_>,
+    ix: LiquidityInstruction,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+) -> Result<LiquidityEvent, DecodeError> {
+    let lp_amount = match &ix.lp_mint {
+        Some(lp_mint) => get_supply_change(lp_mint, tx.pre_token_balances, tx.post_token_balances),
+        None => 0.0,
+    };
+    let event = LiquidityEvent {
+        block_date: convert_to_date(tx.timestamp).await?,
+        block_time: tx.timestamp,
+        block_slot: tx.slot,
+        signature: tx.signature.to_string(),
+        signer: tx.accounts.first().ok_or(DecodeError::AccountIndex(0))?.to_string(),
+        pool_address: ix.amm,
+        event_type: ix.name,
+        base_mint: get_mint(&ix.base_vault, tx.post_token_balances)
+            .await
+            .ok_or_else(|| DecodeError::MissingVaultBalance(ix.base_vault.clone()))?,
+        quote_mint: get_mint(&ix.quote_vault, tx.post_token_balances)
+            .await
+            .ok_or_else(|| DecodeError::MissingVaultBalance(ix.quote_vault.clone()))?,
+        base_amount: get_vault_change(&ix.base_vault, tx.pre_token_balances, tx.post_token_balances)?,
+        quote_amount: get_vault_change(&ix.quote_vault, tx.pre_token_balances, tx.post_token_balances)?,
+        base_vault: ix.base_vault,
+        quote_vault: ix.quote_vault,
+        lp_mint: ix.lp_mint.unwrap_or_default(),
+        lp_amount,
+        is_inner_instruction: is_inner,
+        instruction_index: idx as u32,
+        outer_program: outer_program.to_string(),
+        tick_lower_index: None,
+        tick_upper_index: None,
+        base_transfer_fee: 0.0,
+        quote_transfer_fee: 0.0,
+    };
+
+    debug!(
+        slot = tx.slot,
+        signature = %tx.signature,
+        pool = %event.pool_address,
+        event = %event.event_type,
+        "Decoded liquidity event"
+    );
+    Ok(event)
+}
+
+// A trade of a CLMM or CPMM pool. The swap limits are checked by the program on the
+// trader's side of the transfer fees, so they are compared with the vault changes plus
+// the fees.
+async fn pool_trade(
+    tx: &TxContext<'_>,
+    td: TradeInstruction,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+    details: SwapDetails,
+) -> Result<TradeData, DecodeError> {
+    let base_amount = get_amount(&td.vault_a, tx.pre_token_balances, tx.post_token_balances).await?;
+    let quote_amount = get_amount(&td.vault_b, tx.pre_token_balances, tx.post_token_balances).await?;
+    let base_transfer_fee = to_ui_amount(details.base_transfer_fee, &td.vault_a, tx.post_token_balances)?;
+    let quote_transfer_fee = to_ui_amount(details.quote_transfer_fee, &td.vault_b, tx.post_token_balances)?;
+    let limits = swap_limits(
+        &td,
+        base_amount + base_transfer_fee,
+        quote_amount + quote_transfer_fee,
+        tx.post_token_balances,
+    )?;
+
+    let trade = TradeData {
+        block_date: convert_to_date(tx.timestamp).await?,
+        tx_id: bs58::encode(tx.signature).into_string(),
+        block_slot: tx.slot,
+        block_time: tx.timestamp,
+        signature: tx.signature.to_string(),
+        signer: tx.accounts.first().ok_or(DecodeError::AccountIndex(0))?.to_string(),
+        pool_address: td.amm,
+        base_mint: get_mint(&td.vault_a, tx.post_token_balances)
+            .await
+            .ok_or_else(|| DecodeError::MissingVaultBalance(td.vault_a.clone()))?,
+        quote_mint: get_mint(&td.vault_b, tx.post_token_balances)
+            .await
+            .ok_or_else(|| DecodeError::MissingVaultBalance(td.vault_b.clone()))?,
+        base_amount,
+        quote_amount,
+        base_vault: td.vault_a,
+        quote_vault: td.vault_b,
+        is_inner_instruction: is_inner,
+        instruction_index: idx as u32,
+        instruction_type: td.name,
+        inner_instruction_index: 0,
+        outer_program: outer_program.to_string(),
+        inner_program: if is_inner { td.dapp_address } else { "".to_string() },
+        txn_fee_lamports: tx.fee,
+        signer_lamports_change: get_signer_balance_change(tx.pre_balances, tx.post_balances).await?,
+        amount_in: limits.amount_in,
+        minimum_amount_out: limits.minimum_amount_out,
+        max_amount_in: limits.max_amount_in,
+        amount_out: limits.amount_out,
+        slippage_tolerance: limits.slippage_tolerance,
+        fill_vs_limit: limits.fill_vs_limit,
+        sqrt_price_limit_x64: td.sqrt_price_limit_x64.map(|limit| limit.to_string()),
+        sqrt_price_x64: details.sqrt_price_x64.map(|price| price.to_string()),
+        tick: details.tick,
+        base_transfer_fee,
+        quote_transfer_fee,
+    };
+
+    debug!(
+        slot = tx.slot,
+        signature = %tx.signature,
+        pool = %trade.pool_address,
+        instruction = %trade.instruction_type,
+        "Decoded pool trade"
+    );
+    Ok(trade)
+}
+
+async fn build_clmm_liquidity_event(
+    tx: &TxContext<'_>,
+    decoded_data: &[u8],
+    inst_accounts: &Vec<u8>,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+) -> Result<Option<LiquidityEvent>, DecodeError> {
+    let input_accounts = prepare_input_accounts(inst_accounts, tx.accounts);
+    let Some(ix) = parse_clmm_liquidity_instruction(decoded_data, &input_accounts)? else {
+        return Ok(None);
+    };
+
+    // Only the open instructions name the NFT mint; otherwise it's the mint of the NFT
+    // account, which may be gone after the position is closed.
+    let mut position_nft_mint = ix.position_nft_mint;
+    if let (None, Some(account)) = (&position_nft_mint, &ix.position_nft_account) {
+        position_nft_mint = match get_mint(account, tx.post_token_balances).await {
+            Some(mint) => Some(mint),
+            None => get_mint(account, tx.pre_token_balances).await,
+        };
     }
-    Some(trades)
+
+    let (base_vault, quote_vault) = ix.vaults.unwrap_or_default();
+    let (base_mint, quote_mint, base_amount, quote_amount) = if base_vault.is_empty() {
+        (String::new(), String::new(), 0.0, 0.0)
+    } else {
+        (
+            get_mint(&base_vault, tx.post_token_balances)
+                .await
+                .ok_or_else(|| DecodeError::MissingVaultBalance(base_vault.clone()))?,
+            get_mint(&quote_vault, tx.post_token_balances)
+                .await
+                .ok_or_else(|| DecodeError::MissingVaultBalance(quote_vault.clone()))?,
+            get_vault_change(&base_vault, tx.pre_token_balances, tx.post_token_balances)?,
+            get_vault_change(&quote_vault, tx.pre_token_balances, tx.post_token_balances)?,
+        )
+    };
+
+    let event = LiquidityEvent {
+        block_date: convert_to_date(tx.timestamp).await?,
+        block_time: tx.timestamp,
+        block_slot: tx.slot,
+        signature: tx.signature.to_string(),
+        signer: tx.accounts.first().ok_or(DecodeError::AccountIndex(0))?.to_string(),
+        pool_address: ix.pool.unwrap_or_default(),
+        event_type: ix.name,
+        base_mint,
+        quote_mint,
+        base_vault,
+        quote_vault,
+        base_amount,
+        quote_amount,
+        lp_mint: position_nft_mint.unwrap_or_default(),
+        lp_amount: ix.liquidity,
+        is_inner_instruction: is_inner,
+        instruction_index: idx as u32,
+        outer_program: outer_program.to_string(),
+        tick_lower_index: ix.tick_lower_index,
+        tick_upper_index: ix.tick_upper_index,
+        base_transfer_fee: 0.0,
+        quote_transfer_fee: 0.0,
+    };
+
+    debug!(
+        slot = tx.slot,
+        signature = %tx.signature,
+        pool = %event.pool_address,
+        event = %event.event_type,
+        "Decoded CLMM liquidity event"
+    );
+    Ok(Some(event))
+}
+
+async fn build_pool_created(
+    tx: &TxContext<'_>,
+    decoded_data: &[u8],
+    inst_accounts: &Vec<u8>,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+) -> Result<Option<PoolCreated>, DecodeError> {
+    let input_accounts = prepare_input_accounts(inst_accounts, tx.accounts);
+    let Some(ix) = parse_pool_init_instruction(decoded_data, &input_accounts)? else {
+        return Ok(None);
+    };
+    Ok(Some(pool_created(tx, ix, idx, outer_program, is_inner).await?))
+}
+
+async fn pool_created(
+    tx: &TxContext<'_>,
+    ix: PoolInitInstruction,
+    idx: usize,
+    outer_program: &String,
+    is_inner: bool,
+) -> Result<PoolCreated, DecodeError> {
+    let pool = PoolCreated {
+        block_date: convert_to_date(tx.timestamp).await?,
+        block_time: tx.timestamp,
+        block_slot: tx.slot,
+        signature: tx.signature.to_string(),
+        creator: ix.creator,
+        pool_address: ix.amm,
+        base_mint: ix.base_mint,
+        quote_mint: ix.quote_mint,
+        init_base_amount: to_ui_amount(ix.init_base_amount, &ix.base_vault, tx.post_token_balances)?,
+        init_quote_amount: to_ui_amount(ix.init_quote_amount, &ix.quote_vault, tx.post_token_balances)?,
+        base_vault: ix.base_vault,
+        quote_vault: ix.quote_vault,
+        lp_mint: ix.lp_mint,
+        open_time: ix.open_time as i64,
+        is_inner_instruction: is_inner,
+        instruction_index: idx as u32,
+        outer_program: outer_program.to_string(),
+        program: ix.program,
+    };
+
+    debug!(
+        slot = tx.slot,
+        signature = %tx.signature,
+        pool = %pool.pool_address,
+        base_mint = %pool.base_mint,
+        quote_mint = %pool.quote_mint,
+        "Decoded new pool"
+    );
+    Ok(pool)
 }
 
 async fn build_trade_data(
@@ -225,7 +775,7 @@ async fn build_trade_data(
     pre_balances: &Vec<u64>,
     post_balances: &Vec<u64>,
     fee: u64,
-) -> Option<TradeData> {
+) -> Result<Option<TradeData>, DecodeError> {
     // println!()
     let trade_data = get_trade_instruction(
         program,
@@ -240,25 +790,32 @@ async fn build_trade_data(
         0,
         base_add,
         quote_add,
-    );
+    )?;
 
     // 2. If there's a return, build the TradeData struct
     if let Some(td) = trade_data {
+        let base_amount = get_amount(&td.vault_a, pre_token_balances_vec, post_token_balances_vec).await?;
+        let quote_amount = get_amount(&td.vault_b, pre_token_balances_vec, post_token_balances_vec).await?;
+        let limits = swap_limits(&td, base_amount, quote_amount, post_token_balances_vec)?;
         let td_name = td.name;
         let td_address = td.dapp_address;
 
         let trade = TradeData {
-            block_date: convert_to_date(timestamp).await,
+            block_date: convert_to_date(timestamp).await?,
             tx_id: bs58::encode(signature).into_string(),
             block_slot: slot,
             block_time: timestamp,
             signature: signature.to_string(),
-            signer: accounts.get(0).unwrap().to_string(),
+            signer: accounts.first().ok_or(DecodeError::AccountIndex(0))?.to_string(),
             pool_address: td.amm,
-            base_mint: get_mint(&td.vault_a, post_token_balances_vec).await.unwrap(),
-            quote_mint: get_mint(&td.vault_b, post_token_balances_vec).await.unwrap(),
-            base_amount: get_amount(&td.vault_a, pre_token_balances_vec, post_token_balances_vec).await,
-            quote_amount: get_amount(&td.vault_b, pre_token_balances_vec, post_token_balances_vec).await,
+            base_mint: get_mint(&td.vault_a, post_token_balances_vec)
+                .await
+                .ok_or_else(|| DecodeError::MissingVaultBalance(td.vault_a.clone()))?,
+            quote_mint: get_mint(&td.vault_b, post_token_balances_vec)
+                .await
+                .ok_or_else(|| DecodeError::MissingVaultBalance(td.vault_b.clone()))?,
+            base_amount,
+            quote_amount,
             // base_amount: get_amt(
             //     &td.vault_a,
             //     0,
@@ -288,11 +845,535 @@ async fn build_trade_data(
             outer_program: td_address.clone(),
             inner_program: "".to_string(),
             txn_fee_lamports: fee,
-            signer_lamports_change: get_signer_balance_change(pre_balances, post_balances).await,
+            signer_lamports_change: get_signer_balance_change(pre_balances, post_balances).await?,
+            amount_in: limits.amount_in,
+            minimum_amount_out: limits.minimum_amount_out,
+            max_amount_in: limits.max_amount_in,
+            amount_out: limits.amount_out,
+            slippage_tolerance: limits.slippage_tolerance,
+            fill_vs_limit: limits.fill_vs_limit,
+            sqrt_price_limit_x64: None,
+            sqrt_price_x64: None,
+            tick: None,
+            base_transfer_fee: 0.0,
+            quote_transfer_fee: 0.0,
         };
 
-        Some(trade)
+        debug!(
+            slot,
+            %signature,
+            pool = %trade.pool_address,
+            instruction = %trade.instruction_type,
+            "Decoded trade"
+        );
+        Ok(Some(trade))
     } else {
-        None
+        Ok(None)
+    }
+}
+
+// The signed amounts of a swap in UI units, with the slippage derived from them. See
+// `TradeData` for the definitions.
+#[derive(Default)]
+struct SwapLimits {
+    amount_in: f64,
+    minimum_amount_out: f64,
+    max_amount_in: f64,
+    amount_out: f64,
+    slippage_tolerance: Option<f64>,
+    fill_vs_limit: Option<f64>,
+}
+
+// The pool's vault changes give the direction (the vault that grew received the input)
+// and the actual fill; the instruction amounts take the decimals of their side.
+fn swap_limits(
+    td: &TradeInstruction,
+    base_amount: f64,
+    quote_amount: f64,
+    post_token_balances: &[TokenBalance],
+) -> Result<SwapLimits, DecodeError> {
+    let (input_vault, paid, output_vault, received) = if base_amount > 0.0 && quote_amount < 0.0 {
+        (&td.vault_a, base_amount, &td.vault_b, -quote_amount)
+    } else if quote_amount > 0.0 && base_amount < 0.0 {
+        (&td.vault_b, quote_amount, &td.vault_a, -base_amount)
+    } else {
+        return Ok(SwapLimits::default());
+    };
+
+    match td.name.as_str() {
+        "SwapBaseIn" => {
+            let minimum_amount_out =
+                to_ui_amount(td.minimum_amount_out, output_vault, post_token_balances)?;
+            Ok(SwapLimits {
+                amount_in: to_ui_amount(td.amount_in, input_vault, post_token_balances)?,
+                minimum_amount_out,
+                slippage_tolerance: Some(1.0 - minimum_amount_out / received),
+                fill_vs_limit: (td.minimum_amount_out > 0).then(|| received / minimum_amount_out),
+                ..Default::default()
+            })
+        }
+        "SwapBaseOut" => {
+            let max_amount_in = to_ui_amount(td.max_amount_in, input_vault, post_token_balances)?;
+            Ok(SwapLimits {
+                max_amount_in,
+                amount_out: to_ui_amount(td.amount_out, output_vault, post_token_balances)?,
+                slippage_tolerance: Some(max_amount_in / paid - 1.0),
+                fill_vs_limit: (td.max_amount_in > 0).then(|| paid / max_amount_in),
+                ..Default::default()
+            })
+        }
+        _ => Ok(SwapLimits::default()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use serde_json::{json, Value};
+    use solana_sdk::{hash::hash, pubkey::Pubkey};
+    use solana_transaction_status::option_serializer::OptionSerializer;
+
+    use super::*;
+    use crate::config::SERUM_PROGRAM_ID;
+
+    const SYSTEM_PROGRAM: &str = "11111111111111111111111111111111";
+    const TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+
+    // Accounts are 32 copies of one byte.
+    fn pubkey(byte: u8) -> Pubkey {
+        Pubkey::new_from_array([byte; 32])
+    }
+
+    fn key(byte: u8) -> String {
+        pubkey(byte).to_string()
+    }
+
+    fn balance(account_index: u8, mint: &str, amount: u64, decimals: u8, program: &str) -> Value {
+        let ui_amount = amount as f64 / 10f64.powi(decimals as i32);
+        json!({
+            "accountIndex": account_index,
+            "mint": mint,
+            "uiTokenAmount": {
+                "uiAmount": ui_amount,
+                "decimals": decimals,
+                "amount": amount.to_string(),
+                "uiAmountString": ui_amount.to_string(),
+            },
+            "owner": key(1),
+            "programId": program,
+        })
+    }
+
+    fn instruction(program_id_index: u8, accounts: &[u8], data: &[u8]) -> Value {
+        json!({
+            "programIdIndex": program_id_index,
+            "accounts": accounts,
+            "data": bs58::encode(data).into_string(),
+            "stackHeight": null,
+        })
+    }
+
+    // A successful legacy transaction in getBlock's JSON encoding.
+    fn transaction(
+        account_keys: &[String],
+        instructions: Vec<Value>,
+        inner_instructions: Value,
+        log_messages: &[String],
+        balances: (Vec<Value>, Vec<Value>),
+    ) -> EncodedTransactionWithStatusMeta {
+        let lamports = vec![1_000_000_000u64; account_keys.len()];
+        serde_json::from_value(json!({
+            "transaction": {
+                "signatures": [bs58::encode([7u8; 64]).into_string()],
+                "message": {
+                    "header": {
+                        "numRequiredSignatures": 1,
+                        "numReadonlySignedAccounts": 0,
+                        "numReadonlyUnsignedAccounts": 0,
+                    },
+                    "accountKeys": account_keys,
+                    "recentBlockhash": key(0),
+                    "instructions": instructions,
+                },
+            },
+            "meta": {
+                "err": null,
+                "status": { "Ok": null },
+                "fee": 5000,
+                "preBalances": lamports,
+                "postBalances": lamports,
+                "innerInstructions": inner_instructions,
+                "logMessages": log_messages,
+                "preTokenBalances": balances.0,
+                "postTokenBalances": balances.1,
+                "rewards": [],
+                "loadedAddresses": { "writable": [], "readonly": [] },
+            },
+            "version": "legacy",
+        }))
+        .unwrap()
+    }
+
+    // An AMM v4 swap_base_in of 1 base for 2 quote, behind a system instruction. The
+    // swap's transfers are listed under its own index, 1.
+    fn amm_v4_swap(inner_instructions: Value) -> EncodedTransactionWithStatusMeta {
+        let account_keys = [
+            key(1),
+            key(2),
+            key(3),
+            key(4),
+            SERUM_PROGRAM_ID.to_string(),
+            config().programs.raydium_amm.clone(),
+            SYSTEM_PROGRAM.to_string(),
+        ];
+        let swap_base_in = [
+            &[9u8][..],
+            &1_000_000u64.to_le_bytes(),
+            &1_800_000u64.to_le_bytes(),
+        ];
+        let (base_mint, quote_mint) = (key(8), key(9));
+        transaction(
+            &account_keys,
+            vec![
+                instruction(6, &[0], &[]),
+                instruction(5, &[0, 1, 2, 3, 4], &swap_base_in.concat()),
+            ],
+            inner_instructions,
+            &[],
+            (
+                vec![
+                    balance(2, &base_mint, 10_000_000, 6, TOKEN_PROGRAM_ID),
+                    balance(3, &quote_mint, 10_000_000, 6, TOKEN_PROGRAM_ID),
+                ],
+                vec![
+                    balance(2, &base_mint, 11_000_000, 6, TOKEN_PROGRAM_ID),
+                    balance(3, &quote_mint, 8_000_000, 6, TOKEN_PROGRAM_ID),
+                ],
+            ),
+        )
+    }
+
+    fn transfers(index: u8) -> Value {
+        json!({ "index": index, "instructions": [instruction(6, &[0], &[2, 0, 0, 0])] })
+    }
+
+    #[tokio::test]
+    async fn amm_v4_swap_takes_the_inner_instructions_of_its_own_index() {
+        let trx = amm_v4_swap(json!([transfers(0), transfers(1)]));
+        let events = process_tx(trx, 317233807, 1738195200).await.unwrap();
+        assert_eq!(events.trades.len(), 1);
+        let trade = &events.trades[0];
+        assert_eq!(
+            (trade.instruction_index, trade.instruction_type.as_str()),
+            (1, "SwapBaseIn")
+        );
+        assert_eq!(trade.pool_address, key(2));
+        assert_eq!(
+            (trade.base_vault.clone(), trade.quote_vault.clone()),
+            (key(3), key(4))
+        );
+        assert_eq!(
+            (trade.base_mint.clone(), trade.quote_mint.clone()),
+            (key(8), key(9))
+        );
+        assert_eq!((trade.base_amount, trade.quote_amount), (1.0, -2.0));
+
+        // Another instruction's transfers are not the swap's.
+        let trx = amm_v4_swap(json!([transfers(0)]));
+        assert!(matches!(
+            process_tx(trx, 317233807, 1738195200).await,
+            Err(DecodeError::MissingInnerInstructions(1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn balances_without_owner_or_program_still_decode() {
+        let mut trx = amm_v4_swap(json!([transfers(1)]));
+        let meta = trx.meta.as_mut().unwrap();
+        for balances in [&mut meta.pre_token_balances, &mut meta.post_token_balances] {
+            let OptionSerializer::Some(balances) = balances else {
+                unreachable!()
+            };
+            for balance in balances {
+                balance.owner = OptionSerializer::Skip;
+                balance.program_id = OptionSerializer::Skip;
+            }
+        }
+        let events = process_tx(trx, 317233807, 1738195200).await.unwrap();
+        assert_eq!(events.trades.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn transactions_without_raydium_are_not_decoded() {
+        // Data that is not even base58, and token balances missing altogether.
+        let mut trx = transaction(
+            &[key(1), SYSTEM_PROGRAM.to_string()],
+            vec![json!({ "programIdIndex": 1, "accounts": [0], "data": "0OIl" })],
+            json!([]),
+            &[],
+            (vec![], vec![]),
+        );
+        let meta = trx.meta.as_mut().unwrap();
+        meta.pre_token_balances = OptionSerializer::Skip;
+        meta.post_token_balances = OptionSerializer::Skip;
+        let events = process_tx(trx, 317233807, 1738195200).await.unwrap();
+        assert!(events.trades.is_empty() && events.liquidity.is_empty() && events.pools.is_empty());
+    }
+
+    #[tokio::test]
+    async fn amm_v4_calls_other_than_swaps_are_not_trades() {
+        let programs = &config().programs;
+        let account_keys = [
+            key(1),
+            key(2),
+            key(3),
+            key(4),
+            SERUM_PROGRAM_ID.to_string(),
+            programs.raydium_amm.clone(),
+            programs.jupiter.clone(),
+        ];
+        // monitor_step (tag 2) lists the market program like a swap but moves nothing,
+        // and a route calls the AMM with too few accounts for a swap.
+        let trx = transaction(
+            &account_keys,
+            vec![
+                instruction(5, &[0, 1, 2, 3, 4], &[2, 0, 0]),
+                instruction(6, &[0], &[]),
+            ],
+            json!([{ "index": 1, "instructions": [instruction(5, &[0, 1], &[2])] }]),
+            &[],
+            (vec![], vec![]),
+        );
+        let events = process_tx(trx, 317233807, 1738195200).await.unwrap();
+        assert!(events.trades.is_empty());
+    }
+
+    fn vault(address: &str, decimals: u32) -> TokenBalance {
+        TokenBalance {
+            account_index: 0,
+            address: address.to_string(),
+            mint: key(9),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: 0.0,
+                decimals,
+                amount: "0".to_string(),
+                ui_amount_string: "0".to_string(),
+            },
+            owner: key(1),
+            program_id: TOKEN_PROGRAM_ID.to_string(),
+        }
+    }
+
+    fn close(actual: Option<f64>, expected: f64) -> bool {
+        actual.is_some_and(|actual| (actual - expected).abs() < 1e-9)
+    }
+
+    #[test]
+    fn swap_limits_take_the_decimals_and_direction_of_each_side() {
+        // Base has 6 decimals, quote 9.
+        let balances = [vault("base", 6), vault("quote", 9)];
+        let swap = |name: &str| TradeInstruction {
+            name: name.to_string(),
+            vault_a: "base".to_string(),
+            vault_b: "quote".to_string(),
+            ..Default::default()
+        };
+
+        // 1 base in for 2 quote out, with at least 1.8 quote expected.
+        let base_in = TradeInstruction {
+            amount_in: 1_000_000,
+            minimum_amount_out: 1_800_000_000,
+            ..swap("SwapBaseIn")
+        };
+        let limits = swap_limits(&base_in, 1.0, -2.0, &balances).unwrap();
+        assert_eq!((limits.amount_in, limits.minimum_amount_out), (1.0, 1.8));
+        assert!(close(limits.slippage_tolerance, 0.1));
+        assert!(close(limits.fill_vs_limit, 2.0 / 1.8));
+
+        // 1 quote in for exactly 2 base out, paying at most 1.1 quote.
+        let base_out = TradeInstruction {
+            max_amount_in: 1_100_000_000,
+            amount_out: 2_000_000,
+            ..swap("SwapBaseOut")
+        };
+        let limits = swap_limits(&base_out, -2.0, 1.0, &balances).unwrap();
+        assert_eq!((limits.max_amount_in, limits.amount_out), (1.1, 2.0));
+        assert!(close(limits.slippage_tolerance, 0.1));
+        assert!(close(limits.fill_vs_limit, 1.0 / 1.1));
+
+        // Without a minimum the trader accepted any fill.
+        let no_minimum = TradeInstruction {
+            amount_in: 1_000_000,
+            ..swap("SwapBaseIn")
+        };
+        let limits = swap_limits(&no_minimum, 1.0, -2.0, &balances).unwrap();
+        assert!(close(limits.slippage_tolerance, 1.0));
+        assert_eq!(limits.fill_vs_limit, None);
+
+        // Vaults that did not move in opposite directions were not a swap.
+        let limits = swap_limits(&base_in, 1.0, 2.0, &balances).unwrap();
+        assert_eq!((limits.amount_in, limits.slippage_tolerance), (0.0, None));
+    }
+
+    const TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb";
+
+    fn program_data(event: &str, fields: &[&[u8]]) -> String {
+        let discriminator = &hash(format!("event:{}", event).as_bytes()).to_bytes()[..8];
+        let data = [&[discriminator][..], fields].concat().concat();
+        format!("Program data: {}", STANDARD.encode(data))
+    }
+
+    // A CPMM pool (account 3) between two Token-2022 mints with 1% input and output
+    // transfer fees; instruction accounts follow the swap and deposit layouts.
+    fn cpmm_transaction(
+        accounts: &[u8],
+        data: &[u8],
+        logs: &[String],
+        balances: (Vec<Value>, Vec<Value>),
+    ) -> EncodedTransactionWithStatusMeta {
+        let mut account_keys: Vec<String> = (50..62).map(key).collect();
+        account_keys[9] = key(9);
+        account_keys[10] = key(1);
+        account_keys.push(TOKEN_2022_PROGRAM_ID.to_string());
+        account_keys.push(config().programs.raydium_cpmm.clone());
+        let instruction = instruction(13, accounts, data);
+        transaction(&account_keys, vec![instruction], json!([]), logs, balances)
+    }
+
+    fn cpmm_logs(event: String) -> Vec<String> {
+        let cpmm = &config().programs.raydium_cpmm;
+        vec![
+            format!("Program {} invoke [1]", cpmm),
+            event,
+            format!("Program {} success", cpmm),
+        ]
+    }
+
+    // 1 token of mint 9 in for 0.5 of mint 1 out, with at least 0.45 expected.
+    fn cpmm_swap(logs: &[String]) -> EncodedTransactionWithStatusMeta {
+        let swap_base_input = [
+            &[143, 190, 90, 218, 196, 30, 51, 222][..],
+            &1_000_000u64.to_le_bytes(),
+            &450_000u64.to_le_bytes(),
+        ];
+        cpmm_transaction(
+            &[0, 1, 2, 3, 4, 5, 6, 7, 12, 12, 9, 10, 11],
+            &swap_base_input.concat(),
+            logs,
+            (
+                vec![
+                    balance(6, &key(9), 10_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(7, &key(1), 10_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                ],
+                vec![
+                    balance(6, &key(9), 10_990_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(7, &key(1), 9_500_000, 6, TOKEN_2022_PROGRAM_ID),
+                ],
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn cpmm_swap_limits_account_for_token_2022_fees() {
+        let event = program_data(
+            "SwapEvent",
+            &[
+                pubkey(53).as_ref(),
+                &10_000_000u64.to_le_bytes(),
+                &10_000_000u64.to_le_bytes(),
+                &1_000_000u64.to_le_bytes(),
+                &500_000u64.to_le_bytes(),
+                &10_000u64.to_le_bytes(),
+                &5_000u64.to_le_bytes(),
+                &[1],
+            ],
+        );
+        let events = process_tx(cpmm_swap(&cpmm_logs(event)), 317233807, 1738195200)
+            .await
+            .unwrap();
+        let trade = &events.trades[0];
+        assert_eq!(trade.pool_address, key(53));
+        // Mint 1 sorts first, so the output side is base.
+        assert_eq!(
+            (trade.base_mint.clone(), trade.quote_mint.clone()),
+            (key(1), key(9))
+        );
+        assert!((trade.base_amount + 0.5).abs() < 1e-9);
+        assert!((trade.quote_amount - 0.99).abs() < 1e-9);
+        assert!((trade.base_transfer_fee - 0.005).abs() < 1e-9);
+        assert!((trade.quote_transfer_fee - 0.01).abs() < 1e-9);
+        assert_eq!((trade.amount_in, trade.minimum_amount_out), (1.0, 0.45));
+        // The trader sent 1 and received 0.495 after the fees.
+        assert!(close(trade.slippage_tolerance, 1.0 - 0.45 / 0.495));
+        assert!(close(trade.fill_vs_limit, 0.495 / 0.45));
+
+        // Without the event the fees are unknown and the vault changes are taken as is.
+        let events = process_tx(cpmm_swap(&[]), 317233807, 1738195200)
+            .await
+            .unwrap();
+        let trade = &events.trades[0];
+        assert_eq!(
+            (trade.base_transfer_fee, trade.quote_transfer_fee),
+            (0.0, 0.0)
+        );
+        assert!(close(trade.slippage_tolerance, 1.0 - 0.45 / 0.5));
+    }
+
+    #[tokio::test]
+    async fn cpmm_deposit_takes_its_fees_from_the_lp_change_event() {
+        let event = program_data(
+            "LpChangeEvent",
+            &[
+                pubkey(53).as_ref(),
+                &7_000_000u64.to_le_bytes(),
+                &10_000_000u64.to_le_bytes(),
+                &10_000_000u64.to_le_bytes(),
+                &1_000_000u64.to_le_bytes(),
+                &2_020_000u64.to_le_bytes(),
+                &0u64.to_le_bytes(),
+                &20_000u64.to_le_bytes(),
+                &[0],
+            ],
+        );
+        let deposit = [
+            &[242, 35, 198, 137, 82, 225, 242, 182][..],
+            &1_000_000u64.to_le_bytes(),
+            &1_000_000u64.to_le_bytes(),
+            &2_100_000u64.to_le_bytes(),
+        ];
+        // Account 3 is the pool here too; account 8 holds the minted LP tokens.
+        let trx = cpmm_transaction(
+            &[0, 1, 3, 8, 4, 5, 6, 7, 12, 12, 10, 9, 2],
+            &deposit.concat(),
+            &cpmm_logs(event),
+            (
+                vec![
+                    balance(6, &key(1), 10_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(7, &key(9), 10_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(8, &key(52), 0, 6, TOKEN_PROGRAM_ID),
+                ],
+                vec![
+                    balance(6, &key(1), 11_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(7, &key(9), 12_000_000, 6, TOKEN_2022_PROGRAM_ID),
+                    balance(8, &key(52), 1_000_000, 6, TOKEN_PROGRAM_ID),
+                ],
+            ),
+        );
+        let events = process_tx(trx, 317233807, 1738195200).await.unwrap();
+        let deposit = &events.liquidity[0];
+        assert_eq!(
+            (deposit.event_type.as_str(), deposit.pool_address.clone()),
+            ("Deposit", key(53))
+        );
+        assert_eq!(
+            (deposit.base_mint.clone(), deposit.quote_mint.clone()),
+            (key(1), key(9))
+        );
+        assert_eq!((deposit.base_amount, deposit.quote_amount), (1.0, 2.0));
+        assert_eq!((deposit.lp_mint.clone(), deposit.lp_amount), (key(52), 1.0));
+        assert_eq!(
+            (deposit.base_transfer_fee, deposit.quote_transfer_fee),
+            (0.0, 0.02)
+        );
     }
 }
@@ -1,19 +1,22 @@
-use crate::global::RPC_CLIENT;
-use crate::models::{MarketDataStruct, TokenBalance, TradeData, Transfer};
+use crate::decode_error::DecodeError;
+use crate::global::RPC_POOL;
+use crate::models::{
+    LiquidityEvent, MarketDataStruct, PoolCreated, TokenBalance, TradeData, Transfer,
+};
 use anyhow::Result;
 use avro_rs::types::Record;
 use avro_rs::{Schema, Writer};
 use borsh::BorshDeserialize;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, Utc};
 use csv::WriterBuilder;
-use solana_sdk::program_pack::Pack;
+use serde::{de::DeserializeOwned, Serialize};
 use solana_sdk::pubkey::Pubkey;
-use solana_sdk::{bs58, inner_instruction};
+use solana_sdk::bs58;
 use solana_transaction_status::{UiInnerInstructions, UiInstruction};
-use std::fs::{create_dir_all, OpenOptions};
+use std::fs::{self, create_dir_all, File, OpenOptions};
 use std::path::Path;
 use std::str::FromStr;
-use tokio::task;
+use tracing::{debug, warn};
 
 lazy_static::lazy_static! {
     pub static ref AVRO_SCHEMA: Schema = Schema::parse_str(r#"
@@ -41,7 +44,76 @@ lazy_static::lazy_static! {
             { "name": "outer_program", "type": "string" },
             { "name": "inner_program", "type": "string" },
             { "name": "txn_fee_lamports", "type": "long" },
-            { "name": "signer_lamports_change", "type": "long" }
+            { "name": "signer_lamports_change", "type": "long" },
+            { "name": "amount_in", "type": "double", "default": 0.0 },
+            { "name": "minimum_amount_out", "type": "double", "default": 0.0 },
+            { "name": "max_amount_in", "type": "double", "default": 0.0 },
+            { "name": "amount_out", "type": "double", "default": 0.0 },
+            { "name": "slippage_tolerance", "type": ["null", "double"], "default": null },
+            { "name": "fill_vs_limit", "type": ["null", "double"], "default": null },
+            { "name": "sqrt_price_limit_x64", "type": ["null", "string"], "default": null },
+            { "name": "sqrt_price_x64", "type": ["null", "string"], "default": null },
+            { "name": "tick", "type": ["null", "int"], "default": null },
+            { "name": "base_transfer_fee", "type": "double", "default": 0.0 },
+            { "name": "quote_transfer_fee", "type": "double", "default": 0.0 }
+        ]
+    }
+    "#).expect("Failed to parse Avro schema");
+
+    pub static ref LIQUIDITY_AVRO_SCHEMA: Schema = Schema::parse_str(r#"
+    {
+        "type": "record",
+        "name": "LiquidityEvent",
+        "fields": [
+            { "name": "block_date", "type": "string" },
+            { "name": "block_time", "type": "long" },
+            { "name": "block_slot", "type": "long" },
+            { "name": "signature", "type": "string" },
+            { "name": "signer", "type": "string" },
+            { "name": "pool_address", "type": "string" },
+            { "name": "event_type", "type": "string" },
+            { "name": "base_mint", "type": "string" },
+            { "name": "quote_mint", "type": "string" },
+            { "name": "base_vault", "type": "string" },
+            { "name": "quote_vault", "type": "string" },
+            { "name": "base_amount", "type": "double" },
+            { "name": "quote_amount", "type": "double" },
+            { "name": "lp_mint", "type": "string" },
+            { "name": "lp_amount", "type": "double" },
+            { "name": "is_inner_instruction", "type": "boolean" },
+            { "name": "instruction_index", "type": "int" },
+            { "name": "outer_program", "type": "string" },
+            { "name": "tick_lower_index", "type": ["null", "int"], "default": null },
+            { "name": "tick_upper_index", "type": ["null", "int"], "default": null },
+            { "name": "base_transfer_fee", "type": "double", "default": 0.0 },
+            { "name": "quote_transfer_fee", "type": "double", "default": 0.0 }
+        ]
+    }
+    "#).expect("Failed to parse Avro schema");
+
+    pub static ref POOL_CREATED_AVRO_SCHEMA: Schema = Schema::parse_str(r#"
+    {
+        "type": "record",
+        "name": "PoolCreated",
+        "fields": [
+            { "name": "block_date", "type": "string" },
+            { "name": "block_time", "type": "long" },
+            { "name": "block_slot", "type": "long" },
+            { "name": "signature", "type": "string" },
+            { "name": "creator", "type": "string" },
+            { "name": "pool_address", "type": "string" },
+            { "name": "base_mint", "type": "string" },
+            { "name": "quote_mint", "type": "string" },
+            { "name": "base_vault", "type": "string" },
+            { "name": "quote_vault", "type": "string" },
+            { "name": "lp_mint", "type": "string" },
+            { "name": "open_time", "type": "long" },
+            { "name": "init_base_amount", "type": "double" },
+            { "name": "init_quote_amount", "type": "double" },
+            { "name": "is_inner_instruction", "type": "boolean" },
+            { "name": "instruction_index", "type": "int" },
+            { "name": "outer_program", "type": "string" },
+            { "name": "program", "type": "string", "default": "" }
         ]
     }
     "#).expect("Failed to parse Avro schema");
@@ -83,20 +155,16 @@ lazy_static::lazy_static! {
 // }
 
 pub async fn get_mint(address: &String, token_balances: &Vec<TokenBalance>) -> Option<String> {
-    let index = token_balances.iter().position(|r| r.address == *address);
-    match index {
-        None => None,
-        Some(index) => {
-            let mint = token_balances.get(index).unwrap().mint.clone();
-            Some(mint)
-        }
-    }
+    token_balances
+        .iter()
+        .find(|r| r.address == *address)
+        .map(|balance| balance.mint.clone())
 }
 
-pub fn get_amm_data(amm_address: &String) {
+pub async fn get_amm_data(amm_address: &String) {
     let add = Pubkey::from_str(amm_address).unwrap();
-    let rpc_client = RPC_CLIENT.clone();
-    let acc_data = rpc_client.get_account(&add).unwrap().data;
+    let rpc_client = RPC_POOL.primary();
+    let acc_data = rpc_client.get_account(&add).await.unwrap().data;
     let decoded_data = MarketDataStruct::try_from_slice(&acc_data).unwrap();
     // println!("Account Data: {:?}", decoded_data);
 }
@@ -144,27 +212,84 @@ pub fn get_amm_data(amm_address: &String) {
 //     return vault_b;
 // }
 
-pub async fn get_signer_balance_change(pre_balances: &Vec<u64>, post_balances: &Vec<u64>) -> i64 {
-    return post_balances[0] as i64 - pre_balances[0] as i64;
+pub async fn get_signer_balance_change(
+    pre_balances: &Vec<u64>,
+    post_balances: &Vec<u64>,
+) -> Result<i64, DecodeError> {
+    match (pre_balances.first(), post_balances.first()) {
+        (Some(pre), Some(post)) => Ok(*post as i64 - *pre as i64),
+        _ => Err(DecodeError::MissingLamportBalance(0)),
+    }
 }
 
-pub async fn convert_to_date(ts: i64) -> String {
-    let nt = NaiveDateTime::from_timestamp_opt(ts, 0);
-    let dt: DateTime<Utc> = DateTime::from_naive_utc_and_offset(nt.unwrap(), Utc);
-    let res = dt.format("%Y-%m-%d");
-    return res.to_string();
+pub async fn convert_to_date(ts: i64) -> Result<String, DecodeError> {
+    let dt = DateTime::<Utc>::from_timestamp(ts, 0).ok_or(DecodeError::InvalidBlockTime(ts))?;
+    Ok(dt.format("%Y-%m-%d").to_string())
 }
 
 pub async fn get_amount(
     address: &String,
     pre_token_balances: &Vec<TokenBalance>,
     post_token_balances: &Vec<TokenBalance>,
-) -> f64{
+) -> Result<f64, DecodeError> {
     // calculate diff between post_token_balances and pre_token_balances for address
-    let post_amount = post_token_balances.iter().find(|&x| x.address == *address).unwrap();
-    let pre_amount = pre_token_balances.iter().find(|&x| x.address == *address).unwrap();
-    let diff = post_amount.ui_token_amount.ui_amount - pre_amount.ui_token_amount.ui_amount;
-    diff
+    let find = |balances: &Vec<TokenBalance>| {
+        balances
+            .iter()
+            .find(|&x| x.address == *address)
+            .map(|balance| balance.ui_token_amount.ui_amount)
+            .ok_or_else(|| DecodeError::MissingVaultBalance(address.clone()))
+    };
+    Ok(find(post_token_balances)? - find(pre_token_balances)?)
+}
+
+/// Like `get_amount`, but a vault created by the transaction itself (as in Initialize2)
+/// has no pre balance and counts as having been empty.
+pub fn get_vault_change(
+    address: &String,
+    pre_token_balances: &[TokenBalance],
+    post_token_balances: &[TokenBalance],
+) -> Result<f64, DecodeError> {
+    let amount = |balances: &[TokenBalance]| {
+        balances
+            .iter()
+            .find(|balance| balance.address == *address)
+            .map(|balance| balance.ui_token_amount.ui_amount)
+    };
+    let post = amount(post_token_balances)
+        .ok_or_else(|| DecodeError::MissingVaultBalance(address.clone()))?;
+    Ok(post - amount(pre_token_balances).unwrap_or(0.0))
+}
+
+/// A raw token `amount` in UI units, using the decimals of the token account `address`.
+pub fn to_ui_amount(
+    amount: u64,
+    address: &String,
+    token_balances: &[TokenBalance],
+) -> Result<f64, DecodeError> {
+    let decimals = token_balances
+        .iter()
+        .find(|balance| balance.address == *address)
+        .map(|balance| balance.ui_token_amount.decimals)
+        .ok_or_else(|| DecodeError::MissingVaultBalance(address.clone()))?;
+    Ok(amount as f64 / 10f64.powi(decimals as i32))
+}
+
+/// Net change of a mint's supply as far as the transaction shows it: the summed change
+/// of every token account of `mint` in the balances, e.g. LP tokens minted or burned.
+pub fn get_supply_change(
+    mint: &str,
+    pre_token_balances: &[TokenBalance],
+    post_token_balances: &[TokenBalance],
+) -> f64 {
+    let total = |balances: &[TokenBalance]| -> f64 {
+        balances
+            .iter()
+            .filter(|balance| balance.mint == mint)
+            .map(|balance| balance.ui_token_amount.ui_amount)
+            .sum()
+    };
+    total(post_token_balances) - total(pre_token_balances)
 }
 
 pub async fn get_amt(
@@ -176,18 +301,22 @@ pub async fn get_amt(
     dapp_address: String,
     pre_balances: Vec<u64>,
     post_balances: Vec<u64>,
-) -> f64 {
+) -> Result<f64, DecodeError> {
     // TODO: this entire function should just get base vault, quote vault, base mint, quote mint and amounts
     // then just find the amt change for vaults and assign it accordingly to base and quote amounts
     let mut result: f64 = 0.0;
 
-    let mint = get_mint(address, post_token_balances).await.unwrap();
+    let mint = get_mint(address, post_token_balances)
+        .await
+        .ok_or_else(|| DecodeError::MissingVaultBalance(address.clone()))?;
 
     if mint == "So11111111111111111111111111111111111111112" {
         // TODO: REPLACE THIS SHIT
         // get solana balance change
-        return (get_signer_balance_change(&pre_balances, &post_balances).await as f64)
-            / (u64::pow(10, 9)) as f64;
+        return Ok(
+            (get_signer_balance_change(&pre_balances, &post_balances).await? as f64)
+                / (u64::pow(10, 9)) as f64,
+        );
     }
 
     let source_transfer_amt = get_token_transfer(
@@ -199,7 +328,7 @@ pub async fn get_amt(
         dapp_address.clone(),
         pre_balances.clone(),
         post_balances.clone(),
-    );
+    )?;
 
     let destination_transfer_amt = get_token_transfer(
         address,
@@ -210,7 +339,7 @@ pub async fn get_amt(
         dapp_address.clone(),
         pre_balances.clone(),
         post_balances.clone(),
-    );
+    )?;
 
     if source_transfer_amt != 0.0 {
         result = source_transfer_amt;
@@ -229,7 +358,7 @@ pub async fn get_amt(
             });
     }
 
-    -result
+    Ok(-result)
 }
 
 pub fn get_token_transfer(
@@ -241,7 +370,7 @@ pub fn get_token_transfer(
     dapp_address: String,
     pre_balances: Vec<u64>,
     post_balances: Vec<u64>,
-) -> f64 {
+) -> Result<f64, DecodeError> {
     if dapp_address.eq("6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P") {
         return get_system_program_transfer(
             address,
@@ -254,115 +383,25 @@ pub fn get_token_transfer(
         );
     }
 
-    let mut result = 0.0;
-    let mut result_assigned = false;
+    let result = find_spl_transfer(
+        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+        address,
+        input_inner_idx,
+        inner_instructions,
+        accounts,
+    )?;
 
-    inner_instructions.iter().for_each(|inner_instruction| {
-        inner_instruction
-            .instructions
-            .iter()
-            .enumerate()
-            .for_each(|(inner_idx, inner_inst)| {
-                let inner_inst = match inner_inst {
-                    UiInstruction::Parsed(_) => return,
-                    UiInstruction::Compiled(compiled) => compiled,
-                };
-                let program_id_index = inner_inst.program_id_index as usize;
-                if program_id_index >= accounts.len() {
-                    return;
-                }
-                let inner_program = &accounts[program_id_index];
-                if inner_program
-                    .as_str()
-                    .eq("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA")
-                {
-                    // println!("Inner Program: {:?}", inner_program);
-                    // println!("Data: {:?}", inner_inst.data.clone().into_bytes());
-                    let data = bs58::decode(inner_inst.data.clone())
-                        .into_vec()
-                        .expect("Error decoding data");
-                    let (discriminator_bytes, rest) = data.split_at(1);
-                    let discriminator: u8 = u8::from(discriminator_bytes[0]);
-
-                    match discriminator {
-                        3 => {
-                            let input_accounts =
-                                prepare_input_accounts(&inner_inst.accounts, accounts);
-
-                            let source = input_accounts.get(0).unwrap().to_string();
-                            let destination = input_accounts.get(1).unwrap().to_string();
-
-                            let condition = if input_inner_idx > 0 {
-                                inner_idx as u32 > input_inner_idx
-                            } else {
-                                true
-                            };
-
-                            if condition && address.eq(&source) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = -1.0 * data.amount as f64;
-                                    result_assigned = true;
-                                }
-                            }
-
-                            if condition && address.eq(&destination) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = data.amount as f64;
-                                    result_assigned = true;
-                                }
-                            }
-                        }
-                        12 => {
-                            let input_accounts =
-                                prepare_input_accounts(&inner_inst.accounts, accounts);
-
-                            let source = input_accounts.get(0).unwrap().to_string();
-                            let destination = input_accounts.get(2).unwrap().to_string();
-
-                            let condition = if input_inner_idx > 0 {
-                                inner_idx as u32 > input_inner_idx
-                            } else {
-                                true
-                            };
-
-                            if condition && address.eq(&source) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = -1.0 * data.amount as f64;
-                                    result_assigned = true;
-                                }
-                            }
-
-                            if condition && address.eq(&destination) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = data.amount as f64;
-                                    result_assigned = true;
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            })
-    });
-
-    if !result_assigned {
-        let _result = get_token_22_transfer(
+    match result {
+        Some(result) => Ok(result),
+        None => Ok(get_token_22_transfer(
             address,
             input_inner_idx,
             inner_instructions,
             accounts,
             account_name_to_check,
-        );
-        if _result.is_some() {
-            result = _result.unwrap();
-        }
+        )?
+        .unwrap_or(0.0)),
     }
-
-    result
 }
 
 pub fn get_token_22_transfer(
@@ -371,104 +410,79 @@ pub fn get_token_22_transfer(
     inner_instructions: &Vec<UiInnerInstructions>,
     accounts: &Vec<String>,
     account_name_to_check: String,
-) -> Option<f64> {
-    let mut result = None;
-    let mut result_assigned = false;
+) -> Result<Option<f64>, DecodeError> {
+    find_spl_transfer(
+        "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+        address,
+        input_inner_idx,
+        inner_instructions,
+        accounts,
+    )
+}
 
-    inner_instructions.iter().for_each(|inner_instruction| {
-        inner_instruction
-            .instructions
-            .iter()
-            .enumerate()
-            .for_each(|(inner_idx, inner_inst)| {
-                let inner_inst = match inner_inst {
-                    UiInstruction::Parsed(_) => return,
-                    UiInstruction::Compiled(compiled) => compiled,
-                };
-                let inner_program = &accounts[inner_inst.program_id_index as usize];
-
-                if inner_program
-                    .as_str()
-                    .eq("TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb")
-                {
-                    let data = bs58::decode(inner_inst.data.clone())
-                        .into_vec()
-                        .expect("Error decoding data");
-                    let (discriminator_bytes, rest) = data.split_at(1);
-                    let discriminator: u8 = u8::from(discriminator_bytes[0]);
-
-                    match discriminator {
-                        3 => {
-                            let input_accounts =
-                                prepare_input_accounts(&inner_inst.accounts, accounts);
-
-                            let source = input_accounts.get(0).unwrap().to_string();
-                            let destination = input_accounts.get(1).unwrap().to_string();
-
-                            let condition = if input_inner_idx > 0 {
-                                inner_idx as u32 > input_inner_idx
-                            } else {
-                                true
-                            };
-
-                            if condition && address.eq(&source) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = Some(-1.0 * data.amount as f64);
-                                    result_assigned = true;
-                                }
-                            }
-
-                            if condition && address.eq(&destination) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = Some(data.amount as f64);
-                                    result_assigned = true;
-                                }
-                            }
-                        }
-                        12 => {
-                            let input_accounts =
-                                prepare_input_accounts(&inner_inst.accounts, accounts);
-
-                            let source = input_accounts.get(0).unwrap().to_string();
-                            let destination = input_accounts.get(2).unwrap().to_string();
-
-                            let condition = if input_inner_idx > 0 {
-                                inner_idx as u32 > input_inner_idx
-                            } else {
-                                true
-                            };
-
-                            if condition && address.eq(&source) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = Some(-1.0 * data.amount as f64);
-                                    result_assigned = true;
-                                }
-                            }
-
-                            if condition && address.eq(&destination) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = Some(data.amount as f64);
-                                    result_assigned = true;
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            })
-    });
-
-    result
+// The first Transfer (3) or TransferChecked (12) of `token_program` that moves tokens
+// out of `address` (negative) or into it, in raw token units.
+fn find_spl_transfer(
+    token_program: &str,
+    address: &String,
+    input_inner_idx: u32,
+    inner_instructions: &Vec<UiInnerInstructions>,
+    accounts: &Vec<String>,
+) -> Result<Option<f64>, DecodeError> {
+    for inner_instruction in inner_instructions {
+        for (inner_idx, inner_inst) in inner_instruction.instructions.iter().enumerate() {
+            let inner_inst = match inner_inst {
+                UiInstruction::Parsed(_) => continue,
+                UiInstruction::Compiled(compiled) => compiled,
+            };
+            match accounts.get(inner_inst.program_id_index as usize) {
+                Some(inner_program) if inner_program == token_program => {}
+                _ => continue,
+            }
+
+            let data = bs58::decode(inner_inst.data.clone()).into_vec()?;
+            let Some((&discriminator, rest)) = data.split_first() else {
+                continue;
+            };
+            let destination_position = match discriminator {
+                3 => 1,
+                12 => 2,
+                _ => continue,
+            };
+
+            let condition = if input_inner_idx > 0 {
+                inner_idx as u32 > input_inner_idx
+            } else {
+                true
+            };
+            if !condition {
+                continue;
+            }
+
+            let input_accounts = prepare_input_accounts(&inner_inst.accounts, accounts);
+            let source = input_accounts.first().ok_or(DecodeError::MissingAccount(0))?;
+            let destination = input_accounts
+                .get(destination_position)
+                .ok_or(DecodeError::MissingAccount(destination_position))?;
+
+            if address.eq(source) {
+                let data = Transfer::deserialize(&mut &rest[..])?;
+                return Ok(Some(-1.0 * data.amount as f64));
+            }
+            if address.eq(destination) {
+                let data = Transfer::deserialize(&mut &rest[..])?;
+                return Ok(Some(data.amount as f64));
+            }
+        }
+    }
+
+    Ok(None)
 }
 
 pub fn prepare_input_accounts(account_indices: &Vec<u8>, accounts: &Vec<String>) -> Vec<String> {
     let mut instruction_accounts: Vec<String> = vec![];
     for (index, &el) in account_indices.iter().enumerate() {
-        if el >= accounts.len() as u8 {
+        if el as usize >= accounts.len() {
             continue;
         }
         let account = &accounts[el as usize];
@@ -485,86 +499,68 @@ fn get_system_program_transfer(
     account_name_to_check: String,
     pre_balances: Vec<u64>,
     post_balances: Vec<u64>,
-) -> f64 {
-    let mut result = 0.0;
-    let mut result_assigned = false;
-
-    inner_instructions.iter().for_each(|inner_instruction| {
-        inner_instruction
-            .instructions
-            .iter()
-            .enumerate()
-            .for_each(|(inner_idx, inner_inst)| {
-                let inner_inst = match inner_inst {
-                    UiInstruction::Parsed(_) => return,
-                    UiInstruction::Compiled(compiled) => compiled,
-                };
-                let inner_program = &accounts[inner_inst.program_id_index as usize];
-
-                if inner_program
-                    .as_str()
-                    .eq("11111111111111111111111111111111")
-                {
-                    // decode hex
-                    let data = bs58::decode(inner_inst.data.clone())
-                        .into_vec()
-                        .expect("Error decoding data");
-                    let (discriminator_bytes, rest) = data.split_at(4);
-
-                    let disc_bytes_arr: [u8; 4] = discriminator_bytes.to_vec().try_into().unwrap();
-                    let discriminator: u32 = u32::from_le_bytes(disc_bytes_arr);
-
-                    match discriminator {
-                        2 => {
-                            let input_accounts =
-                                prepare_input_accounts(&inner_inst.accounts, accounts);
-
-                            let source = input_accounts.get(0).unwrap().to_string();
-                            let destination = input_accounts.get(1).unwrap().to_string();
-
-                            let condition = if input_inner_idx > 0 {
-                                inner_idx as u32 > input_inner_idx
-                            } else {
-                                true
-                            };
-
-                            if condition && address.eq(&source) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = -1.0 * data.amount as f64;
-                                    result /= 10f64.powi(9);
-                                    result_assigned = true;
-                                }
-                            }
-
-                            if condition && address.eq(&destination) {
-                                let data = Transfer::deserialize(&mut rest.clone()).unwrap();
-                                if !result_assigned {
-                                    result = 1.0 * data.amount as f64;
-                                    result /= 10f64.powi(9);
-                                    result_assigned = true;
-                                }
-                            }
-                        }
-                        _ => {}
-                    }
-                }
-            })
-    });
-
-    if !result_assigned {
-        let index = accounts.iter().position(|r| r == address).unwrap();
-        let _result = post_balances[index] as f64 - pre_balances[index] as f64;
-        result = 1.0 * _result as f64;
-        result /= 10f64.powi(9);
+) -> Result<f64, DecodeError> {
+    for inner_instruction in inner_instructions {
+        for (inner_idx, inner_inst) in inner_instruction.instructions.iter().enumerate() {
+            let inner_inst = match inner_inst {
+                UiInstruction::Parsed(_) => continue,
+                UiInstruction::Compiled(compiled) => compiled,
+            };
+            match accounts.get(inner_inst.program_id_index as usize) {
+                Some(inner_program) if inner_program == "11111111111111111111111111111111" => {}
+                _ => continue,
+            }
+
+            // decode hex
+            let data = bs58::decode(inner_inst.data.clone()).into_vec()?;
+            let Some((discriminator_bytes, rest)) = data.split_first_chunk::<4>() else {
+                continue;
+            };
+            let discriminator: u32 = u32::from_le_bytes(*discriminator_bytes);
+
+            // 2 = Transfer
+            if discriminator != 2 {
+                continue;
+            }
+
+            let condition = if input_inner_idx > 0 {
+                inner_idx as u32 > input_inner_idx
+            } else {
+                true
+            };
+            if !condition {
+                continue;
+            }
+
+            let input_accounts = prepare_input_accounts(&inner_inst.accounts, accounts);
+            let source = input_accounts.first().ok_or(DecodeError::MissingAccount(0))?;
+            let destination = input_accounts.get(1).ok_or(DecodeError::MissingAccount(1))?;
+
+            if address.eq(source) {
+                let data = Transfer::deserialize(&mut &rest[..])?;
+                return Ok(-1.0 * data.amount as f64 / 10f64.powi(9));
+            }
+            if address.eq(destination) {
+                let data = Transfer::deserialize(&mut &rest[..])?;
+                return Ok(data.amount as f64 / 10f64.powi(9));
+            }
+        }
     }
 
-    result
+    // No transfer instruction; fall back to the account's lamport balance change.
+    let index = accounts
+        .iter()
+        .position(|r| r == address)
+        .ok_or_else(|| DecodeError::MissingVaultBalance(address.clone()))?;
+    match (pre_balances.get(index), post_balances.get(index)) {
+        (Some(pre), Some(post)) => Ok((*post as f64 - *pre as f64) / 10f64.powi(9)),
+        _ => Err(DecodeError::MissingLamportBalance(index)),
+    }
 }
 
 pub async fn save_trades_to_avro(trades: &Vec<TradeData>, file_path: &str) -> Result<()> {
     if trades.is_empty() {
-        println!("No trades to save");
+        warn!(path = file_path, "No trades to save");
         return Err(anyhow::anyhow!("No trades to save"));
     }
     // Ensure the directory exists.
@@ -572,81 +568,187 @@ pub async fn save_trades_to_avro(trades: &Vec<TradeData>, file_path: &str) -> Re
         create_dir_all(parent)?;
     }
 
-    // Clone trades and file_path for the blocking task.
-    let trades = trades.clone();
-    let file_path = file_path.to_string();
-
-    let file = OpenOptions::new()
-        .create(true)
-        .truncate(true)
-        .write(true)
-        .open(&file_path)?;
-
-    let mut writer = Writer::new(&AVRO_SCHEMA, file);
-
-    for trade in trades.clone() {
-        let mut record = Record::new(&AVRO_SCHEMA).expect("Failed to create Avro record");
-        record.put("block_date", trade.block_date.clone());
-        record.put("block_time", trade.block_time);
-        // Convert u64 to i64
-        record.put("block_slot", trade.block_slot as i64);
-        record.put("signature", trade.signature.clone());
-        record.put("tx_id", trade.tx_id.clone());
-        record.put("signer", trade.signer.clone());
-        record.put("pool_address", trade.pool_address.clone());
-        record.put("base_mint", trade.base_mint.clone());
-        record.put("quote_mint", trade.quote_mint.clone());
-        record.put("base_vault", trade.base_vault.clone());
-        record.put("quote_vault", trade.quote_vault.clone());
-        record.put("base_amount", trade.base_amount);
-        record.put("quote_amount", trade.quote_amount);
-        record.put("is_inner_instruction", trade.is_inner_instruction);
-        record.put("instruction_index", trade.instruction_index as i32);
-        record.put("instruction_type", trade.instruction_type.clone());
-        record.put(
-            "inner_instruction_index",
-            trade.inner_instruction_index as i32,
-        );
-        record.put("outer_program", trade.outer_program.clone());
-        record.put("inner_program", trade.inner_program.clone());
-        record.put("txn_fee_lamports", trade.txn_fee_lamports as i64);
-        record.put(
-            "signer_lamports_change",
-            trade.signer_lamports_change as i64,
-        );
+    write_atomically(Path::new(file_path), |file| {
+        let mut writer = Writer::new(&AVRO_SCHEMA, file);
+
+        for trade in trades {
+            let mut record = Record::new(&AVRO_SCHEMA).expect("Failed to create Avro record");
+            record.put("block_date", trade.block_date.clone());
+            record.put("block_time", trade.block_time);
+            // Convert u64 to i64
+            record.put("block_slot", trade.block_slot as i64);
+            record.put("signature", trade.signature.clone());
+            record.put("tx_id", trade.tx_id.clone());
+            record.put("signer", trade.signer.clone());
+            record.put("pool_address", trade.pool_address.clone());
+            record.put("base_mint", trade.base_mint.clone());
+            record.put("quote_mint", trade.quote_mint.clone());
+            record.put("base_vault", trade.base_vault.clone());
+            record.put("quote_vault", trade.quote_vault.clone());
+            record.put("base_amount", trade.base_amount);
+            record.put("quote_amount", trade.quote_amount);
+            record.put("is_inner_instruction", trade.is_inner_instruction);
+            record.put("instruction_index", trade.instruction_index as i32);
+            record.put("instruction_type", trade.instruction_type.clone());
+            record.put(
+                "inner_instruction_index",
+                trade.inner_instruction_index as i32,
+            );
+            record.put("outer_program", trade.outer_program.clone());
+            record.put("inner_program", trade.inner_program.clone());
+            record.put("txn_fee_lamports", trade.txn_fee_lamports as i64);
+            record.put(
+                "signer_lamports_change",
+                trade.signer_lamports_change as i64,
+            );
+            record.put("amount_in", trade.amount_in);
+            record.put("minimum_amount_out", trade.minimum_amount_out);
+            record.put("max_amount_in", trade.max_amount_in);
+            record.put("amount_out", trade.amount_out);
+            record.put("slippage_tolerance", trade.slippage_tolerance);
+            record.put("fill_vs_limit", trade.fill_vs_limit);
+            record.put("sqrt_price_limit_x64", trade.sqrt_price_limit_x64.clone());
+            record.put("sqrt_price_x64", trade.sqrt_price_x64.clone());
+            record.put("tick", trade.tick);
+            record.put("base_transfer_fee", trade.base_transfer_fee);
+            record.put("quote_transfer_fee", trade.quote_transfer_fee);
+
+            writer.append(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
 
-        writer.append(record)?;
+pub async fn save_liquidity_events_to_avro(events: &[LiquidityEvent], file_path: &str) -> Result<()> {
+    if let Some(parent) = Path::new(file_path).parent() {
+        create_dir_all(parent)?;
     }
-    writer.flush()?;
-    Ok(())
+
+    write_atomically(Path::new(file_path), |file| {
+        let mut writer = Writer::new(&LIQUIDITY_AVRO_SCHEMA, file);
+
+        for event in events {
+            let mut record =
+                Record::new(&LIQUIDITY_AVRO_SCHEMA).expect("Failed to create Avro record");
+            record.put("block_date", event.block_date.clone());
+            record.put("block_time", event.block_time);
+            record.put("block_slot", event.block_slot as i64);
+            record.put("signature", event.signature.clone());
+            record.put("signer", event.signer.clone());
+            record.put("pool_address", event.pool_address.clone());
+            record.put("event_type", event.event_type.clone());
+            record.put("base_mint", event.base_mint.clone());
+            record.put("quote_mint", event.quote_mint.clone());
+            record.put("base_vault", event.base_vault.clone());
+            record.put("quote_vault", event.quote_vault.clone());
+            record.put("base_amount", event.base_amount);
+            record.put("quote_amount", event.quote_amount);
+            record.put("lp_mint", event.lp_mint.clone());
+            record.put("lp_amount", event.lp_amount);
+            record.put("is_inner_instruction", event.is_inner_instruction);
+            record.put("instruction_index", event.instruction_index as i32);
+            record.put("outer_program", event.outer_program.clone());
+            record.put("tick_lower_index", event.tick_lower_index);
+            record.put("tick_upper_index", event.tick_upper_index);
+            record.put("base_transfer_fee", event.base_transfer_fee);
+            record.put("quote_transfer_fee", event.quote_transfer_fee);
+
+            writer.append(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
 }
 
-pub async fn save_trades_to_csv(trades: &Vec<TradeData>, file_path: &str) -> Result<()> {
+pub async fn save_pools_to_avro(pools: &[PoolCreated], file_path: &str) -> Result<()> {
     if let Some(parent) = Path::new(file_path).parent() {
         create_dir_all(parent)?;
     }
 
-    // Check if the file exists
-    let file_exists = Path::new(file_path).exists();
+    write_atomically(Path::new(file_path), |file| {
+        let mut writer = Writer::new(&POOL_CREATED_AVRO_SCHEMA, file);
+
+        for pool in pools {
+            let mut record =
+                Record::new(&POOL_CREATED_AVRO_SCHEMA).expect("Failed to create Avro record");
+            record.put("block_date", pool.block_date.clone());
+            record.put("block_time", pool.block_time);
+            record.put("block_slot", pool.block_slot as i64);
+            record.put("signature", pool.signature.clone());
+            record.put("creator", pool.creator.clone());
+            record.put("pool_address", pool.pool_address.clone());
+            record.put("base_mint", pool.base_mint.clone());
+            record.put("quote_mint", pool.quote_mint.clone());
+            record.put("base_vault", pool.base_vault.clone());
+            record.put("quote_vault", pool.quote_vault.clone());
+            record.put("lp_mint", pool.lp_mint.clone());
+            record.put("open_time", pool.open_time);
+            record.put("init_base_amount", pool.init_base_amount);
+            record.put("init_quote_amount", pool.init_quote_amount);
+            record.put("is_inner_instruction", pool.is_inner_instruction);
+            record.put("instruction_index", pool.instruction_index as i32);
+            record.put("outer_program", pool.outer_program.clone());
+            record.put("program", pool.program.clone());
+
+            writer.append(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })
+}
 
-    // Open the file in append mode
-    let file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(file_path)?;
+/// Writes one slot's records of any stream. Replaces any earlier file for the slot, so
+/// a rerun doesn't duplicate its rows.
+pub async fn save_to_csv<T: Serialize>(records: &[T], file_path: &str) -> Result<()> {
+    if let Some(parent) = Path::new(file_path).parent() {
+        create_dir_all(parent)?;
+    }
 
-    // Configure the writer
-    let mut writer = WriterBuilder::new()
-        .has_headers(!file_exists) // Only write headers if the file doesn't exist
-        .from_writer(file);
+    write_atomically(Path::new(file_path), |file| {
+        let mut writer = WriterBuilder::new().has_headers(true).from_writer(file);
+        for record in records {
+            writer.serialize(record)?;
+        }
+        writer.flush()?;
+        Ok(())
+    })?;
+    debug!(records = records.len(), path = file_path, "Saved records");
 
-    // Write each trade
-    for trade in trades {
-        writer.serialize(trade)?;
-    }
+    Ok(())
+}
 
-    writer.flush()?; // Ensure all data is written to the file
-    println!("Saved {} trades to {}", trades.len(), file_path);
+// Writes `<path>.tmp`, syncs it and renames it over `path`, so an interrupted write
+// never leaves a truncated file behind under the real name.
+fn write_atomically(path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = Path::new(&tmp_path);
 
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(true)
+        .write(true)
+        .open(tmp_path)?;
+    write(&mut file)?;
+    file.sync_all()?;
+    fs::rename(tmp_path, path)?;
     Ok(())
 }
+
+/// Reads back a slot file of any stream, written as Avro or by `save_to_csv`.
+pub fn load_records<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let mut records = vec![];
+    if path.extension().is_some_and(|ext| ext == "csv") {
+        let mut reader = csv::Reader::from_path(path)?;
+        for record in reader.deserialize() {
+            records.push(record?);
+        }
+    } else {
+        let reader = avro_rs::Reader::new(std::fs::File::open(path)?)?;
+        for value in reader {
+            records.push(avro_rs::from_value::<T>(&value?)?);
+        }
+    }
+    Ok(records)
+}
@@ -0,0 +1,81 @@
+// Runs a recorded getBlock response through FileBlockSource and process_block, the same
+// path the indexer and preprocessor take with --replay-dir.
+
+use std::{fs, path::PathBuf};
+
+use common::{
+    block_processor::process_block,
+    block_source::{BlockSource, FileBlockSource},
+    listener::TradeFilter,
+    models::{OutputConfig, OutputFormat, TradeData},
+};
+use futures::StreamExt;
+use solana_sdk::pubkey::Pubkey;
+
+const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures");
+// A CPMM swap_base_input between two Token-2022 mints, next to a plain SOL transfer.
+const SWAP_SLOT: u64 = 317233807;
+
+// Accounts in the fixture are 32 copies of one byte.
+fn key(byte: u8) -> String {
+    Pubkey::new_from_array([byte; 32]).to_string()
+}
+
+fn output_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("raydium-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+#[tokio::test]
+async fn replays_recorded_block_into_slot_files() {
+    let source = FileBlockSource::new(FIXTURES);
+    let blocks: Vec<_> = source.fetch(vec![SWAP_SLOT]).collect().await;
+    assert_eq!(blocks.len(), 1);
+    let (slot, block) = blocks.into_iter().next().unwrap().unwrap();
+    assert_eq!(slot, SWAP_SLOT);
+    assert_eq!(block.transactions.len(), 2);
+
+    let dir = output_dir("replay");
+    let output = OutputConfig {
+        dir: dir.to_string_lossy().into_owned(),
+        format: OutputFormat::Csv,
+        filter: TradeFilter::default(),
+    };
+    let written = process_block(slot, block, &output, None).await.unwrap();
+    assert_eq!(written, 1);
+
+    let path = output.slot_file(None, "2025-01-30", SWAP_SLOT);
+    let trades: Vec<TradeData> = csv::Reader::from_path(&path)
+        .unwrap()
+        .deserialize()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(trades.len(), 1);
+    let trade = &trades[0];
+    assert_eq!(trade.block_slot, SWAP_SLOT);
+    assert_eq!(trade.block_time, 1738195200);
+    assert_eq!(trade.instruction_type, "SwapBaseIn");
+    assert_eq!(trade.pool_address, key(103));
+    assert_eq!(trade.outer_program, "CPMMoo8L3F4NbTegBCKVNunggL7H1ZpdTHKxQB5qKP1C");
+    // The output mint sorts first, so it is token 0 (base): the pool paid out 0.5 base
+    // and took in 0.99 quote after the 1% input transfer fee.
+    assert_eq!((trade.base_mint.clone(), trade.quote_mint.clone()), (key(1), key(9)));
+    assert!((trade.base_amount + 0.5).abs() < 1e-9);
+    assert!((trade.quote_amount - 0.99).abs() < 1e-9);
+    assert!((trade.base_transfer_fee - 0.005).abs() < 1e-9);
+    assert!((trade.quote_transfer_fee - 0.01).abs() < 1e-9);
+
+    // Nothing but the trade was decoded, so no other stream got a file.
+    assert!(!output.slot_file(Some("liquidity"), "2025-01-30", SWAP_SLOT).exists());
+    assert!(!dir.join("dead_letters").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn reports_slots_missing_from_the_recording() {
+    let source = FileBlockSource::new(FIXTURES);
+    let blocks: Vec<_> = source.fetch(vec![SWAP_SLOT + 1]).collect().await;
+    assert_eq!(blocks.len(), 1);
+    assert!(blocks[0].is_err());
+}
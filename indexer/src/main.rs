@@ -1,83 +1,482 @@
 use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::Instant,
+    time::{Duration, Instant},
 };
 
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use clap::{ArgGroup, Parser, ValueEnum};
 use common::{
-    block_processor::process_block,
-    rpc_client::{fetch_block_with_version, get_latest_slot},
+    block_archive::BlockArchive,
+    block_processor::{process_block, record_skipped_slot},
+    block_source::{
+        BlockSource, BlockStream, FailedSlot, GeyserBlockSource, LeftBehind, SourceArgs,
+        WsBlockSource,
+    },
+    checkpoint::{Checkpoint, SlotStatus},
+    config::{self, config},
+    decode_error::failure_counts,
+    geyser::GeyserClient,
+    logging,
+    metrics::{self, record_processed, SLOTS},
+    models::{OutputConfig, OutputFormat},
+    planner::read_jobs,
+    reconcile::Reconciler,
+    rpc_client::{find_slot_for_timestamp, get_latest_slot, FetchError},
+    shutdown::Shutdown,
 };
-use tokio::sync::{RwLock, Semaphore};
-use zmq;
-
-async fn run_indexer(/*publisher_arc: Option<Arc<Mutex<zmq::Socket>>>*/) {
-        println!("Starting indexer");
-        let start_slot = 317233807;
-        let end_slot = 317447178;
-
-        let max_concurrent_tasks = 25; // Limit to 10 concurrent tasks
-        let semaphore = Arc::new(Semaphore::new(max_concurrent_tasks));
-        let mut handles = Vec::new();
-        for block_num in (start_slot..end_slot).rev() {
-            let permit = semaphore.clone().acquire_owned().await.unwrap(); // Acquire a permit
-            // if publisher_arc.is_some() {
-            //     publisher_clone = Arc::clone(&publisher_arc.unwrap().clone());
-            // }
-            
-            let handle = tokio::spawn(async move {
-                let start_time = Instant::now();
-                let block = fetch_block_with_version(block_num).await;
-                match block {
-                    Ok(_) => {
-                        let block = block.unwrap();
-                        println!("Processing block: {}", block.transactions.len());
-
-                        println!("Processing block: {}", block_num);
-                        // spawn a new thread to process_block
-                        // tokio::spawn(async move {
-
-                        process_block(block_num, block, /*Some(publisher_clone)*/None).await;
-                        // });
-                        let elapsed = start_time.elapsed();
-                        println!("Block {} processed in {:?}", block_num, elapsed);
+use futures::StreamExt;
+use tracing::{debug, error, info, warn};
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Direction {
+    Forward,
+    Reverse,
+}
+
+/// Indexes Raydium trades from Solana blocks into per-slot files.
+#[derive(Parser, Debug)]
+#[command(name = "indexer")]
+#[command(group(ArgGroup::new("mode").required(true).multiple(true).args(["start_slot", "start_date", "tail", "jobs"])))]
+#[command(group(ArgGroup::new("source").args(["geyser", "ws", "replay_dir", "car"])))]
+struct Args {
+    /// First slot to index (inclusive)
+    #[arg(long)]
+    start_slot: Option<u64>,
+
+    /// Last slot to index (exclusive), defaults to the current tip
+    #[arg(long, requires = "start_slot")]
+    end_slot: Option<u64>,
+
+    /// First day to index (UTC), e.g. 2025-01-30
+    #[arg(long, conflicts_with_all = ["start_slot", "tail"])]
+    start_date: Option<NaiveDate>,
+
+    /// Last day to index (UTC, inclusive), defaults to the start date
+    #[arg(long, requires = "start_date")]
+    end_date: Option<NaiveDate>,
+
+    /// Index the slot ranges in a job file written by `planner --jobs`
+    #[arg(long, conflicts_with_all = ["start_slot", "start_date", "tail"])]
+    jobs: Option<String>,
+
+    /// Follow the confirmed tip instead of indexing a fixed range. With --start-slot,
+    /// catch up from that slot first
+    #[arg(long)]
+    tail: bool,
+
+    /// Stream confirmed blocks from a Yellowstone gRPC endpoint instead of polling RPC (tail only)
+    #[arg(long, requires = "tail")]
+    geyser: Option<String>,
+
+    /// x-token sent with Geyser gRPC requests
+    #[arg(long, requires = "geyser")]
+    x_token: Option<String>,
+
+    /// Receive blocks over a websocket blockSubscribe endpoint instead of polling RPC (tail only)
+    #[arg(long, requires = "tail")]
+    ws: Option<String>,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Also save every fetched block here, zstd-compressed, for offline replay,
+    /// defaults to paths.archive_dir
+    #[arg(long)]
+    archive_dir: Option<String>,
+
+    /// Order in which a range is walked
+    #[arg(long, value_enum, default_value_t = Direction::Reverse)]
+    direction: Direction,
+
+    /// Upper bound on blocks fetched at once; lowered automatically while the RPC throttles.
+    /// Defaults to concurrency.indexer
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// In tail mode, how far behind the tip the indexer may fall before skipping ahead.
+    /// Defaults to concurrency.max_lag
+    #[arg(long)]
+    max_lag: Option<u64>,
+
+    /// Output directory, defaults to paths.output_dir
+    #[arg(long)]
+    output_dir: Option<String>,
+
+    /// Output file format, defaults to sinks.format
+    #[arg(long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// ZMQ endpoint to publish processed slots on, e.g. tcp://*:5555. Defaults to
+    /// sinks.publisher
+    #[arg(long)]
+    publisher: Option<String>,
+
+    /// Slot status log used to resume interrupted runs, defaults to paths.checkpoint or
+    /// <output-dir>/checkpoint
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    /// Process every slot in the range again, even ones the checkpoint marks as finished
+    #[arg(long)]
+    no_resume: bool,
+
+    /// Don't re-check slots indexed at confirmed commitment once they are finalized
+    #[arg(long)]
+    no_reconcile: bool,
+
+    /// On SIGINT/SIGTERM, seconds to wait for the block in progress before cancelling it
+    #[arg(long, default_value_t = 30)]
+    drain_timeout: u64,
+
+    /// Serve Prometheus metrics on this address, e.g. 0.0.0.0:9090. Defaults to
+    /// sinks.metrics_addr
+    #[arg(long)]
+    metrics_addr: Option<SocketAddr>,
+
+    /// TOML config file, defaults to RAYDIUM_CONFIG or ./config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Config profile to apply on top of the file's base settings, defaults to
+    /// RAYDIUM_PROFILE
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+// How often slots indexed ahead of finalization are checked against the finalized chain.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(30);
+// How long queued ZMQ messages may take to go out once the indexer stops.
+const PUBLISHER_LINGER_MS: i32 = 1_000;
+
+async fn run_indexer(
+    mut blocks: BlockStream,
+    output: OutputConfig,
+    checkpoint: &Checkpoint,
+    mut reconciler: Option<Reconciler>,
+    archive: Option<BlockArchive>,
+    publisher_arc: Option<Arc<Mutex<zmq::Socket>>>,
+    mut shutdown: Shutdown,
+) {
+    info!("Starting indexer");
+    let mut last_reconcile = Instant::now();
+    let mut cancelled = false;
+    loop {
+        // Once shutdown is requested no further block is taken; the one being
+        // processed below always runs to completion.
+        let block = tokio::select! {
+            biased;
+            _ = shutdown.requested() => {
+                info!("Stopped scheduling new slots");
+                break;
+            }
+            block = blocks.next() => match block {
+                Some(block) => block,
+                None => break,
+            },
+        };
+        // Slots the source jumped over are marked failed, so the next run over
+        // the range (or a planner job) fetches them.
+        if let Some(LeftBehind(range)) = block.as_ref().err().and_then(|e| e.downcast_ref()) {
+            warn!(
+                start = range.start,
+                end = range.end,
+                "Recording slots left behind as failed"
+            );
+            for slot in range.clone() {
+                SLOTS
+                    .with_label_values(&[&SlotStatus::Failed.to_string()])
+                    .inc();
+                if let Err(e) = checkpoint.record(slot, SlotStatus::Failed) {
+                    error!(slot, "Failed to checkpoint slot: {:#}", e);
+                }
+            }
+            continue;
+        }
+        let start_time = Instant::now();
+        let (slot, status) = match block {
+            Ok((block_num, block)) => {
+                SLOTS.with_label_values(&["fetched"]).inc();
+                let block_time = block.block_time;
+                debug!(
+                    slot = block_num,
+                    transactions = block.transactions.len(),
+                    "Processing block"
+                );
+                if let Some(archive) = &archive {
+                    if let Err(e) = archive.write(block_num, &block) {
+                        warn!(slot = block_num, "Failed to archive block: {:#}", e);
                     }
-                    Err(e) => {
-                        println!("Error: {:?}", e);
+                }
+                if let Some(reconciler) = reconciler.as_mut() {
+                    reconciler.track(block_num, &block);
+                }
+                // Past the drain deadline the block is abandoned and checkpointed as
+                // failed; its files are only renamed into place once complete.
+                let processing = process_block(block_num, block, &output, publisher_arc.clone());
+                let processed = tokio::select! {
+                    processed = processing => Some(processed),
+                    _ = shutdown.forced() => None,
+                };
+                let status = match processed {
+                    Some(Ok(0)) => SlotStatus::Empty,
+                    Some(Ok(_)) => SlotStatus::Done,
+                    Some(Err(e)) => {
+                        error!(slot = block_num, "Error processing block: {:#}", e);
+                        SlotStatus::Failed
                     }
+                    None => {
+                        warn!(slot = block_num, "Cancelled the block in progress");
+                        cancelled = true;
+                        SlotStatus::Failed
+                    }
+                };
+                let elapsed = start_time.elapsed();
+                info!(
+                    slot = block_num,
+                    elapsed_ms = elapsed.as_millis() as u64,
+                    "Block processed"
+                );
+                record_processed(block_num, block_time);
+                (block_num, status)
+            }
+            Err(e) => match (
+                e.downcast_ref::<FetchError>(),
+                e.downcast_ref::<FailedSlot>(),
+            ) {
+                (Some(FetchError::Skipped(slot)), _) => {
+                    info!(slot, "Slot was skipped");
+                    if let Err(e) = record_skipped_slot(*slot, &output) {
+                        error!(slot, "Failed to record skipped slot: {:#}", e);
+                    }
+                    (*slot, SlotStatus::Skipped)
+                }
+                (_, Some(FailedSlot(slot))) => {
+                    error!(slot, "{:#}", e);
+                    (*slot, SlotStatus::Failed)
+                }
+                _ => {
+                    error!("{:#}", e);
+                    continue;
+                }
+            },
+        };
+        SLOTS.with_label_values(&[&status.to_string()]).inc();
+        if let Err(e) = checkpoint.record(slot, status) {
+            error!(slot, "Failed to checkpoint slot: {:#}", e);
+        }
+        if cancelled {
+            break;
+        }
+
+        if let Some(reconciler) = reconciler.as_mut() {
+            if last_reconcile.elapsed() >= RECONCILE_INTERVAL
+                && reconciler.pending() > 0
+                && !shutdown.is_requested()
+            {
+                if let Err(e) = reconciler.reconcile(checkpoint).await {
+                    error!("Reconciliation failed: {:#}", e);
                 }
-                drop(permit);
-            });
-            handles.push(handle);
+                last_reconcile = Instant::now();
+            }
         }
+    }
 
-        for handle in handles {
-            handle.await.unwrap();
+    // Slots finalized since the last pass are checked once more before stopping.
+    if let Some(reconciler) = reconciler
+        .as_mut()
+        .filter(|reconciler| reconciler.pending() > 0)
+    {
+        tokio::select! {
+            result = reconciler.reconcile(checkpoint) => {
+                if let Err(e) = result {
+                    error!("Reconciliation failed: {:#}", e);
+                }
+            }
+            _ = shutdown.forced() => warn!("Cancelled the last reconciliation"),
         }
+    }
+
+    if let Err(e) = checkpoint.sync() {
+        error!("Failed to sync checkpoint: {:#}", e);
+    }
+    for (reason, count) in failure_counts() {
+        warn!(reason, count, "Transactions failed to decode");
+    }
+}
+
+fn block_source(args: &Args) -> Box<dyn BlockSource> {
+    if let Some(endpoint) = &args.geyser {
+        let client = GeyserClient::new(endpoint).x_token(args.x_token.clone());
+        Box::new(GeyserBlockSource::new(client))
+    } else if let Some(url) = &args.ws {
+        Box::new(WsBlockSource::new(url))
+    } else {
+        let concurrency = &config().concurrency;
+        args.source.block_source(
+            args.concurrency.unwrap_or(concurrency.indexer),
+            args.max_lag.unwrap_or(concurrency.max_lag),
+        )
+    }
 }
 
-fn bind_zmq(port: &str) -> zmq::Socket {
+fn bind_zmq(endpoint: &str) -> zmq::Socket {
     let ctx = zmq::Context::new();
     let publisher = ctx
         .socket(zmq::PUB)
         .expect("Failed to create ZMQ PUB socket");
+    publisher.bind(endpoint).expect("Failed to bind publisher");
     publisher
-        .bind(format!("tcp://*:{}", port).as_str())
-        .expect("Failed to bind publisher");
-    publisher
+}
+
+async fn resolve_slots(args: &Args) -> Result<Vec<u64>> {
+    if let Some(path) = &args.jobs {
+        let jobs = read_jobs(Path::new(path))?;
+        info!(jobs = jobs.len(), path = %path, "Indexing jobs");
+        let mut slots: Vec<u64> = jobs
+            .iter()
+            .flat_map(|job| job.start_slot..job.end_slot)
+            .collect();
+        slots.sort();
+        slots.dedup();
+        if let Direction::Reverse = args.direction {
+            slots.reverse();
+        }
+        return Ok(slots);
+    }
+
+    let (start_slot, end_slot) = match (args.start_slot, args.start_date) {
+        (Some(start_slot), _) => {
+            let end_slot = match args.end_slot {
+                Some(end_slot) => end_slot,
+                None => get_latest_slot().await? + 1,
+            };
+            (start_slot, end_slot)
+        }
+        (None, Some(start_date)) => {
+            // end date is inclusive, so the range stops at midnight of the following day
+            let end_date = args.end_date.unwrap_or(start_date).succ_opt().unwrap();
+            let start_slot = find_slot_for_timestamp(midnight_timestamp(start_date)).await?;
+            let end_slot = match find_slot_for_timestamp(midnight_timestamp(end_date)).await {
+                Ok(end_slot) => end_slot,
+                Err(_) => get_latest_slot().await? + 1,
+            };
+            (start_slot, end_slot)
+        }
+        (None, None) => return Err(anyhow!("Either a slot range or a date range is required")),
+    };
+    info!("Indexing slots {}..{}", start_slot, end_slot);
+
+    let slots = match args.direction {
+        Direction::Forward => (start_slot..end_slot).collect(),
+        Direction::Reverse => (start_slot..end_slot).rev().collect(),
+    };
+    Ok(slots)
+}
+
+fn midnight_timestamp(date: NaiveDate) -> i64 {
+    date.and_hms_opt(0, 0, 0).unwrap().and_utc().timestamp()
 }
 
 #[tokio::main]
-async fn main() {
-    let ctx = zmq::Context::new();
-    let publisher = ctx
-        .socket(zmq::PUB)
-        .expect("Failed to create ZMQ PUB socket");
-    publisher
-        .bind("tcp://*:5555")
-        .expect("Failed to bind publisher");
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    logging::init();
+    let config = config::init(args.config.as_deref(), args.profile.as_deref())?;
+    let shutdown = Shutdown::listen(Duration::from_secs(args.drain_timeout));
+
+    if let Some(addr) = args.metrics_addr.or(config.sinks.metrics_addr) {
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(addr).await {
+                error!("Metrics server stopped: {:#}", e);
+            }
+        });
+    }
+
+    let output = OutputConfig {
+        dir: args
+            .output_dir
+            .clone()
+            .or_else(|| config.paths.output_dir.clone())
+            .ok_or_else(|| {
+                anyhow!("No output directory: pass --output-dir or set paths.output_dir")
+            })?,
+        format: args.format.unwrap_or(config.sinks.format),
+        filter: config.filters.clone(),
+    };
+
+    let publisher_arc = args
+        .publisher
+        .as_deref()
+        .or(config.sinks.publisher.as_deref())
+        .map(|endpoint| Arc::new(Mutex::new(bind_zmq(endpoint))));
+
+    let checkpoint_path = match args
+        .checkpoint
+        .as_ref()
+        .or(config.paths.checkpoint.as_ref())
+    {
+        Some(path) => PathBuf::from(path),
+        None => Path::new(&output.dir).join("checkpoint"),
+    };
+    let checkpoint = Checkpoint::open(&checkpoint_path)?;
+
+    // Replayed blocks are historical and may be indexed without any RPC access.
+    let reconciler = if args.no_reconcile || args.source.is_offline() {
+        None
+    } else {
+        Some(Reconciler::new(output.clone(), publisher_arc.clone()).await?)
+    };
 
-    // // 2. Wrap the publisher in an Arc<Mutex> so we can share it
-    let publisher_arc = Arc::new(Mutex::new(publisher));
+    let source = block_source(&args);
+    let blocks = if args.tail {
+        // Without an explicit start, carry on after the last slot a previous run reached.
+        let from = match args.start_slot {
+            Some(slot) => Some(slot),
+            None if args.no_resume => None,
+            None => checkpoint.last_slot().map(|slot| slot + 1),
+        };
+        source.follow(from)
+    } else {
+        let slots = resolve_slots(&args).await?;
+        let slots = if args.no_resume {
+            slots
+        } else {
+            let total = slots.len();
+            let pending = checkpoint.pending(slots);
+            info!(
+                checkpoint = %checkpoint_path.display(),
+                pending = pending.len(),
+                total,
+                failed = checkpoint.failed().len(),
+                "Resuming"
+            );
+            pending
+        };
+        source.fetch(slots)
+    };
+    let archive = args
+        .archive_dir
+        .as_deref()
+        .or(config.paths.archive_dir.as_deref())
+        .map(BlockArchive::new);
+    run_indexer(
+        blocks,
+        output,
+        &checkpoint,
+        reconciler,
+        archive,
+        publisher_arc.clone(),
+        shutdown,
+    )
+    .await;
 
-    run_indexer().await;
+    // Closing the socket waits for queued messages, bounded by the linger period.
+    if let Some(publisher) = publisher_arc {
+        publisher.lock().unwrap().set_linger(PUBLISHER_LINGER_MS)?;
+    }
+    info!("Indexer stopped");
+    Ok(())
 }
@@ -0,0 +1,116 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use clap::Parser;
+use common::{
+    checkpoint::Checkpoint,
+    config,
+    planner::{archived_slots, covered_slots, find_gaps, plan_jobs, write_jobs},
+};
+
+/// Lists slot ranges missing from the indexer output and plans jobs to backfill them.
+#[derive(Parser, Debug)]
+#[command(name = "planner")]
+struct Args {
+    /// Output directory to scan, defaults to paths.output_dir
+    #[arg(long)]
+    output_dir: Option<String>,
+
+    /// Indexer checkpoint, defaults to paths.checkpoint or <output-dir>/checkpoint
+    #[arg(long)]
+    checkpoint: Option<String>,
+
+    /// Only scan date folders from this day (UTC)
+    #[arg(long)]
+    start_date: Option<NaiveDate>,
+
+    /// Only scan date folders up to this day (UTC, inclusive), defaults to the start date
+    #[arg(long, requires = "start_date")]
+    end_date: Option<NaiveDate>,
+
+    /// First slot expected in the archive, defaults to the lowest one found
+    #[arg(long)]
+    start_slot: Option<u64>,
+
+    /// End of the expected range (exclusive), defaults to just past the highest slot found
+    #[arg(long)]
+    end_slot: Option<u64>,
+
+    /// Largest number of slots in a single job
+    #[arg(long, default_value_t = 10_000)]
+    max_job_slots: u64,
+
+    /// Write the jobs here, one JSON object per line, for `indexer --jobs`
+    #[arg(long)]
+    jobs: Option<String>,
+
+    /// TOML config file, defaults to RAYDIUM_CONFIG or ./config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Config profile to apply, defaults to RAYDIUM_PROFILE
+    #[arg(long)]
+    profile: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+    let config = config::init(args.config.as_deref(), args.profile.as_deref())?;
+
+    let dir = match args.output_dir.as_ref().or(config.paths.output_dir.as_ref()) {
+        Some(dir) => PathBuf::from(dir),
+        None => return Err(anyhow!("No output directory: pass --output-dir or set paths.output_dir")),
+    };
+    let checkpoint_path = match args.checkpoint.as_ref().or(config.paths.checkpoint.as_ref()) {
+        Some(path) => PathBuf::from(path),
+        None => dir.join("checkpoint"),
+    };
+    let checkpoint = Checkpoint::open(&checkpoint_path)?;
+    let dates = args
+        .start_date
+        .map(|start_date| (start_date, args.end_date.unwrap_or(start_date)));
+
+    let archived = archived_slots(&dir, dates)?;
+    let covered = covered_slots(&dir, &checkpoint, &archived)?;
+    let start_slot = match args.start_slot.or_else(|| archived.first().copied()) {
+        Some(slot) => slot,
+        None => return Err(anyhow!("Nothing archived in {}, pass --start-slot", dir.display())),
+    };
+    let end_slot = match args.end_slot.or_else(|| archived.last().map(|slot| slot + 1)) {
+        Some(slot) => slot,
+        None => return Err(anyhow!("Nothing archived in {}, pass --end-slot", dir.display())),
+    };
+
+    let gaps = find_gaps(&covered, start_slot..end_slot);
+    let missing: u64 = gaps.iter().map(|gap| gap.end - gap.start).sum();
+    println!(
+        "Slots {}..{}: {} missing in {} ranges",
+        start_slot,
+        end_slot,
+        missing,
+        gaps.len()
+    );
+    for gap in &gaps {
+        println!("  {}..{} ({} slots)", gap.start, gap.end, gap.end - gap.start);
+    }
+
+    let jobs = plan_jobs(&gaps, args.max_job_slots);
+    match &args.jobs {
+        Some(path) => {
+            write_jobs(Path::new(path), &jobs)?;
+            println!("Wrote {} jobs to {}", jobs.len(), path);
+        }
+        None => {
+            for job in &jobs {
+                println!(
+                    "indexer --start-slot {} --end-slot {} --output-dir {}",
+                    job.start_slot,
+                    job.end_slot,
+                    dir.display()
+                );
+            }
+        }
+    }
+    Ok(())
+}
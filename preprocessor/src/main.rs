@@ -1,18 +1,46 @@
 mod models;
 mod preprocessor;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use avro_rs::types::{Record, Value};
-use chrono::{Datelike, NaiveDateTime, Timelike};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, Timelike};
 use csv::Reader;
 use lazy_static::lazy_static;
 use std::collections::{BTreeSet, HashMap};
 use std::fs::{self, File, OpenOptions};
 use std::io::{BufReader, BufWriter, Write};
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 
 use avro_rs::{Codec, Schema, Writer};
+use clap::Parser;
+use common::block_source::SourceArgs;
+use tracing::{debug, error, info, warn};
+
+/// Re-fetches missing or broken slot files of a day and merges them with prices.
+#[derive(Parser, Debug)]
+#[command(name = "preprocessor")]
+struct Args {
+    /// Day to preprocess (UTC), defaults to yesterday
+    #[arg(long)]
+    date: Option<NaiveDate>,
+
+    #[command(flatten)]
+    source: SourceArgs,
+
+    /// Slots re-fetched at once, defaults to concurrency.preprocessor
+    #[arg(long)]
+    concurrency: Option<usize>,
+
+    /// TOML config file, defaults to RAYDIUM_CONFIG or ./config.toml
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Config profile to apply on top of the file's base settings, defaults to
+    /// RAYDIUM_PROFILE
+    #[arg(long)]
+    profile: Option<String>,
+}
 
 lazy_static! {
     pub static ref AVRO_SCHEMA: Schema = Schema::parse_str(
@@ -91,11 +119,11 @@ pub fn merge_avro_per_hour(raw_avro_folder: &str, output_folder: &str) -> Result
         let entry = entry?;
         let avro_path = entry.path();
 
-        println!("Processing {}", avro_path.display());
+        debug!(path = %avro_path.display(), "Merging slot file");
 
         if avro_path.extension().map_or(false, |ext| ext == "avro") {
             if fs::metadata(&avro_path)?.len() == 0 {
-                println!("🛑 Skipping empty file: {}", avro_path.display());
+                warn!(path = %avro_path.display(), "Skipping empty file");
                 continue;
             }
             let file = File::open(&avro_path)?;
@@ -142,26 +170,48 @@ pub fn merge_avro_per_hour(raw_avro_folder: &str, output_folder: &str) -> Result
     // Flush all writers to finalize Avro files
     for (hour, mut writer) in hourly_writers {
         writer.flush()?;
-        println!("✅ Merged slot files → {}/{}.avro", output_folder, hour);
+        info!(hour = %hour, "Merged slot files into {}/{}.avro", output_folder, hour);
     }
 
     Ok(())
 }
 
 #[tokio::main]
-async fn main() {
-    // Assumption - this gets triggered once every day
-    let binding = (chrono::Utc::today().naive_utc() - chrono::Duration::days(1))
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    // Runs once a day for the day before, unless a date is given.
+    let date = args
+        .date
+        .unwrap_or_else(|| chrono::Utc::now().date_naive() - chrono::Duration::days(1))
         .to_string();
-    let yesterday = binding
-        .as_str();
-    let path = "/Users/jakkie/Dev/solana_data/test/";
-    let preprocessor = preprocessor::Preprocessor::new(path, "2025-01-30").await;
-    println!("Preprocessor {:?}", preprocessor.path);
+    common::logging::init();
+    let config = common::config::init(args.config.as_deref(), args.profile.as_deref())?;
+    // The preprocessor reads the indexer's `<output_dir>/<date>/` folders.
+    let path = format!(
+        "{}/",
+        config
+            .paths
+            .output_dir
+            .as_deref()
+            .ok_or_else(|| anyhow!("No output directory: set paths.output_dir"))?
+            .trim_end_matches('/')
+    );
+    if let Some(addr) = config.sinks.metrics_addr {
+        tokio::spawn(async move {
+            if let Err(e) = common::metrics::serve(addr).await {
+                error!("Metrics server stopped: {:#}", e);
+            }
+        });
+    }
+    let concurrency = args.concurrency.unwrap_or(config.concurrency.preprocessor);
+    let source = Arc::from(args.source.block_source(concurrency, 0));
+    let preprocessor = preprocessor::Preprocessor::new(&path, &date, source, concurrency).await;
+    info!(path = %preprocessor.path.display(), "Starting preprocessor");
     let preprocessor = Arc::new(preprocessor);
     // time it 
     let start = std::time::Instant::now();
     preprocessor.run().await; 
     let duration = start.elapsed();
-    println!("Time elapsed in running preprocessor is: {:?}", duration);
+    info!(elapsed_ms = duration.as_millis() as u64, "Preprocessor finished");
+    Ok(())
 }
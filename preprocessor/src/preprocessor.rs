@@ -1,7 +1,7 @@
 use anyhow::{Result, anyhow};
 use chrono::{NaiveDate, NaiveDateTime, Datelike, Timelike};
 use common::{
-    block_processor::process_block, models::{KlineData, TradeData}, pricer::{fetch_klines_for_date, store_klines}, rpc_client::fetch_block_with_version
+    block_processor::{load_skipped_slots, process_block, record_skipped_slot}, block_source::BlockSource, metrics::SLOTS, rpc_client::FetchError, models::{KlineData, OutputConfig, OutputFormat, TradeData}, pricer::{fetch_klines_for_date, store_klines}
 };
 
 use native_tls::TlsConnector;
@@ -14,6 +14,8 @@ use tokio::{
     time::{self, sleep, timeout},
 };
 use polars::prelude::*;
+use tracing::{debug, error, info, instrument, warn};
+use tokio_stream::StreamExt;
 
 use crate::models::{TokenMeta, ProcessedTrade};
 use crate::models::Side::{Buy, Sell};
@@ -33,11 +35,19 @@ pub struct Preprocessor {
     // pub db_client: tokio_postgres::Client,
     // token_meta_map: Arc<Mutex<HashMap<String, TokenMeta>>>,
     sol_prices: Vec<KlineData>,
+    source: Arc<dyn BlockSource>,
+    // Slots re-fetched at once.
+    concurrency: usize,
     // hourly_writers: Mutex<HashMap<String, Writer<'static, BufWriter<File>>>>,
 }
 
 impl Preprocessor {
-    pub async fn new(path: &str, date: &str) -> Self {
+    pub async fn new(
+        path: &str,
+        date: &str,
+        source: Arc<dyn BlockSource>,
+        concurrency: usize,
+    ) -> Self {
         let base_path = Path::new(path);
         if !base_path.exists() {
             panic!("Directory does not exist!");
@@ -68,6 +78,8 @@ impl Preprocessor {
             // db_client: client,
             // token_meta_map: Arc::new(Mutex::new(HashMap::new())),
             sol_prices: prices,
+            source,
+            concurrency,
             // hourly_writers: Mutex::new(HashMap::new()),
         };
 
@@ -182,10 +194,9 @@ impl Preprocessor {
     // }
 
     async fn get_raw_files(&self, dir: &str) -> Vec<String> {
-        println!("Getting raw files");
+        debug!(dir, "Getting raw files");
         let mut files = vec![];
 
-        println!("{:?}", dir);
 
         for entry in fs::read_dir(dir).expect("Failed to read directory") {
             let entry = entry.expect("Failed to read entry");
@@ -312,13 +323,15 @@ impl Preprocessor {
         // TODO: make functions out of preprocessor
         let raw_files = self.get_raw_files(folder.as_str()).await;
         let min = extract_slot_from_filename(raw_files.first().unwrap()).unwrap();
-        println!("Min: {}", min);
+        info!(min, "Lowest slot");
         let max = extract_slot_from_filename(raw_files.last().unwrap()).unwrap();
-        println!("Max: {}", max);
+        info!(max, "Highest slot");
 
-        let slots = (min..=max).collect::<Vec<u64>>();
-        let max_concurrent_tasks = 30;
-        let semaphore = Arc::new(Semaphore::new(max_concurrent_tasks));
+        let skipped = load_skipped_slots(&self.path)?;
+        let slots = (min..=max)
+            .filter(|slot| !skipped.contains(slot))
+            .collect::<Vec<u64>>();
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
         for slot in slots {
             let permit = semaphore.clone().acquire_owned().await?;
             let self_clone = Arc::clone(&self);
@@ -333,10 +346,11 @@ impl Preprocessor {
         Ok(())
     }
 
+    #[instrument(skip(self))]
     async fn process_slot(&self, slot: u64) -> Result<()>{
         // 1. Check if slot.csv and slot.avro exist
 
-        println!("Processing slot: {}", slot);
+        debug!("Processing slot");
 
         let slot_str = slot.to_string();
         let csv_file = format!("{}{}/{}.csv", self.path.to_str().unwrap(), self.date, slot_str);
@@ -369,31 +383,65 @@ impl Preprocessor {
         } 
         
         if !is_verified {
+            let output = OutputConfig {
+                dir: self.path.to_string_lossy().into_owned(),
+                format: OutputFormat::Avro,
+                filter: common::config::config().filters.clone(),
+            };
             for attempt in 1..=3 {
-                let block = match fetch_block_with_version(slot).await {
-                    Ok(block) => block,
-                    Err(e) => {
-                        println!("Failed to fetch block: {}", e);
+                let block = match self.source.fetch(vec![slot]).next().await {
+                    Some(Ok((_, block))) => {
+                        SLOTS.with_label_values(&["fetched"]).inc();
+                        block
+                    }
+                    Some(Err(e)) => match e.downcast_ref::<FetchError>() {
+                        Some(FetchError::Skipped(_)) => {
+                            SLOTS.with_label_values(&["skipped"]).inc();
+                            info!("Slot was skipped");
+                            record_skipped_slot(slot, &output)?;
+                            return Ok(());
+                        }
+                        Some(FetchError::Pruned(_)) => {
+                            SLOTS.with_label_values(&["failed"]).inc();
+                            return Err(anyhow!("Slot {} is no longer available: {}", slot, e));
+                        }
+                        _ => {
+                            warn!(attempt, "Failed to fetch block: {}", e);
+                            sleep(Duration::from_millis(500)).await;
+                            continue;
+                        }
+                    },
+                    None => {
+                        warn!(attempt, "Block source returned nothing");
                         sleep(Duration::from_millis(500)).await;
                         continue;
                     }
                 };
-                if let Err(e) = process_block(slot, block, None).await {
-                    println!("Failed to process block: {}", e);
-                    sleep(Duration::from_millis(500)).await;
-                    continue;
+                match process_block(slot, block, &output, None).await {
+                    Ok(0) => {
+                        SLOTS.with_label_values(&["empty"]).inc();
+                        debug!("No trades in slot");
+                        return Ok(());
+                    }
+                    Ok(_) => SLOTS.with_label_values(&["done"]).inc(),
+                    Err(e) => {
+                        warn!(attempt, "Failed to process block: {}", e);
+                        sleep(Duration::from_millis(500)).await;
+                        continue;
+                    }
                 }
                 if self.verify_slot(&avro_file) {
                     is_verified = true;
                     file_path = avro_file.clone();
                     break;
                 } else {
-                    println!("Verification failed for slot {} on attempt {}", slot, attempt);
+                    warn!(attempt, "Verification failed");
                 }
                 sleep(Duration::from_secs(2)).await;
             }
         }
         if !is_verified {
+            SLOTS.with_label_values(&["failed"]).inc();
             return Err(anyhow!("Failed to verify slot {} after 3 attempts", slot));
         }
 
@@ -636,7 +684,7 @@ fn list_directories(path: &str) -> Vec<String> {
 async fn load_prices(path: &Path, date: &str) -> Result<Vec<KlineData>> {
     // check if SOL_PRICE file for given date exists in folder
     let file = format!("SOL_{}.bin", date);
-    println!("Date: {}", date);
+    debug!(date, "Loading SOL prices");
     let date_nd = NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap();
     if !Path::new(&file).exists() {
         match fetch_klines_for_date("SOL", date_nd).await {
@@ -645,7 +693,7 @@ async fn load_prices(path: &Path, date: &str) -> Result<Vec<KlineData>> {
                 return Ok(data);
             }
             Err(e) => {
-                println!("Error fetching SOL price: {}", e);
+                error!(date, "Error fetching SOL price: {}", e);
                 return Err(anyhow!("Error fetching SOL price"));
             }
         }